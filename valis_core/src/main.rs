@@ -1,11 +1,70 @@
-use clap::{App, Arg, SubCommand};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use dirs;
 
-use valis_core::modules::admin::authinfo;
+use std::error::Error;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chrono::Local;
+
+use valis_core::modules::admin::credentials;
+use valis_core::modules::admin::secrets::{self, DatabaseKey, Secret};
+use valis_core::modules::config::{self, Config};
+use valis_core::modules::core::{self, Command};
+use valis_core::modules::log::{self, Format};
+use valis_core::modules::notes::graph::{build_graph, GraphFilter, GraphFormat};
+use valis_core::modules::notes::humble::{build_with_config, get_pages, HumbleConfig};
+use valis_core::modules::notes::journal;
+use valis_core::modules::notes::journal::{JournalConfig, Period};
+use valis_core::modules::notes::rename;
+use valis_core::modules::notes::resolver::LinkResolver;
+use valis_core::modules::notes::watch::{watch, WatchConfig};
+use valis_core::modules::plan;
 use valis_core::modules::projects::git::github;
 
 fn main() {
     let matches = App::new("my_app")
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .takes_value(true)
+                .global(true)
+                .help("Use the settings of this profile of the configuration"),
+        )
+        .arg(
+            Arg::with_name("dry-run").long("dry-run").global(true).help(
+                "Print the commands, file writes and database changes instead of running them",
+            ),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short('q')
+                .global(true)
+                .help("Only log warnings and errors"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .long("verbose")
+                .short('v')
+                .multiple_occurrences(true)
+                .global(true)
+                .help("Log debug messages, and trace messages when repeated"),
+        )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .takes_value(true)
+                .possible_values(["text", "json"])
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("log-file")
+                .long("log-file")
+                .takes_value(true)
+                .global(true)
+                .help("Also append the logs to this file"),
+        )
         .subcommand(
             SubCommand::with_name("projects").subcommand(
                 SubCommand::with_name("github")
@@ -22,19 +81,293 @@ fn main() {
                     ),
             ),
         )
+        .subcommand(
+            SubCommand::with_name("notes")
+                .arg(
+                    Arg::with_name("vault")
+                        .long("vault")
+                        .takes_value(true)
+                        .default_value("."),
+                )
+                .arg(Arg::with_name("db").long("db").takes_value(true))
+                .subcommand(
+                    SubCommand::with_name("new")
+                        .arg(Arg::with_name("PATH").required(true))
+                        .arg(
+                            Arg::with_name("template")
+                                .long("template")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("journal").arg(Arg::with_name("weekly").long("weekly")),
+                )
+                .subcommand(
+                    SubCommand::with_name("append")
+                        .arg(Arg::with_name("PATH").required(true))
+                        .arg(Arg::with_name("HEADING").required(true))
+                        .arg(Arg::with_name("TEXT").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("build")
+                        .arg(Arg::with_name("DESTINATION").required(true))
+                        .arg(Arg::with_name("assets").long("assets").takes_value(true))
+                        .arg(
+                            Arg::with_name("base-url")
+                                .long("base-url")
+                                .takes_value(true),
+                        )
+                        .arg(Arg::with_name("watch").long("watch"))
+                        .arg(
+                            Arg::with_name("serve")
+                                .long("serve")
+                                .takes_value(true)
+                                .requires("watch"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("graph")
+                        .arg(
+                            Arg::with_name("format")
+                                .long("format")
+                                .takes_value(true)
+                                .possible_values(["dot", "graphml", "json"])
+                                .default_value("dot"),
+                        )
+                        .arg(Arg::with_name("tag").long("tag").takes_value(true))
+                        .arg(Arg::with_name("folder").long("folder").takes_value(true))
+                        .arg(Arg::with_name("around").long("around").takes_value(true))
+                        .arg(
+                            Arg::with_name("hops")
+                                .long("hops")
+                                .takes_value(true)
+                                .default_value("1"),
+                        )
+                        .arg(Arg::with_name("output").long("output").takes_value(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("mv")
+                        .arg(Arg::with_name("OLD").required(true))
+                        .arg(Arg::with_name("NEW").required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("secrets").subcommand(
+                SubCommand::with_name("exec")
+                    .about("Run a command with secrets of a KeePass database in its environment")
+                    .arg(
+                        Arg::with_name("kdbx")
+                            .long("kdbx")
+                            .takes_value(true)
+                            .help("The database, `credentials.kdbx` by default"),
+                    )
+                    .arg(
+                        Arg::with_name("key-file")
+                            .long("key-file")
+                            .takes_value(true)
+                            .help("The key file of the database, `credentials.kdbx_key_file` by default"),
+                    )
+                    .arg(
+                        Arg::with_name("secret")
+                            .long("secret")
+                            .short('s')
+                            .takes_value(true)
+                            .multiple_occurrences(true)
+                            .required(true)
+                            .help("A variable to set, as VAR=entry[#field], e.g. GITHUB_TOKEN=Work/CI/github"),
+                    )
+                    .arg(
+                        Arg::with_name("COMMAND")
+                            .multiple_values(true)
+                            .required(true)
+                            .last(true),
+                    ),
+            ),
+        )
         .get_matches();
 
+    let config = match config::init(matches.value_of("profile")) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error(&e.to_string());
+            std::process::exit(1);
+        }
+    };
+
+    let mut log_config = config.log.clone();
+    if let Some(format) = matches.value_of("log-format") {
+        log_config.format = Format::from_str(format).unwrap();
+    }
+    if let Some(file) = matches.value_of("log-file") {
+        log_config.file = Some(PathBuf::from(file));
+    }
+    let log_config = log_config.with_verbosity(
+        matches.is_present("quiet"),
+        matches.occurrences_of("verbose"),
+    );
+    log::init(log_config).expect("Could not open the log file");
+
+    let dry_run = matches.is_present("dry-run");
+    plan::set_dry_run(dry_run);
+
+    if let Some(notes) = matches.subcommand_matches("notes") {
+        let config = JournalConfig {
+            vault: PathBuf::from(notes.value_of("vault").unwrap()),
+            sprint_db: Some(
+                notes
+                    .value_of("db")
+                    .map(|db| db.to_owned())
+                    .unwrap_or_else(|| config.db_path()),
+            ),
+            ..Default::default()
+        };
+
+        if let Some(new) = notes.subcommand_matches("new") {
+            match journal::new_note(
+                &config,
+                &PathBuf::from(new.value_of("PATH").unwrap()),
+                new.value_of("template"),
+                Local::now().date_naive(),
+            ) {
+                Ok(path) => println!("{}", path.display()),
+                Err(e) => {
+                    log::error(&format!("Failed to create the note: {}", e));
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        if let Some(journal_matches) = notes.subcommand_matches("journal") {
+            let period = if journal_matches.is_present("weekly") {
+                Period::Weekly
+            } else {
+                Period::Daily
+            };
+            match journal::today(&config, period) {
+                Ok(path) => println!("{}", path.display()),
+                Err(e) => {
+                    log::error(&format!("Failed to create the journal note: {}", e));
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        if let Some(append) = notes.subcommand_matches("append") {
+            if let Err(e) = journal::append_to_note(
+                &config.vault,
+                &PathBuf::from(append.value_of("PATH").unwrap()),
+                append.value_of("HEADING").unwrap(),
+                append.value_of("TEXT").unwrap(),
+            ) {
+                log::error(&format!("Failed to append to the note: {}", e));
+                std::process::exit(1);
+            }
+        }
+
+        if let Some(build) = notes.subcommand_matches("build") {
+            let destination = PathBuf::from(build.value_of("DESTINATION").unwrap());
+            let assets = build
+                .value_of("assets")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| destination.clone());
+            let humble_config = HumbleConfig {
+                base_url: build.value_of("base-url").map(|url| url.to_owned()),
+                ..Default::default()
+            };
+            if build.is_present("watch") {
+                let serve = match build.value_of("serve").map(|port| port.parse()).transpose() {
+                    Ok(serve) => serve,
+                    Err(_) => {
+                        log::error("--serve must be a port number");
+                        std::process::exit(1);
+                    }
+                };
+                let watch_config = WatchConfig {
+                    serve,
+                    ..Default::default()
+                };
+                if let Err(e) = watch(
+                    config.vault.clone(),
+                    destination,
+                    assets,
+                    &humble_config,
+                    &watch_config,
+                ) {
+                    log::error(&format!("Failed to watch the vault: {}", e));
+                    std::process::exit(1);
+                }
+            } else if let Err(e) =
+                build_with_config(config.vault.clone(), destination, assets, &humble_config)
+            {
+                log::error(&format!("Failed to build the site: {}", e));
+                std::process::exit(1);
+            }
+        }
+
+        if let Some(graph) = notes.subcommand_matches("graph") {
+            let format = GraphFormat::from_str(graph.value_of("format").unwrap()).unwrap();
+            let filter = GraphFilter {
+                tag: graph.value_of("tag").map(|tag| tag.to_owned()),
+                folder: graph.value_of("folder").map(|folder| folder.to_owned()),
+                around: graph.value_of("around").map(|note| note.to_owned()),
+                hops: graph
+                    .value_of("hops")
+                    .unwrap()
+                    .parse()
+                    .expect("HOPS must be a number"),
+            };
+            let pages = get_pages(config.vault.clone());
+            let resolver = LinkResolver::new(&config.vault, &pages);
+            let export = build_graph(&resolver, &pages)
+                .filter(&filter, &resolver)
+                .export(format);
+            match graph.value_of("output") {
+                Some(output) => plan::write(output, export).unwrap(),
+                None => print!("{}", export),
+            }
+        }
+
+        if let Some(mv) = notes.subcommand_matches("mv") {
+            let dry_run = mv.is_present("dry-run");
+            let rename = match rename::rename_note(
+                &config.vault,
+                &PathBuf::from(mv.value_of("OLD").unwrap()),
+                &PathBuf::from(mv.value_of("NEW").unwrap()),
+                dry_run,
+            ) {
+                Ok(rename) => rename,
+                Err(e) => {
+                    log::error(&format!("Failed to rename the note: {}", e));
+                    std::process::exit(1);
+                }
+            };
+            if dry_run {
+                // The diff shows the whole rename, which isn't recorded in the plan
+                print!("{}", rename.diff());
+                return;
+            } else {
+                println!(
+                    "Renamed {} to {}, rewrote {} links in {} notes",
+                    rename.from.display(),
+                    rename.to.display(),
+                    rename.links(),
+                    rename.edits.len()
+                );
+            }
+        }
+    }
+
     if let Some(projects) = matches.subcommand_matches("projects") {
         if let Some(github) = projects.subcommand_matches("github") {
-            let mut authinfo_file = dirs::home_dir().unwrap();
-            authinfo_file.push(".authinfo");
-            let authinfos = authinfo::read_auth_file(&authinfo_file).unwrap();
-            let github_auth = authinfo::find_auth_info_for_machine("api.github.com", authinfos)
-                .get(0)
-                .cloned()
-                .unwrap();
-            let user = github_auth.login.name.to_owned();
-            let token = github_auth.password.to_owned();
+            let github_auth = match credentials::get("github") {
+                Ok(credential) => credential,
+                Err(e) => {
+                    log::error(&e.to_string());
+                    std::process::exit(1);
+                }
+            };
+            let user = github_auth.login.unwrap_or_default();
+            let token = github_auth.secret.expose().to_owned();
 
             if let Some(get_milestones) = github.subcommand_matches("get-milestones") {
                 let org = get_milestones.value_of("ORG").unwrap();
@@ -72,4 +405,43 @@ fn main() {
             }
         }
     }
+
+    if let Some(secrets_matches) = matches.subcommand_matches("secrets") {
+        if let Some(exec) = secrets_matches.subcommand_matches("exec") {
+            if let Err(e) = secrets_exec(config, exec) {
+                log::error(&e.to_string());
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if dry_run {
+        plan::print_plan();
+    }
+}
+
+/// Run the command of `valis_cli secrets exec` with its secrets, exiting with its code.
+fn secrets_exec(config: &Config, exec: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let kdbx = exec
+        .value_of("kdbx")
+        .and_then(core::to_path_buf)
+        .or_else(|| config.credentials.kdbx.clone())
+        .ok_or("No database: pass --kdbx or set `credentials.kdbx`")?;
+    let key_file = exec
+        .value_of("key-file")
+        .and_then(core::to_path_buf)
+        .or_else(|| config.credentials.kdbx_key_file.clone());
+    let key = DatabaseKey::resolve(&kdbx, None, key_file, &config.credentials.kdbx_password_env)?;
+    let secrets = exec
+        .values_of("secret")
+        .unwrap()
+        .map(Secret::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    let argv = exec.values_of("COMMAND").unwrap().collect::<Vec<_>>();
+    let command = Command::new(argv[0]).args(&argv[1..]);
+    let output = secrets::with_env(&kdbx, &key, &secrets, command)?.status()?;
+    if !output.success() {
+        std::process::exit(output.code.unwrap_or(1));
+    }
+    Ok(())
 }
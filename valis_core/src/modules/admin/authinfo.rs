@@ -1,82 +1,492 @@
-use std::fs::File;
-use std::io::{self, BufRead};
+//! Reading and writing authinfo and netrc files, e.g. `~/.authinfo` or `~/.authinfo.gpg`.
+//! An entry is a list of `key value` tokens, separated by spaces or newlines, starting with
+//! `machine <name>`, or `default` for the entry used when no machine matches. Values with
+//! spaces are quoted, and lines starting with `#` are comments.
+//!
+//! ```text
+//! # GitHub
+//! machine api.github.com login octocat password "correct horse battery" port 443
+//! default login anonymous password guest
+//! ```
+//!
+//! Files ending in `.gpg` are decrypted and encrypted by `gpg`, the passphrase being asked for
+//! by the gpg agent: the plain text is never written to disk.
+use std::error::Error;
+use std::fs;
 use std::path::Path;
 
-#[derive(Clone, Debug)]
+use regex::Regex;
+
+use crate::modules::core::{Command, SecretString};
+use crate::modules::formats::text;
+
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct AuthInfo {
-    pub machine: String,
+    /// The machine, `None` for the `default` entry.
+    pub machine: Option<String>,
     pub login: Login,
-    pub password: String,
-    pub port: Option<u16>,
+    pub password: SecretString,
+    pub port: Option<String>,
+    /// The other tokens, e.g. `account`, in order. A `macdef` has the name of the macro and
+    /// its lines.
+    pub extra: Vec<(String, String)>,
 }
 
-#[derive(Clone, Debug)]
+/// A login, with the domain after a `^`, e.g. `jdoe^corp`.
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Login {
     pub name: String,
     pub domain: Option<String>,
 }
 
-pub fn parse_auth_info(line: &str) -> Result<AuthInfo, &str> {
-    let mut parts: Vec<&str> = line.split_whitespace().collect();
-
-    if parts.len() < 6 {
-        return Err("Invalid auth info format");
+impl Login {
+    pub fn parse(login: &str) -> Login {
+        match login.split_once('^') {
+            Some((name, domain)) => Login {
+                name: name.to_string(),
+                domain: Some(domain.to_string()),
+            },
+            None => Login {
+                name: login.to_string(),
+                domain: None,
+            },
+        }
     }
+}
 
-    let machine = parts[1].to_string();
-    let password = parts[5].to_string();
-    let mut port = None;
+impl std::fmt::Display for Login {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.domain {
+            Some(domain) => write!(f, "{}^{}", self.name, domain),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
 
-    if parts.len() > 6 {
-        if parts[6] == "port" {
-            port = parts.get(7).and_then(|s| s.parse::<u16>().ok());
-            parts.truncate(6); // to make sure we ignore anything beyond the port info
+impl AuthInfo {
+    fn set(&mut self, key: &str, value: String) {
+        match key {
+            "machine" => self.machine = Some(value),
+            "login" | "user" => self.login = Login::parse(&value),
+            "password" => self.password = SecretString::from(value),
+            "port" | "protocol" => self.port = Some(value),
+            _ => self.extra.push((key.to_string(), value)),
         }
     }
 
-    let login_parts: Vec<&str> = parts[3].split('^').collect();
-    let login = if login_parts.len() == 2 {
-        Login {
-            name: login_parts[0].to_string(),
-            domain: Some(login_parts[1].to_string()),
+    /// Whether the entry is for the same machine, login and port as `other`.
+    fn same_key(&self, other: &AuthInfo) -> bool {
+        self.machine == other.machine && self.login == other.login && self.port == other.port
+    }
+
+    fn to_line(&self) -> String {
+        let mut tokens = match &self.machine {
+            Some(machine) => vec!["machine".to_string(), quote(machine)],
+            None => vec!["default".to_string()],
+        };
+        if !self.login.name.is_empty() {
+            tokens.extend(["login".to_string(), quote(&self.login.to_string())]);
         }
-    } else if login_parts.len() == 1 {
-        Login {
-            name: login_parts[0].to_string(),
-            domain: None,
+        if !self.password.is_empty() {
+            tokens.extend(["password".to_string(), quote(self.password.expose())]);
         }
+        if let Some(port) = &self.port {
+            tokens.extend(["port".to_string(), quote(port)]);
+        }
+        let mut macdef = None;
+        for (key, value) in &self.extra {
+            if key == "macdef" {
+                macdef = Some(value);
+            } else {
+                tokens.extend([key.clone(), quote(value)]);
+            }
+        }
+        let mut line = tokens.join(" ");
+        // The body of the macro follows its name, up to a blank line
+        if let Some(macdef) = macdef {
+            line.push_str(" macdef ");
+            line.push_str(macdef);
+            line.push('\n');
+        }
+        line
+    }
+}
+
+/// Quote `value` if it is empty or has spaces, quotes or backslashes.
+fn quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && !value.starts_with('#')
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '\\');
+    if plain {
+        value.to_string()
     } else {
-        return Err("Invalid login format");
-    };
-
-    Ok(AuthInfo {
-        machine,
-        login,
-        password,
-        port,
-    })
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+/// Split a line into tokens. Quoted tokens can contain spaces, and `\"` or `\\`.
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let quoted = match chars.peek() {
+            Some('"') => chars.next().is_some(),
+            Some(_) => false,
+            None => break,
+        };
+        let mut token = String::new();
+        if quoted {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c) => token.push(c),
+                        None => return Err("Unterminated quoted value".to_string()),
+                    },
+                    Some(c) => token.push(c),
+                    None => return Err("Unterminated quoted value".to_string()),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                token.push(c);
+            }
+        }
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+enum Line {
+    /// A comment or a blank line.
+    Text(String),
+    /// An entry, with its original lines while it's unchanged and alone on them.
+    Entry(AuthInfo, Option<String>),
+}
+
+/// An authinfo file. Comments and the formatting of unchanged entries are kept when it's
+/// written back; changed entries are written on one line.
+#[derive(Clone, Debug, Default)]
+pub struct AuthFile {
+    lines: Vec<Line>,
+    /// The keys the file was encrypted to, for a `.gpg` file.
+    recipients: Vec<String>,
+    /// Whether the file was encrypted with a passphrase rather than keys.
+    symmetric: bool,
 }
 
-pub fn read_auth_file(file_path: &Path) -> Result<Vec<AuthInfo>, Box<dyn std::error::Error>> {
-    let file = File::open(&file_path)?;
-    let reader = io::BufReader::new(file);
-    let mut auth_infos = Vec::new();
+fn is_encrypted(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "gpg")
+}
+
+impl AuthFile {
+    /// Parse the contents of an authinfo file.
+    pub fn parse(contents: &str) -> Result<AuthFile, Box<dyn Error>> {
+        let mut lines = Vec::new();
+        // The index of the entry being parsed in `lines`, and the key waiting for its value
+        let mut current: Option<usize> = None;
+        let mut key: Option<String> = None;
+        let mut in_macdef = false;
+        for (number, line) in contents.lines().enumerate() {
+            if in_macdef {
+                // The body of a macro, up to a blank line, is kept with the name of the macro
+                in_macdef = !line.trim().is_empty();
+                if let Some(Line::Entry(entry, original)) = current.and_then(|i| lines.get_mut(i)) {
+                    if let Some((_, body)) =
+                        entry.extra.iter_mut().rfind(|(key, _)| key == "macdef")
+                    {
+                        if in_macdef {
+                            body.push('\n');
+                            body.push_str(line);
+                        }
+                    }
+                    if let Some(original) = original {
+                        original.push('\n');
+                        original.push_str(line);
+                    }
+                }
+                continue;
+            }
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                lines.push(Line::Text(line.to_string()));
+                continue;
+            }
+            let error = |e: String| format!("Invalid authinfo at line {}: {}", number + 1, e);
+            let mut started = false;
+            for (index, token) in tokenize(line).map_err(error)?.into_iter().enumerate() {
+                if let Some(key) = key.take() {
+                    if let Some(Line::Entry(entry, _)) = current.and_then(|i| lines.get_mut(i)) {
+                        in_macdef = key == "macdef";
+                        entry.set(&key, token);
+                    }
+                    continue;
+                }
+                if token == "machine" || token == "default" {
+                    if let Some(Line::Entry(_, original)) = current.and_then(|i| lines.get_mut(i)) {
+                        if index > 0 {
+                            *original = None;
+                        }
+                    }
+                    let original = if index == 0 {
+                        Some(line.to_string())
+                    } else {
+                        None
+                    };
+                    lines.push(Line::Entry(AuthInfo::default(), original));
+                    current = Some(lines.len() - 1);
+                    started = true;
+                    if token == "machine" {
+                        key = Some(token);
+                    }
+                } else if current.is_none() {
+                    return Err(error(format!("`{}` outside of an entry", token)).into());
+                } else {
+                    key = Some(token);
+                }
+            }
+            if !started {
+                if let Some(Line::Entry(_, Some(original))) = current.and_then(|i| lines.get_mut(i))
+                {
+                    original.push('\n');
+                    original.push_str(line);
+                }
+            }
+        }
+        if let Some(key) = key {
+            return Err(format!("Invalid authinfo: no value for `{}`", key).into());
+        }
+        Ok(AuthFile {
+            lines,
+            ..Default::default()
+        })
+    }
+
+    /// Read the authinfo file at `path`, decrypting it with `gpg` if it ends in `.gpg`.
+    pub fn read(path: &Path) -> Result<AuthFile, Box<dyn Error>> {
+        if !is_encrypted(path) {
+            return AuthFile::parse(&fs::read_to_string(path)?);
+        }
+        let path_str = path.to_string_lossy();
+        let command = Command::new("gpg")
+            .args(&["--quiet", "--batch", "--decrypt"])
+            .arg(&path_str)
+            .read_only();
+        let output = command.output()?.check(&command)?;
+        let contents = SecretString::from(output.stdout);
+        let mut file = AuthFile::parse(contents.expose())?;
+
+        // List the keys the file is encrypted to, without decrypting it again
+        let command = Command::new("gpg")
+            .args(&["--batch", "--list-packets", "--list-only"])
+            .arg(&path_str)
+            .read_only();
+        let packets = command.output()?.stdout;
+        let keyid = Regex::new(r":pubkey enc packet:.*keyid ([0-9A-Fa-f]+)").unwrap();
+        file.recipients = keyid
+            .captures_iter(&packets)
+            .map(|captures| captures[1].to_string())
+            .collect();
+        file.symmetric = file.recipients.is_empty() && packets.contains(":symkey enc packet:");
+        Ok(file)
+    }
+
+    /// Write the file to `path` atomically, keeping the permissions of the existing file, or
+    /// readable only by the user for a new one. A `.gpg` file is encrypted to the keys it was
+    /// read with, or to the default key of the user.
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let contents = self.to_text();
+        if !is_encrypted(path) {
+            text::write_atomically(path, contents.expose())?;
+            return Ok(());
+        }
+        let mut command = Command::new("gpg").args(&["--quiet", "--batch", "--yes", "--armor"]);
+        if self.symmetric {
+            command = command.arg("--symmetric");
+        } else if self.recipients.is_empty() {
+            command = command.args(&["--encrypt", "--default-recipient-self"]);
+        } else {
+            command = command.arg("--encrypt");
+            for recipient in &self.recipients {
+                command = command.arg("--recipient").arg(recipient);
+            }
+        }
+        let command = command.stdin(&contents);
+        let output = command.output()?.check(&command)?;
+        text::write_atomically(path, &output.stdout)?;
+        Ok(())
+    }
 
-    for line in reader.lines() {
-        let line = line?.trim().to_string(); // trim white spaces
-        if line.is_empty() {
-            continue; // Skip empty lines
+    /// The contents of the file.
+    pub fn to_text(&self) -> SecretString {
+        let mut contents = String::new();
+        for line in &self.lines {
+            match line {
+                Line::Text(text) | Line::Entry(_, Some(text)) => contents.push_str(text),
+                Line::Entry(entry, None) => contents.push_str(&entry.to_line()),
+            }
+            contents.push('\n');
         }
+        SecretString::from(contents)
+    }
+
+    /// The entries, in the order of the file.
+    pub fn entries(&self) -> impl Iterator<Item = &AuthInfo> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Entry(entry, _) => Some(entry),
+            Line::Text(_) => None,
+        })
+    }
 
-        match parse_auth_info(&line) {
-            Ok(auth_info) => auth_infos.push(auth_info),
-            Err(e) => eprintln!("Skipping line due to error: {}", e),
+    /// The first entry for `machine`, and `login` if given, or else the `default` entry.
+    pub fn find(&self, machine: &str, login: Option<&str>) -> Option<&AuthInfo> {
+        let matches_login = |entry: &&AuthInfo| login.is_none_or(|login| entry.login.name == login);
+        self.entries()
+            .filter(matches_login)
+            .find(|entry| entry.machine.as_deref() == Some(machine))
+            .or_else(|| {
+                self.entries()
+                    .filter(matches_login)
+                    .find(|entry| entry.machine.is_none())
+            })
+    }
+
+    /// Replace the entry with the machine, login and port of `entry`, or add it before the
+    /// `default` entry. Returns whether an entry was replaced.
+    pub fn upsert(&mut self, entry: AuthInfo) -> bool {
+        for line in self.lines.iter_mut() {
+            if let Line::Entry(existing, original) = line {
+                if existing.same_key(&entry) {
+                    if *existing != entry {
+                        *existing = entry;
+                        *original = None;
+                    }
+                    return true;
+                }
+            }
         }
+        let position = match entry.machine {
+            Some(_) => self
+                .lines
+                .iter()
+                .position(
+                    |line| matches!(line, Line::Entry(existing, _) if existing.machine.is_none()),
+                )
+                .unwrap_or(self.lines.len()),
+            None => self.lines.len(),
+        };
+        self.lines.insert(position, Line::Entry(entry, None));
+        false
+    }
+
+    /// Remove the entries for `machine`, `None` for the `default` entry, and `login` if
+    /// given. Returns the number of entries removed.
+    pub fn remove(&mut self, machine: Option<&str>, login: Option<&str>) -> usize {
+        let count = self.lines.len();
+        self.lines.retain(|line| match line {
+            Line::Entry(entry, _) => {
+                entry.machine.as_deref() != machine
+                    || login.is_some_and(|login| entry.login.name != login)
+            }
+            Line::Text(_) => true,
+        });
+        count - self.lines.len()
     }
-    Ok(auth_infos)
 }
 
+/// Parse an entry on one line, e.g. `machine api.github.com login octocat password token`.
+pub fn parse_auth_info(line: &str) -> Result<AuthInfo, Box<dyn Error>> {
+    AuthFile::parse(line)?
+        .entries()
+        .next()
+        .cloned()
+        .ok_or_else(|| "Invalid auth info format".into())
+}
+
+/// Read the entries of the authinfo file at `file_path`, decrypting it if it ends in `.gpg`.
+pub fn read_auth_file(file_path: &Path) -> Result<Vec<AuthInfo>, Box<dyn Error>> {
+    Ok(AuthFile::read(file_path)?.entries().cloned().collect())
+}
 
+/// The entries for `machine`, or the `default` entries if there are none.
 pub fn find_auth_info_for_machine(machine: &str, auth_infos: Vec<AuthInfo>) -> Vec<AuthInfo> {
-    auth_infos.into_iter().filter(|info| info.machine == machine).collect()
+    let (matching, others): (Vec<_>, Vec<_>) = auth_infos
+        .into_iter()
+        .partition(|info| info.machine.as_deref() == Some(machine));
+    if !matching.is_empty() {
+        return matching;
+    }
+    others
+        .into_iter()
+        .filter(|info| info.machine.is_none())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUTHINFO: &str = r#"# Work
+machine api.github.com password ghp_token login octocat
+machine imap.example.com
+    login "jdoe^corp" password "two words \"quoted\"" port imaps
+machine ftp.example.com login anonymous macdef init
+cd /pub
+
+default login guest password guest
+"#;
+
+    #[test]
+    fn test_parse() {
+        let file = AuthFile::parse(AUTHINFO).unwrap();
+        let ftp = file.find("ftp.example.com", None).unwrap();
+        assert_eq!(
+            ftp.extra,
+            vec![("macdef".to_string(), "init\ncd /pub".to_string())]
+        );
+        let github = file.find("api.github.com", None).unwrap();
+        assert_eq!(github.login.name, "octocat");
+        assert_eq!(github.password.expose(), "ghp_token");
+
+        let imap = file.find("imap.example.com", Some("jdoe")).unwrap();
+        assert_eq!(imap.login.domain.as_deref(), Some("corp"));
+        assert_eq!(imap.password.expose(), "two words \"quoted\"");
+        assert_eq!(imap.port.as_deref(), Some("imaps"));
+
+        assert_eq!(file.find("unknown.org", None).unwrap().login.name, "guest");
+        assert_eq!(file.entries().count(), 4);
+        // Unchanged files are written back as they were
+        assert_eq!(file.to_text().expose(), AUTHINFO);
+
+        assert!(AuthFile::parse("login octocat").is_err());
+        assert!(AuthFile::parse("machine example.com password").is_err());
+        assert!(AuthFile::parse("machine example.com password \"open").is_err());
+    }
+
+    #[test]
+    fn test_update() {
+        let mut file = AuthFile::parse(AUTHINFO).unwrap();
+        let mut github = file.find("api.github.com", None).unwrap().clone();
+        github.password = SecretString::from("new token");
+        assert!(file.upsert(github));
+        assert!(!file.upsert(parse_auth_info("machine gitlab.com login me password pat").unwrap()));
+        assert_eq!(file.remove(Some("ftp.example.com"), None), 1);
+
+        let text = file.to_text();
+        assert!(text
+            .expose()
+            .contains("machine api.github.com login octocat password \"new token\"\n"));
+        assert!(text.expose().ends_with(
+            "machine gitlab.com login me password pat\ndefault login guest password guest\n"
+        ));
+        let reparsed = AuthFile::parse(text.expose()).unwrap();
+        assert_eq!(
+            reparsed.entries().collect::<Vec<_>>(),
+            file.entries().collect::<Vec<_>>()
+        );
+    }
 }
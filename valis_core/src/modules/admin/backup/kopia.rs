@@ -1,37 +1,39 @@
 use std::path::PathBuf;
 
-use crate::modules::core::run;
-use crate::modules::log::ack;
+use crate::modules::core::{Command, SecretString};
+use crate::modules::log::{self, ack};
 
 #[derive(Debug)]
 pub struct S3Endpoint {
     pub bucket: String,
     pub access_key: String,
-    pub secret_key: String,
+    pub secret_key: SecretString,
     pub endpoint: String,
-    pub password: String,
+    pub password: SecretString,
 }
 
 /// Connect to a kopia repository on S3.
+/// The secret key and the repository password are passed to kopia through its environment
+/// (`AWS_SECRET_ACCESS_KEY` and `KOPIA_PASSWORD`), so that they don't show in `ps`.
 ///
 /// # Arguments
 ///
 /// * `s3` - A struct containing the S3 endpoint information.
 pub fn kopia_connect_s3(s3: &S3Endpoint) {
-    let bucket = s3.bucket.to_owned();
-    let access_key = s3.access_key.to_owned();
-    let secret_key = s3.secret_key.to_owned();
-    let endpoint = s3.endpoint.to_owned();
-    let password = s3.password.to_owned();
-
-    run(
-        &format!("kopia repository connect s3 {} {} {} {} {}",
-                 &format!("--bucket={bucket}"),
-                 &format!("--access-key={access_key}"),
-                 &format!("--secret-access-key={secret_key}"),
-                 &format!("--endpoint={endpoint}"),
-                 &format!("--password={password}"))
-    )
+    let command = Command::new("kopia")
+        .args(&[
+            "repository",
+            "connect",
+            "s3",
+            &format!("--bucket={}", s3.bucket),
+            &format!("--access-key={}", s3.access_key),
+            &format!("--endpoint={}", s3.endpoint),
+        ])
+        .secret_env("AWS_SECRET_ACCESS_KEY", &s3.secret_key)
+        .secret_env("KOPIA_PASSWORD", &s3.password);
+    if let Err(e) = command.status().and_then(|output| output.check(&command)) {
+        log::warn(&format!("Could not connect to the kopia repository: {}", e));
+    }
 }
 
 /// Backup a list of locations to a kopia repository on S3.
@@ -41,6 +43,7 @@ pub fn kopia_connect_s3(s3: &S3Endpoint) {
 /// * `s3` - A struct containing the S3 endpoint information.
 /// * `locations` - A vector of locations to backup.
 pub fn backup(s3: &S3Endpoint, locations: &Vec<PathBuf>) {
+    let _span = log::span("backup", &[("bucket", &s3.bucket)]);
     ack(&format!(
         "Backing up to {}@{}",
         s3.bucket, s3.endpoint
@@ -57,5 +60,8 @@ pub fn backup(s3: &S3Endpoint, locations: &Vec<PathBuf>) {
 ///
 /// * `location` - A location to create snapshot.
 pub fn create_snapshot(location: &PathBuf) {
-    run(&format!("kopia snapshot create {} ", location.to_str().unwrap()))
+    let command = Command::new("kopia").args(&["snapshot", "create", &location.to_string_lossy()]);
+    if let Err(e) = command.status().and_then(|output| output.check(&command)) {
+        log::warn(&format!("Could not create a snapshot of {}: {}", location.display(), e));
+    }
 }
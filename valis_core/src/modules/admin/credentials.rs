@@ -0,0 +1,381 @@
+//! Credentials looked up by logical name, e.g. `github`, `todoist` or `kopia-s3`, in a chain
+//! of backends: environment variables, authinfo, the keyring, a pass(1) store and a KeePass
+//! database, tried in the order of the `credentials.order` setting.
+//!
+//! A backend looks a credential up by the name configured for it, e.g. the machine of an
+//! authinfo entry, or by the logical name:
+//!
+//! ```toml
+//! [credentials]
+//! order = ["pass", "authinfo"]
+//!
+//! [credentials.github]
+//! authinfo = "api.github.com"
+//! pass = "dev/github"
+//! ```
+use std::cell::OnceCell;
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::modules::admin::authinfo::AuthFile;
+use crate::modules::admin::secrets::{DatabaseKey, KeePass};
+use crate::modules::config::{self, Config};
+use crate::modules::core::{Command, SecretString};
+use crate::modules::log;
+
+/// A secret, with the login it goes with if the backend has one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Credential {
+    pub login: Option<String>,
+    pub secret: SecretString,
+}
+
+/// A backend holding credentials.
+pub trait CredentialProvider {
+    /// The name of the backend, e.g. `authinfo`, used in the settings.
+    fn name(&self) -> &str;
+
+    /// Look up the credential named `key` in this backend, e.g. the machine of an authinfo
+    /// entry. Returns `None` when the backend doesn't have it.
+    fn lookup(&self, key: &str) -> Result<Option<Credential>, Box<dyn Error>>;
+}
+
+/// Environment variables, named by the key.
+pub struct EnvProvider;
+
+impl CredentialProvider for EnvProvider {
+    fn name(&self) -> &str {
+        "env"
+    }
+
+    fn lookup(&self, key: &str) -> Result<Option<Credential>, Box<dyn Error>> {
+        Ok(env::var(key).ok().map(|secret| Credential {
+            login: None,
+            secret: SecretString::from(secret),
+        }))
+    }
+}
+
+/// The entries of an authinfo file, by machine. The file is read on the first lookup.
+pub struct AuthinfoProvider {
+    path: PathBuf,
+    file: OnceCell<AuthFile>,
+}
+
+impl AuthinfoProvider {
+    pub fn new(path: &Path) -> AuthinfoProvider {
+        AuthinfoProvider {
+            path: path.to_path_buf(),
+            file: OnceCell::new(),
+        }
+    }
+}
+
+impl CredentialProvider for AuthinfoProvider {
+    fn name(&self) -> &str {
+        "authinfo"
+    }
+
+    fn lookup(&self, key: &str) -> Result<Option<Credential>, Box<dyn Error>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let file = match self.file.get() {
+            Some(file) => file,
+            None => {
+                let file = AuthFile::read(&self.path)?;
+                self.file.get_or_init(|| file)
+            }
+        };
+        // The `default` entry would hide the next backends
+        Ok(file
+            .entries()
+            .find(|entry| entry.machine.as_deref() == Some(key))
+            .map(|entry| Credential {
+                login: Some(entry.login.to_string()).filter(|login| !login.is_empty()),
+                secret: entry.password.clone(),
+            }))
+    }
+}
+
+/// The Secret Service keyring, e.g. GNOME Keyring or KWallet, through `secret-tool`, or the
+/// macOS keychain through `security`. Credentials are stored with the `valis` service and
+/// the key as account.
+pub struct KeyringProvider;
+
+impl CredentialProvider for KeyringProvider {
+    fn name(&self) -> &str {
+        "keyring"
+    }
+
+    fn lookup(&self, key: &str) -> Result<Option<Credential>, Box<dyn Error>> {
+        #[cfg(target_os = "macos")]
+        let command = Command::new("security")
+            .args(&["find-generic-password", "-s", "valis", "-a", key, "-w"])
+            .read_only();
+        #[cfg(not(target_os = "macos"))]
+        let command = Command::new("secret-tool")
+            .args(&["lookup", "service", "valis", "account", key])
+            .read_only();
+        let output = command.output()?;
+        if !output.success() {
+            return Ok(None);
+        }
+        let secret = SecretString::from(output.stdout);
+        Ok(Some(Credential {
+            login: None,
+            secret: SecretString::from(secret.expose().trim_end_matches('\n')),
+        }))
+    }
+}
+
+/// A pass(1) store. The secret is the first line of an entry, and the login a `login:`,
+/// `user:` or `username:` line.
+pub struct PassProvider {
+    dir: Option<PathBuf>,
+}
+
+impl PassProvider {
+    /// The store in `dir`, or in `~/.password-store`.
+    pub fn new(dir: Option<&Path>) -> PassProvider {
+        PassProvider {
+            dir: dir.map(|dir| dir.to_path_buf()),
+        }
+    }
+}
+
+fn parse_pass_entry(entry: &str) -> Credential {
+    let mut lines = entry.lines();
+    let secret = SecretString::from(lines.next().unwrap_or(""));
+    let login = lines.find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        match key.trim().to_lowercase().as_str() {
+            "login" | "user" | "username" => Some(value.trim().to_string()),
+            _ => None,
+        }
+    });
+    Credential { login, secret }
+}
+
+impl CredentialProvider for PassProvider {
+    fn name(&self) -> &str {
+        "pass"
+    }
+
+    fn lookup(&self, key: &str) -> Result<Option<Credential>, Box<dyn Error>> {
+        let mut command = Command::new("pass").arg("show").arg(key).read_only();
+        if let Some(dir) = &self.dir {
+            command = command.env("PASSWORD_STORE_DIR", &dir.to_string_lossy());
+        }
+        let output = command.output()?;
+        if !output.success() {
+            return Ok(None);
+        }
+        let entry = SecretString::from(output.stdout);
+        Ok(Some(parse_pass_entry(entry.expose())))
+    }
+}
+
+/// The entries of a KeePass database, by group path, title or UUID. The password of the
+/// database is read from an environment variable, or asked for on the terminal, and the
+/// database unlocked on the first lookup.
+pub struct KdbxProvider {
+    path: PathBuf,
+    password_env: String,
+    key_file: Option<PathBuf>,
+    db: OnceCell<KeePass>,
+}
+
+impl KdbxProvider {
+    pub fn new(path: &Path, password_env: &str) -> KdbxProvider {
+        KdbxProvider {
+            path: path.to_path_buf(),
+            password_env: password_env.to_string(),
+            key_file: None,
+            db: OnceCell::new(),
+        }
+    }
+
+    /// Also unlock the database with the key file `key_file`.
+    pub fn with_key_file(mut self, key_file: Option<&Path>) -> KdbxProvider {
+        self.key_file = key_file.map(|key_file| key_file.to_path_buf());
+        self
+    }
+
+    fn db(&self) -> Result<&KeePass, Box<dyn Error>> {
+        if let Some(db) = self.db.get() {
+            return Ok(db);
+        }
+        let key =
+            DatabaseKey::resolve(&self.path, None, self.key_file.clone(), &self.password_env)?;
+        let db = KeePass::open(&self.path, &key)?;
+        Ok(self.db.get_or_init(|| db))
+    }
+}
+
+impl CredentialProvider for KdbxProvider {
+    fn name(&self) -> &str {
+        "kdbx"
+    }
+
+    fn lookup(&self, key: &str) -> Result<Option<Credential>, Box<dyn Error>> {
+        let db = self.db()?;
+        Ok(db.field(key, "Password").map(|secret| Credential {
+            login: db
+                .field(key, "UserName")
+                .map(|login| login.expose().to_string())
+                .filter(|login| !login.is_empty()),
+            secret,
+        }))
+    }
+}
+
+/// Backends tried in turn for a credential.
+pub struct CredentialChain {
+    providers: Vec<Box<dyn CredentialProvider>>,
+    names: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl CredentialChain {
+    /// A chain without backends, with the names of the credentials in the backends, by
+    /// logical name and backend.
+    pub fn new(names: BTreeMap<String, BTreeMap<String, String>>) -> CredentialChain {
+        CredentialChain {
+            providers: Vec::new(),
+            names,
+        }
+    }
+
+    /// Add a backend, tried after the others.
+    pub fn with(mut self, provider: Box<dyn CredentialProvider>) -> CredentialChain {
+        self.providers.push(provider);
+        self
+    }
+
+    /// The chain of the `credentials` settings of `config`. Unknown backends are skipped,
+    /// as is `kdbx` without a database.
+    pub fn from_config(config: &Config) -> CredentialChain {
+        let settings = &config.credentials;
+        let mut chain = CredentialChain::new(settings.names.clone());
+        for backend in &settings.order {
+            let provider: Box<dyn CredentialProvider> = match backend.as_str() {
+                "env" => Box::new(EnvProvider),
+                "authinfo" => Box::new(AuthinfoProvider::new(&config.authinfo)),
+                "keyring" => Box::new(KeyringProvider),
+                "pass" => Box::new(PassProvider::new(settings.pass_dir.as_deref())),
+                "kdbx" => match &settings.kdbx {
+                    Some(path) => Box::new(
+                        KdbxProvider::new(path, &settings.kdbx_password_env)
+                            .with_key_file(settings.kdbx_key_file.as_deref()),
+                    ),
+                    None => continue,
+                },
+                _ => {
+                    log::warn(&format!("Unknown credential backend: {}", backend));
+                    continue;
+                }
+            };
+            chain = chain.with(provider);
+        }
+        chain
+    }
+
+    /// The name of the credential `name` in `backend`.
+    pub fn key<'a>(&'a self, backend: &str, name: &'a str) -> &'a str {
+        self.names
+            .get(name)
+            .and_then(|keys| keys.get(backend))
+            .map(|key| key.as_str())
+            .unwrap_or(name)
+    }
+
+    /// The credential `name`, from the first backend which has it. The backends which fail,
+    /// e.g. because `pass` isn't installed, are skipped.
+    pub fn get(&self, name: &str) -> Result<Credential, Box<dyn Error>> {
+        for provider in &self.providers {
+            let key = self.key(provider.name(), name);
+            match provider.lookup(key) {
+                Ok(Some(credential)) => {
+                    log::debug(&format!("Found {} in {}", name, provider.name()));
+                    return Ok(credential);
+                }
+                Ok(None) => {}
+                Err(e) => log::debug(&format!(
+                    "Could not look up {} in {}: {}",
+                    name,
+                    provider.name(),
+                    e
+                )),
+            }
+        }
+        let backends = self
+            .providers
+            .iter()
+            .map(|provider| provider.name())
+            .collect::<Vec<_>>();
+        Err(format!("No credential named {} in {}", name, backends.join(", ")).into())
+    }
+}
+
+/// The credential `name`, from the backends of the configuration.
+pub fn get(name: &str) -> Result<Credential, Box<dyn Error>> {
+    CredentialChain::from_config(config::get()).get(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(&'static str, Vec<(&'static str, &'static str)>);
+
+    impl CredentialProvider for Fixed {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn lookup(&self, key: &str) -> Result<Option<Credential>, Box<dyn Error>> {
+            if key == "broken" {
+                return Err("backend unavailable".into());
+            }
+            Ok(self
+                .1
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, secret)| Credential {
+                    login: None,
+                    secret: SecretString::from(*secret),
+                }))
+        }
+    }
+
+    #[test]
+    fn test_chain() {
+        let mut names = BTreeMap::new();
+        names.insert(
+            "github".to_string(),
+            BTreeMap::from([("second".to_string(), "api.github.com".to_string())]),
+        );
+        let chain = CredentialChain::new(names)
+            .with(Box::new(Fixed("first", vec![("todoist", "t1")])))
+            .with(Box::new(Fixed(
+                "second",
+                vec![("api.github.com", "g2"), ("todoist", "t2")],
+            )));
+        assert_eq!(chain.get("todoist").unwrap().secret.expose(), "t1");
+        assert_eq!(chain.get("github").unwrap().secret.expose(), "g2");
+        assert_eq!(
+            chain.get("kopia").unwrap_err().to_string(),
+            "No credential named kopia in first, second"
+        );
+        assert!(chain.get("broken").is_err());
+    }
+
+    #[test]
+    fn test_parse_pass_entry() {
+        let credential = parse_pass_entry("s3cret\nurl: https://github.com\nLogin: octocat\n");
+        assert_eq!(credential.secret.expose(), "s3cret");
+        assert_eq!(credential.login.as_deref(), Some("octocat"));
+    }
+}
@@ -0,0 +1,113 @@
+use std::path::Path;
+
+use rlua::{Context, Error, Table, Value};
+
+use crate::modules::admin::secrets::{DatabaseKey, KeePass, Secret};
+use crate::modules::config;
+use crate::modules::core::{self, SecretString};
+use crate::modules::script::engine::{command_from_lua, output_to_lua};
+
+/// The key of the database `db`, from the `password` and `key_file` options, or else the
+/// password in the variable of the `credentials.kdbx_password_env` setting, or asked for.
+pub(crate) fn database_key(db: &str, options: Option<Table>) -> rlua::Result<DatabaseKey> {
+    let (password, key_file) = match options {
+        Some(options) => (
+            options.get::<_, Option<String>>("password")?,
+            options.get::<_, Option<String>>("key_file")?,
+        ),
+        None => (None, None),
+    };
+    DatabaseKey::resolve(
+        Path::new(db),
+        password.map(SecretString::from),
+        key_file.and_then(|key_file| core::to_path_buf(&key_file)),
+        &config::get().credentials.kdbx_password_env,
+    )
+    .map_err(|e| Error::external(e.to_string()))
+}
+
+pub(crate) fn open(db: &str, options: Option<Table>) -> rlua::Result<KeePass> {
+    let key = database_key(db, options)?;
+    KeePass::open(Path::new(db), &key).map_err(|e| Error::external(e.to_string()))
+}
+
+/// KeePass functions. Entries are referred to by UUID, group path and title, or title, and
+/// `options` may have the `password` and `key_file` of the database.
+/// - `kdbx_get(db, entry, [field], [options])` returns a field of an entry, `Password` by
+///   default, or nil.
+/// - `kdbx_totp(db, entry, [options])` returns the current TOTP code of an entry, or nil.
+/// - `kdbx_set(db, entry, fields, [options])` sets the fields of an entry, creating it, when
+///   `entry` is a group path such as `Work/CI/registry` or `/registry`, and the database if
+///   needed, and saves the database. Returns whether the entry was created.
+/// - `kdbx_exec(db, secrets, command, [options])` runs a command like `run`, with the
+///   environment variables of `secrets`, e.g. `{GITHUB_TOKEN = "Work/CI/github"}`, set from
+///   the entries of the database. `options` may also have the options of `run`.
+pub fn kdbx_functions(ctx: &Context) {
+    let get = ctx
+        .create_function(
+            |_, (db, entry, field, options): (String, String, Option<String>, Option<Table>)| {
+                let field = field.unwrap_or_else(|| "Password".to_string());
+                Ok(open(&db, options)?
+                    .field(&entry, &field)
+                    .map(|value| value.expose().to_string()))
+            },
+        )
+        .unwrap();
+    ctx.globals().set("kdbx_get", get).unwrap();
+
+    let totp = ctx
+        .create_function(|_, (db, entry, options): (String, String, Option<Table>)| {
+            open(&db, options)?
+                .totp(&entry)
+                .map_err(|e| Error::external(e.to_string()))
+        })
+        .unwrap();
+    ctx.globals().set("kdbx_totp", totp).unwrap();
+
+    let set = ctx
+        .create_function(
+            |_, (db, entry, fields, options): (String, String, Table, Option<Table>)| {
+                let key = database_key(&db, options)?;
+                let path = Path::new(&db);
+                let mut keepass = if path.exists() {
+                    KeePass::open(path, &key)
+                } else {
+                    KeePass::create(path, &key)
+                }
+                .map_err(|e| Error::external(e.to_string()))?;
+                let fields = fields
+                    .pairs::<String, String>()
+                    .map(|pair| pair.map(|(key, value)| (key, SecretString::from(value))))
+                    .collect::<rlua::Result<Vec<_>>>()?;
+                let fields = fields
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value))
+                    .collect::<Vec<_>>();
+                let created = keepass
+                    .set_entry(&entry, &fields)
+                    .map_err(|e| Error::external(e.to_string()))?;
+                keepass.save().map_err(|e| Error::external(e.to_string()))?;
+                Ok(created)
+            },
+        )
+        .unwrap();
+    ctx.globals().set("kdbx_set", set).unwrap();
+
+    let exec = ctx
+        .create_function(
+            |ctx, (db, secrets, command, options): (String, Table, Value, Option<Table>)| {
+                let secrets = secrets
+                    .pairs::<String, String>()
+                    .map(|pair| pair.map(|(env_var, entry)| Secret::new(&env_var, &entry)))
+                    .collect::<rlua::Result<Vec<_>>>()?;
+                let command = command_from_lua(command, options.clone())?;
+                let output = open(&db, options)?
+                    .env(&secrets, command)
+                    .and_then(|command| command.output())
+                    .map_err(|e| Error::external(e.to_string()))?;
+                output_to_lua(ctx, &output)
+            },
+        )
+        .unwrap();
+    ctx.globals().set("kdbx_exec", exec).unwrap();
+}
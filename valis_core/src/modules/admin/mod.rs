@@ -1,3 +1,5 @@
 pub mod secrets;
 pub mod authinfo;
+pub mod credentials;
 pub mod backup;
+pub mod lua;
@@ -1,52 +1,555 @@
-use std::path::PathBuf;
+//! KeePass databases (KDBX 4). Entries are referred to by UUID, by group path and title, e.g.
+//! `Work/CI/github`, or by title alone anywhere in the database.
+//! Attachments aren't supported: kdbx-rs doesn't expose the binaries of the inner header.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use valis_core::modules::admin::secrets::{DatabaseKey, KeePass};
+//! use valis_core::modules::core::SecretString;
+//!
+//! let key = DatabaseKey::password(&SecretString::from("hunter2")).with_key_file("~/db.keyx");
+//! let mut db = KeePass::open(Path::new("secrets.kdbx"), &key).unwrap();
+//! let token = db.field("Work/CI/github", "Password");
+//! db.set_entry("Work/CI/registry", &[("Password", &SecretString::from("generated"))])
+//!     .unwrap();
+//! db.save().unwrap();
+//! ```
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use kdbx_rs;
-use kdbx_rs::CompositeKey;
+use kdbx_rs::binary::Unlocked;
+use kdbx_rs::database::{Entry, Field, Group};
+use kdbx_rs::{CompositeKey, Database, Kdbx};
+use termion::input::TermRead;
+use totp_rs::{Algorithm, Secret as TotpSecret, TOTP};
 
-#[derive(Clone)]
+use crate::modules::core::{self, Command, SecretString};
+use crate::modules::formats::text;
+
+/// A field of an entry, exported as the environment variable `env_var` by [`with_env`].
+#[derive(Clone, Debug, PartialEq)]
 pub struct Secret {
     pub entry: String,
     pub field: String,
     pub env_var: String,
 }
 
-pub fn read_fields_from_entry(
-    kdbx_file_path: &PathBuf,
-    password: String,
-    secrets: Vec<Secret>,
-) -> Result<Vec<Option<String>>, Box<dyn std::error::Error>> {
-    // Open the KDBX file
-    // let file = File::open(kdbx_file_path).unwrap();
-    // let reader = BufReader::new(file);
-
-    // Create a composite key using the provided password
-    let composite_key = CompositeKey::from_password(&password);
-
-    // Read the KDBX file and create a database
-    // let db = kdbx_rs::(reader, &composite_key)?;
-    let locked_db = kdbx_rs::open(kdbx_file_path).unwrap();
-    let db = locked_db.unlock(&composite_key).ok().unwrap();
-
-    let values = secrets
-        .into_iter()
-        .map(|secret| {
-            let entry = db
-                .root()
-                .entries()
-                .find(|e| e.title().unwrap() == secret.entry);
-
-            match entry {
-                None => return None,
-                Some(e) => {
-                    let field_value = e.fields().find(|f| f.key() == secret.field);
-                    match field_value {
-                        None => return None,
-                        Some(f) => return Some(f.value().unwrap().to_string()),
+impl Secret {
+    /// The field of `reference`, an entry optionally followed by `#` and a field, e.g.
+    /// `Work/CI/github#UserName`. The field is `Password` by default.
+    pub fn new(env_var: &str, reference: &str) -> Secret {
+        let (entry, field) = reference
+            .rsplit_once('#')
+            .unwrap_or((reference, "Password"));
+        Secret {
+            entry: entry.to_string(),
+            field: field.to_string(),
+            env_var: env_var.to_string(),
+        }
+    }
+
+    /// A secret from `VAR=entry[#field]`, e.g. `GITHUB_TOKEN=Work/CI/github`.
+    pub fn parse(spec: &str) -> Result<Secret, Box<dyn Error>> {
+        match spec.split_once('=') {
+            Some((env_var, reference)) if !env_var.is_empty() && !reference.is_empty() => {
+                Ok(Secret::new(env_var, reference))
+            }
+            _ => Err(format!("Expected VAR=entry[#field]: {}", spec).into()),
+        }
+    }
+}
+
+/// Ask for a password on the terminal, without echoing it.
+/// # Returns
+/// `None` when stdin isn't a terminal, or at the end of the input.
+pub fn prompt_password(prompt: &str) -> io::Result<Option<SecretString>> {
+    if !termion::is_tty(&io::stdin()) {
+        return Ok(None);
+    }
+    let mut stderr = io::stderr();
+    write!(stderr, "{}", prompt)?;
+    stderr.flush()?;
+    let password = io::stdin().read_passwd(&mut stderr)?;
+    writeln!(stderr)?;
+    Ok(password.map(SecretString::from))
+}
+
+/// The key of a database: a password, a key file, or both.
+#[derive(Clone, Debug, Default)]
+pub struct DatabaseKey {
+    pub password: Option<SecretString>,
+    pub key_file: Option<PathBuf>,
+}
+
+impl DatabaseKey {
+    pub fn password(password: &SecretString) -> DatabaseKey {
+        DatabaseKey {
+            password: Some(password.clone()),
+            key_file: None,
+        }
+    }
+
+    /// The key of the database `db`: `password`, or else the password in the variable
+    /// `password_env`, or else one asked for on the terminal. Without a password, a key file
+    /// alone is used.
+    pub fn resolve(
+        db: &Path,
+        password: Option<SecretString>,
+        key_file: Option<PathBuf>,
+        password_env: &str,
+    ) -> Result<DatabaseKey, Box<dyn Error>> {
+        let password =
+            match password.or_else(|| env::var(password_env).ok().map(SecretString::from)) {
+                Some(password) => Some(password),
+                None if key_file.is_some() => None,
+                None => Some(
+                    prompt_password(&format!("Password of {}: ", db.display()))?
+                        .ok_or_else(|| format!("No password for {}", db.display()))?,
+                ),
+            };
+        Ok(DatabaseKey { password, key_file })
+    }
+
+    /// Add a key file, `~` being the home directory.
+    pub fn with_key_file(mut self, key_file: &str) -> DatabaseKey {
+        self.key_file = core::to_path_buf(key_file);
+        self
+    }
+
+    fn composite(&self) -> Result<CompositeKey, Box<dyn Error>> {
+        let key_file =
+            match &self.key_file {
+                Some(path) => Some(fs::read(path).map_err(|e| {
+                    format!("Could not read the key file {}: {}", path.display(), e)
+                })?),
+                None => None,
+            };
+        let password = self
+            .password
+            .as_ref()
+            .map(|password| password.expose().to_string());
+        Ok(CompositeKey::new(password, key_file))
+    }
+}
+
+/// A UUID as KeePass shows it: 32 hexadecimal digits, or with hyphens.
+fn normalize_uuid(reference: &str) -> Option<String> {
+    let hex = reference.replace('-', "").to_lowercase();
+    if hex.len() == 32 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(hex)
+    } else {
+        None
+    }
+}
+
+fn field_value<'a>(entry: &'a Entry, key: &str) -> Option<&'a str> {
+    entry
+        .fields()
+        .find(|field| field.key() == key)
+        .and_then(|field| field.value())
+}
+
+fn set_field(entry: &mut Entry, key: &str, value: &str) {
+    if key == "Password" {
+        // Keeps the password protected in memory by KeePass
+        entry.set_password(value);
+        return;
+    }
+    if let Some(field) = entry.fields_mut().find(|field| field.key() == key) {
+        field.set_value(value);
+        return;
+    }
+    entry.add_field(Field::new(key, value));
+}
+
+/// Find the entry `title` in `group` or its subgroups, with the path of its group.
+fn find_by_title<'a>(group: &'a Group, title: &str, path: &str) -> Option<(String, &'a Entry)> {
+    if let Some(entry) = group.entries().find(|entry| entry.title() == Some(title)) {
+        return Some((path.to_string(), entry));
+    }
+    group
+        .groups()
+        .find_map(|child| find_by_title(child, title, &format!("{}{}/", path, child.name())))
+}
+
+fn find_by_uuid<'a>(group: &'a Group, uuid: &str, path: &str) -> Option<(String, &'a Entry)> {
+    if let Some(entry) = group
+        .entries()
+        .find(|entry| normalize_uuid(&entry.uuid().to_string()).as_deref() == Some(uuid))
+    {
+        return Some((path.to_string(), entry));
+    }
+    group
+        .groups()
+        .find_map(|child| find_by_uuid(child, uuid, &format!("{}{}/", path, child.name())))
+}
+
+fn find_by_uuid_mut<'a>(group: &'a mut Group, uuid: &str) -> Option<&'a mut Entry> {
+    if group
+        .entries()
+        .any(|entry| entry.uuid().to_string() == uuid)
+    {
+        return group
+            .entries_mut()
+            .find(|entry| entry.uuid().to_string() == uuid);
+    }
+    group
+        .groups_mut()
+        .find_map(|child| find_by_uuid_mut(child, uuid))
+}
+
+/// The TOTP code at `time` of an entry with the fields `fields`: KeePassXC's `otp` URI, or
+/// the `TimeOtp-*` fields of KeePass 2.
+fn totp_at(
+    fields: impl Fn(&str) -> Option<String>,
+    time: u64,
+) -> Result<Option<String>, Box<dyn Error>> {
+    if let Some(uri) = fields("otp") {
+        let totp = TOTP::from_url_unchecked(&uri).map_err(|e| format!("Invalid otp URI: {}", e))?;
+        return Ok(Some(totp.generate(time)));
+    }
+    let secret = match fields("TimeOtp-Secret-Base32") {
+        Some(secret) => TotpSecret::Encoded(secret.replace(' ', "").to_uppercase())
+            .to_bytes()
+            .map_err(|e| format!("Invalid TOTP secret: {}", e))?,
+        None => return Ok(None),
+    };
+    let algorithm = match fields("TimeOtp-Algorithm").as_deref() {
+        None | Some("HMAC-SHA-1") => Algorithm::SHA1,
+        Some("HMAC-SHA-256") => Algorithm::SHA256,
+        Some("HMAC-SHA-512") => Algorithm::SHA512,
+        Some(other) => return Err(format!("Unknown TOTP algorithm: {}", other).into()),
+    };
+    let number = |key: &str, default| -> Result<u64, Box<dyn Error>> {
+        match fields(key) {
+            Some(value) => Ok(value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid {}: {}", key, value))?),
+            None => Ok(default),
+        }
+    };
+    let digits = number("TimeOtp-Length", 6)? as usize;
+    let period = number("TimeOtp-Period", 30)?;
+    let totp = TOTP::new_unchecked(algorithm, digits, 1, period, secret, None, String::new());
+    Ok(Some(totp.generate(time)))
+}
+
+/// An unlocked KeePass database.
+pub struct KeePass {
+    path: PathBuf,
+    key: DatabaseKey,
+    kdbx: Kdbx<Unlocked>,
+}
+
+impl KeePass {
+    /// Open and unlock the database at `path`.
+    pub fn open(path: &Path, key: &DatabaseKey) -> Result<KeePass, Box<dyn Error>> {
+        let locked =
+            kdbx_rs::open(path).map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+        let kdbx = locked.unlock(&key.composite()?).map_err(|_| {
+            format!(
+                "Could not unlock {}: wrong password or key file",
+                path.display()
+            )
+        })?;
+        Ok(KeePass {
+            path: path.to_path_buf(),
+            key: key.clone(),
+            kdbx,
+        })
+    }
+
+    /// A new, empty database, written to `path` when saved.
+    pub fn create(path: &Path, key: &DatabaseKey) -> Result<KeePass, Box<dyn Error>> {
+        Ok(KeePass {
+            path: path.to_path_buf(),
+            key: key.clone(),
+            kdbx: Kdbx::from_database(Database::default()),
+        })
+    }
+
+    /// Find the entry referred to by `reference`: a UUID, a group path and title, or a title.
+    /// # Returns
+    /// The path of the group of the entry, e.g. `Work/CI/`, and the entry.
+    fn locate(&self, reference: &str) -> Option<(String, &Entry)> {
+        let root = self.kdbx.root();
+        if let Some(uuid) = normalize_uuid(reference) {
+            if let Some(found) = find_by_uuid(root, &uuid, "") {
+                return Some(found);
+            }
+        }
+        match reference.rsplit_once('/') {
+            Some((groups, title)) => {
+                let mut group = root;
+                for name in groups.split('/').filter(|name| !name.is_empty()) {
+                    group = group.groups().find(|group| group.name() == name)?;
+                }
+                group
+                    .entries()
+                    .find(|entry| entry.title() == Some(title))
+                    .map(|entry| match groups.trim_matches('/') {
+                        "" => (String::new(), entry),
+                        groups => (format!("{}/", groups), entry),
+                    })
+            }
+            None => find_by_title(root, reference, ""),
+        }
+    }
+
+    /// Whether the database has the entry `reference`.
+    pub fn contains(&self, reference: &str) -> bool {
+        self.locate(reference).is_some()
+    }
+
+    /// The field `field` of the entry `reference`, e.g. `Password` or `UserName`.
+    pub fn field(&self, reference: &str, field: &str) -> Option<SecretString> {
+        let (_, entry) = self.locate(reference)?;
+        field_value(entry, field).map(SecretString::from)
+    }
+
+    /// The current TOTP code of the entry `reference`, if it has a TOTP secret.
+    pub fn totp(&self, reference: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let (_, entry) = self
+            .locate(reference)
+            .ok_or_else(|| format!("No entry {} in {}", reference, self.path.display()))?;
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        totp_at(
+            |key| field_value(entry, key).map(|value| value.to_string()),
+            time,
+        )
+    }
+
+    /// Set the fields of the entry `reference`, found as by [`KeePass::field`]. An entry
+    /// is only created for a group path and title with no match, e.g. `Work/CI/registry`
+    /// or `/registry` at the root, along with its missing groups.
+    /// # Returns
+    /// Whether the entry was created.
+    pub fn set_entry(
+        &mut self,
+        reference: &str,
+        fields: &[(&str, &SecretString)],
+    ) -> Result<bool, Box<dyn Error>> {
+        let existing = self
+            .locate(reference)
+            .map(|(_, entry)| entry.uuid().to_string());
+        let (entry, created) = match existing {
+            Some(uuid) => (
+                find_by_uuid_mut(self.kdbx.root_mut(), &uuid).unwrap(),
+                false,
+            ),
+            None => {
+                let (groups, title) = reference.rsplit_once('/').ok_or_else(|| {
+                    format!(
+                        "No entry {} in {}: use a group path to create it, e.g. /{}",
+                        reference,
+                        self.path.display(),
+                        reference
+                    )
+                })?;
+                let mut group = self.kdbx.root_mut();
+                for name in groups.split('/').filter(|name| !name.is_empty()) {
+                    if !group.groups().any(|group| group.name() == name) {
+                        group.add_group(Group::new(name));
                     }
+                    group = group
+                        .groups_mut()
+                        .find(|group| group.name() == name)
+                        .unwrap();
                 }
-            };
+                let mut entry = Entry::default();
+                entry.set_title(title);
+                group.add_entry(entry);
+                (group.entries_mut().last().unwrap(), true)
+            }
+        };
+        for (key, value) in fields {
+            set_field(entry, key, value.expose());
+        }
+        Ok(created)
+    }
+
+    /// `command` with the fields of `secrets` set as its environment variables.
+    pub fn env(&self, secrets: &[Secret], command: Command) -> Result<Command, Box<dyn Error>> {
+        secrets.iter().try_fold(command, |command, secret| {
+            if !self.contains(&secret.entry) {
+                return Err(format!("No entry {} in {}", secret.entry, self.path.display()).into());
+            }
+            let value = self.field(&secret.entry, &secret.field).ok_or_else(|| {
+                format!("No field {} in the entry {}", secret.field, secret.entry)
+            })?;
+            Ok(command.secret_env(&secret.env_var, &value))
         })
-        .collect::<Vec<Option<String>>>();
+    }
+
+    /// Write the database back to its file, atomically. In dry-run mode, the write is only
+    /// recorded.
+    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
+        self.kdbx
+            .set_key(self.key.composite()?)
+            .map_err(|e| format!("Could not derive the key of {}: {}", self.path.display(), e))?;
+        let mut contents = Vec::new();
+        self.kdbx
+            .write(&mut contents)
+            .map_err(|e| format!("Could not write {}: {}", self.path.display(), e))?;
+        text::write_atomically(&self.path, &contents)?;
+        Ok(())
+    }
+}
+
+/// Read the fields of `secrets` from the database at `kdbx_file_path`.
+/// # Returns
+/// The values, in the order of `secrets`, `None` for the missing entries or fields.
+pub fn read_fields_from_entry(
+    kdbx_file_path: &Path,
+    key: &DatabaseKey,
+    secrets: Vec<Secret>,
+) -> Result<Vec<Option<SecretString>>, Box<dyn std::error::Error>> {
+    let db = KeePass::open(kdbx_file_path, key)?;
+    Ok(secrets
+        .iter()
+        .map(|secret| db.field(&secret.entry, &secret.field))
+        .collect())
+}
+
+/// `command` with the fields of `secrets` from the database `kdbx` in its environment,
+/// unlocking the database once. The secrets are only passed to the child process, never
+/// written to disk, and the database is closed before the command runs.
+pub fn with_env(
+    kdbx: &Path,
+    key: &DatabaseKey,
+    secrets: &[Secret],
+    command: Command,
+) -> Result<Command, Box<dyn Error>> {
+    KeePass::open(kdbx, key)?.env(secrets, command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_secret() {
+        let secret = Secret::parse("GITHUB_USER=Work/CI/github#UserName").unwrap();
+        assert_eq!(secret.env_var, "GITHUB_USER");
+        assert_eq!(secret.entry, "Work/CI/github");
+        assert_eq!(secret.field, "UserName");
+        assert_eq!(Secret::parse("TOKEN=github").unwrap().field, "Password");
+        assert!(Secret::parse("github").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets.kdbx");
+        let key = DatabaseKey::password(&SecretString::from("hunter2"));
+        let token = SecretString::from("t0k3n");
+        let mut db = KeePass::create(&path, &key).unwrap();
+        let fields = [
+            ("Password", &token),
+            ("UserName", &SecretString::from("ci")),
+        ];
+        assert!(db.set_entry("Work/CI/github", &fields).unwrap());
+        db.save().unwrap();
+
+        let mut db = KeePass::open(&path, &key).unwrap();
+        let uuid = db.locate("github").unwrap().1.uuid().to_string();
+        for reference in ["Work/CI/github", "github", uuid.as_str()] {
+            assert_eq!(db.field(reference, "Password"), Some(token.clone()));
+        }
+        assert_eq!(db.field("Work/github", "Password"), None);
+
+        // Updates go to the entry found, wherever it is
+        let rotated = SecretString::from("r0tated");
+        assert!(!db.set_entry(&uuid, &[("Password", &rotated)]).unwrap());
+        assert!(!db
+            .set_entry(
+                "github",
+                &[("URL", &SecretString::from("https://github.com"))]
+            )
+            .unwrap());
+        assert!(db.set_entry("gitlab", &[("Password", &token)]).is_err());
+        db.save().unwrap();
+
+        let db = KeePass::open(&path, &key).unwrap();
+        assert_eq!(db.field("Work/CI/github", "Password"), Some(rotated));
+        assert_eq!(
+            db.field("github", "URL").unwrap().expose(),
+            "https://github.com"
+        );
+        assert_eq!(db.kdbx.root().entries().count(), 0);
+
+        let wrong = DatabaseKey::password(&SecretString::from("wrong"));
+        assert!(KeePass::open(&path, &wrong).is_err());
+    }
+
+    #[test]
+    fn test_with_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets.kdbx");
+        let key = DatabaseKey::password(&SecretString::from("hunter2"));
+        let mut db = KeePass::create(&path, &key).unwrap();
+        db.set_entry(
+            "Work/CI/github",
+            &[("Password", &SecretString::from("t0k3n"))],
+        )
+        .unwrap();
+        db.save().unwrap();
+        let saved = fs::read(&path).unwrap();
+
+        let secrets = [Secret::parse("VAR=Work/CI/github").unwrap()];
+        let command = Command::new("sh")
+            .args(&["-c", "printf %s \"$VAR\""])
+            .cwd(dir.path());
+        let output = with_env(&path, &key, &secrets, command)
+            .unwrap()
+            .output()
+            .unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout, "t0k3n");
+
+        // Only the database is in the directory, unchanged
+        let files = fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(files, 1);
+        assert_eq!(fs::read(&path).unwrap(), saved);
+
+        let missing = [Secret::parse("VAR=Work/CI/gitlab").unwrap()];
+        assert!(with_env(&path, &key, &missing, Command::new("true")).is_err());
+    }
+
+    #[test]
+    fn test_normalize_uuid() {
+        assert_eq!(
+            normalize_uuid("6F2D1B1C-6A1D-4E47-9D0A-3F0C3A4E2B11").as_deref(),
+            Some("6f2d1b1c6a1d4e479d0a3f0c3a4e2b11")
+        );
+        assert_eq!(normalize_uuid("Work/CI/github"), None);
+    }
+
+    #[test]
+    fn test_totp() {
+        // The RFC 6238 test secret, "12345678901234567890"
+        let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+        let uri = format!(
+            "otpauth://totp/valis:ci?secret={}&digits=8&period=30",
+            secret
+        );
+        let otp = |key: &str| (key == "otp").then(|| uri.clone());
+        assert_eq!(totp_at(otp, 59).unwrap().as_deref(), Some("94287082"));
 
-    Ok(values)
+        let keepass = |key: &str| match key {
+            "TimeOtp-Secret-Base32" => Some(secret.to_string()),
+            "TimeOtp-Length" => Some("8".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            totp_at(keepass, 1111111109).unwrap().as_deref(),
+            Some("07081804")
+        );
+        assert_eq!(totp_at(|_| None, 59).unwrap(), None);
+    }
 }
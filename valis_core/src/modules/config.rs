@@ -0,0 +1,403 @@
+//! The valis configuration, read from `~/.config/valis/config.toml`, or from the file named
+//! by `VALIS_CONFIG`. Every setting has a default, so the file and all of its keys are
+//! optional.
+//!
+//! ```toml
+//! db = "~/valis.db"
+//!
+//! [kind]
+//! version = "1.27.3"
+//!
+//! [profiles.work]
+//! db = "~/work/valis.db"
+//! kopia = { bucket = "work-backups" }
+//! ```
+//!
+//! The settings of a profile, selected with `--profile`, `VALIS_PROFILE` or a top-level
+//! `profile` key, override the top-level ones. Environment variables named after a setting,
+//! with `__` between the section and the key, override both, e.g. `VALIS_DB` or
+//! `VALIS_KIND__VERSION`.
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value as YamlValue};
+use toml_edit::Document;
+
+use crate::modules::core;
+use crate::modules::formats::{toml, yaml};
+use crate::modules::log::{self, LogConfig};
+
+const ENV_PREFIX: &str = "VALIS_";
+
+/// The kind settings.
+/// - `version`: The Kubernetes version of the clusters, "1.22.15" by default.
+/// - `context`: The name of the clusters, "kind" by default.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KindSettings {
+    pub version: String,
+    pub context: String,
+}
+
+impl Default for KindSettings {
+    fn default() -> Self {
+        KindSettings {
+            version: "1.22.15".to_owned(),
+            context: "kind".to_owned(),
+        }
+    }
+}
+
+/// The Todoist settings. The API token is the `todoist` credential.
+/// - `url`: The URL of the REST API.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TodoistSettings {
+    pub url: String,
+}
+
+impl Default for TodoistSettings {
+    fn default() -> Self {
+        TodoistSettings {
+            url: "https://api.todoist.com/rest/v2".to_owned(),
+        }
+    }
+}
+
+/// The settings of the Kopia S3 repository. The S3 secret key is the `kopia-s3` credential
+/// and the repository password the `kopia` one.
+/// - `bucket`: The bucket of the repository.
+/// - `endpoint`: The S3 endpoint.
+/// - `access_key`: The S3 access key.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KopiaSettings {
+    pub bucket: Option<String>,
+    pub endpoint: Option<String>,
+    pub access_key: Option<String>,
+}
+
+/// The credential settings.
+/// - `order`: The backends tried in turn for a credential: `env`, `authinfo`, `keyring`,
+///   `pass` and `kdbx` by default.
+/// - `kdbx`: The KeePass database of the `kdbx` backend, which is skipped without one.
+/// - `kdbx_password_env`: The variable holding the password of the database, `KDBX_PASSWORD`
+///   by default. The password is asked for on the terminal when it is unset.
+/// - `kdbx_key_file`: The key file of the database, when it has one.
+/// - `pass_dir`: The store of the `pass` backend, `~/.password-store` by default.
+/// - `names`: The names of the credentials in the backends, by logical name and backend,
+///   e.g. `[credentials.github] authinfo = "api.github.com"`. A backend without a name for
+///   a credential looks it up by its logical name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CredentialSettings {
+    pub order: Vec<String>,
+    pub kdbx: Option<PathBuf>,
+    pub kdbx_password_env: String,
+    pub kdbx_key_file: Option<PathBuf>,
+    pub pass_dir: Option<PathBuf>,
+    #[serde(flatten)]
+    pub names: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Default for CredentialSettings {
+    fn default() -> Self {
+        let names = [
+            ("github", "env", "GITHUB_TOKEN"),
+            ("github", "authinfo", "api.github.com"),
+            ("todoist", "env", "TODOIST_TOKEN"),
+            ("todoist", "authinfo", "api.todoist.com"),
+            ("kopia-s3", "env", "WASABI_KOPIA_SECRET_KEY"),
+            ("kopia", "env", "KOPIA_PASSWORD"),
+        ];
+        let mut settings = CredentialSettings {
+            order: ["env", "authinfo", "keyring", "pass", "kdbx"]
+                .iter()
+                .map(|backend| backend.to_string())
+                .collect(),
+            kdbx: None,
+            kdbx_password_env: "KDBX_PASSWORD".to_owned(),
+            kdbx_key_file: None,
+            pass_dir: None,
+            names: BTreeMap::new(),
+        };
+        for (name, backend, key) in names {
+            settings
+                .names
+                .entry(name.to_owned())
+                .or_default()
+                .insert(backend.to_owned(), key.to_owned());
+        }
+        settings
+    }
+}
+
+/// The valis configuration.
+/// - `db`: The SQLite database of the agile and Todoist modules,
+///   `~/.config/valis/valis.db` by default.
+/// - `authinfo`: The authinfo file, `~/.authinfo.gpg` if it exists, else `~/.authinfo`.
+/// - `virtualenvs`: The directory of the Python virtualenvs, `~/.virtualenvs` by default.
+/// - `kind`, `todoist`, `kopia`, `credentials`, `log`: The settings of these modules.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub db: PathBuf,
+    pub authinfo: PathBuf,
+    pub virtualenvs: PathBuf,
+    pub kind: KindSettings,
+    pub todoist: TodoistSettings,
+    pub kopia: KopiaSettings,
+    pub credentials: CredentialSettings,
+    pub log: LogConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            db: PathBuf::from("~/.config/valis/valis.db"),
+            authinfo: ["~/.authinfo.gpg", "~/.authinfo"]
+                .into_iter()
+                .map(|path| expand(Path::new(path)))
+                .find(|path| path.exists())
+                .unwrap_or_else(|| PathBuf::from("~/.authinfo")),
+            virtualenvs: PathBuf::from("~/.virtualenvs"),
+            kind: KindSettings::default(),
+            todoist: TodoistSettings::default(),
+            kopia: KopiaSettings::default(),
+            credentials: CredentialSettings::default(),
+            log: LogConfig::default(),
+        }
+        .expand_home()
+    }
+}
+
+fn expand(path: &Path) -> PathBuf {
+    match path.to_str() {
+        Some(path) => core::to_path_buf(path).unwrap_or_else(|| PathBuf::from(path)),
+        None => path.to_path_buf(),
+    }
+}
+
+/// Merge `overrides` into `base`: mappings are merged key by key, other values replaced.
+fn merge(base: &mut YamlValue, overrides: YamlValue) {
+    match (base, overrides) {
+        (YamlValue::Mapping(base), YamlValue::Mapping(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// Parse the value of an environment variable with the type of the setting it overrides:
+/// `VALIS_KIND__VERSION=1.25` stays a string, `VALIS_LOG__FILE` a path.
+fn parse_env_value(current: Option<&YamlValue>, value: &str) -> YamlValue {
+    match current {
+        Some(YamlValue::Bool(_))
+        | Some(YamlValue::Number(_))
+        | Some(YamlValue::Sequence(_))
+        | Some(YamlValue::Mapping(_)) => {
+            serde_yaml::from_str(value).unwrap_or_else(|_| YamlValue::from(value))
+        }
+        _ => YamlValue::from(value),
+    }
+}
+
+/// `doc` with the setting at `path` set to the value of a variable, if the result is a valid
+/// configuration.
+fn apply_env_value(doc: &YamlValue, path: &str, value: &str) -> Result<YamlValue, Box<dyn Error>> {
+    let mut doc = doc.clone();
+    let current = yaml::get_values(&doc, path)?.into_iter().next();
+    yaml::set_value(&mut doc, path, &parse_env_value(current.as_ref(), value))?;
+    serde_yaml::from_value::<Config>(doc.clone())?;
+    Ok(doc)
+}
+
+impl Config {
+    /// The path of the database, as taken by the agile and Todoist functions.
+    pub fn db_path(&self) -> String {
+        self.db.to_string_lossy().to_string()
+    }
+
+    fn expand_home(mut self) -> Config {
+        self.db = expand(&self.db);
+        self.authinfo = expand(&self.authinfo);
+        self.virtualenvs = expand(&self.virtualenvs);
+        self.credentials.kdbx = self.credentials.kdbx.as_deref().map(expand);
+        self.credentials.kdbx_key_file = self.credentials.kdbx_key_file.as_deref().map(expand);
+        self.credentials.pass_dir = self.credentials.pass_dir.as_deref().map(expand);
+        self.log.file = self.log.file.as_deref().map(expand);
+        self
+    }
+
+    /// Parse the configuration from the contents of a file, with the settings of `profile`
+    /// and then the `VALIS_` variables of `vars` applied.
+    pub fn parse<I>(
+        contents: &str,
+        profile: Option<&str>,
+        vars: I,
+    ) -> Result<Config, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut doc = serde_yaml::to_value(Config::default())?;
+        let mut file = match toml::toml_to_yaml(contents.parse::<Document>()?.as_item()) {
+            YamlValue::Mapping(file) => file,
+            _ => Mapping::new(),
+        };
+        let profiles = file.remove(&YamlValue::from("profiles"));
+        let default_profile = file
+            .remove(&YamlValue::from("profile"))
+            .and_then(|profile| profile.as_str().map(|profile| profile.to_owned()));
+        merge(&mut doc, YamlValue::Mapping(file));
+
+        let vars = vars.into_iter().collect::<Vec<_>>();
+        let profile = profile
+            .map(|profile| profile.to_owned())
+            .or_else(|| {
+                vars.iter()
+                    .find(|(key, _)| key == "VALIS_PROFILE")
+                    .map(|(_, value)| value.clone())
+            })
+            .or(default_profile);
+        if let Some(profile) = profile {
+            let settings = profiles
+                .as_ref()
+                .and_then(|profiles| profiles.get(profile.as_str()))
+                .ok_or_else(|| format!("Unknown profile: {}", profile))?;
+            merge(&mut doc, settings.clone());
+        }
+
+        // A malformed variable is ignored, rather than making every command fail
+        for (key, value) in vars {
+            let path = match key.strip_prefix(ENV_PREFIX) {
+                Some("PROFILE") | Some("CONFIG") | None => continue,
+                Some(name) => name.to_lowercase().replace("__", "."),
+            };
+            match apply_env_value(&doc, &path, &value) {
+                Ok(overridden) => doc = overridden,
+                Err(e) => log::warn(&format!("Ignoring {}: {}", key, e)),
+            }
+        }
+
+        let config: Config = serde_yaml::from_value(doc)?;
+        Ok(config.expand_home())
+    }
+}
+
+/// The path of the configuration file: `VALIS_CONFIG`, or `~/.config/valis/config.toml`.
+pub fn path() -> PathBuf {
+    match env::var("VALIS_CONFIG") {
+        Ok(path) => expand(Path::new(&path)),
+        Err(_) => expand(Path::new("~/.config/valis/config.toml")),
+    }
+}
+
+/// Load the configuration from `path`, with the settings of `profile` and the environment
+/// variables applied. A missing file gives the default configuration.
+pub fn load(path: &Path, profile: Option<&str>) -> Result<Config, Box<dyn Error>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Could not read {}: {}", path.display(), e).into()),
+    };
+    Config::parse(&contents, profile, env::vars())
+        .map_err(|e| format!("Invalid configuration {}: {}", path.display(), e).into())
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Load the configuration of the process, with the settings of `profile`. Without a profile,
+/// a configuration already loaded, e.g. by [`get`], is returned. With one, that is an error,
+/// as the profile couldn't be applied anymore.
+pub fn init(profile: Option<&str>) -> Result<&'static Config, Box<dyn Error>> {
+    if CONFIG.get().is_none() {
+        let config = load(&path(), profile)?;
+        if CONFIG.set(config).is_ok() {
+            return Ok(CONFIG.get().unwrap());
+        }
+    }
+    match profile {
+        Some(profile) => Err(format!(
+            "The configuration is already loaded, the profile {} can't be applied",
+            profile
+        )
+        .into()),
+        None => Ok(CONFIG.get().unwrap()),
+    }
+}
+
+/// The configuration of the process, loaded on first use. An invalid configuration is
+/// reported and replaced by the defaults.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(|| {
+        load(&path(), None).unwrap_or_else(|e| {
+            log::warn(&format!("{}, using the defaults", e));
+            Config::default()
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let contents = r#"
+profile = "home"
+db = "/data/valis.db"
+
+[kind]
+version = "1.27.3"
+
+[profiles.home.kopia]
+bucket = "photos"
+
+[profiles.work]
+db = "~/work/valis.db"
+log = { level = "debug" }
+"#;
+        let config = Config::parse(contents, None, vec![]).unwrap();
+        assert_eq!(config.db, PathBuf::from("/data/valis.db"));
+        assert_eq!(config.kind.version, "1.27.3");
+        assert_eq!(config.kind.context, "kind");
+        assert_eq!(config.kopia.bucket.as_deref(), Some("photos"));
+
+        let config = Config::parse(contents, Some("work"), vec![]).unwrap();
+        assert_eq!(config.db, dirs::home_dir().unwrap().join("work/valis.db"));
+        assert_eq!(config.log.level, log::Level::Debug);
+        assert_eq!(config.kopia.bucket, None);
+
+        assert!(Config::parse(contents, Some("missing"), vec![]).is_err());
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let vars = vec![
+            ("VALIS_PROFILE".to_string(), "ci".to_string()),
+            ("VALIS_KIND__VERSION".to_string(), "1.25".to_string()),
+            ("VALIS_LOG__FORMAT".to_string(), "json".to_string()),
+            ("PATH".to_string(), "/usr/bin".to_string()),
+            ("VALIS_LOG__LEVEL".to_string(), "loud".to_string()),
+        ];
+        let contents = "[profiles.ci]\nvirtualenvs = \"/opt/venvs\"\n";
+        let config = Config::parse(contents, None, vars).unwrap();
+        assert_eq!(config.kind.version, "1.25");
+        assert_eq!(config.log.format, log::Format::Json);
+        assert_eq!(config.log.level, LogConfig::default().level);
+        assert_eq!(config.virtualenvs, PathBuf::from("/opt/venvs"));
+        assert_eq!(Config::parse("", None, vec![]).unwrap(), Config::default());
+    }
+}
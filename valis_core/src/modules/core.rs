@@ -1,51 +1,408 @@
-use std::io::BufRead;
-use std::io::BufReader;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use std::process::Stdio;
+use std::process::{self, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{env, fs};
 
 use dirs;
 use globmatch::Matcher;
+use zeroize::Zeroizing;
 
-/// Run a command string and outputs realtime output to stdout.
-///
-/// # Arguments
+use crate::modules::plan;
+
+/// A secret such as a password or a token. Its memory is zeroed when it is dropped, and it
+/// is never shown: `Debug` prints `[redacted]`.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct SecretString(Zeroizing<String>);
+
+impl SecretString {
+    pub fn new(secret: String) -> SecretString {
+        SecretString(Zeroizing::new(secret))
+    }
+
+    /// The secret value, to pass it to where it is needed.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> SecretString {
+        SecretString::new(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> SecretString {
+        SecretString::new(secret.to_string())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", REDACTED)
+    }
+}
+
+/// How secrets are shown in command lines and logs.
+pub const REDACTED: &str = "[redacted]";
+
+/// The result of a [`Command`].
+/// - `code`: The exit code, `None` if the process was killed by a signal or timed out.
+/// - `stdout`, `stderr`: The captured outputs, empty when they are not captured.
+/// - `timed_out`: Whether the command was killed after its timeout.
+/// - `interrupted`: Whether the command was stopped by Ctrl-C.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandOutput {
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub timed_out: bool,
+    pub interrupted: bool,
+}
+
+impl CommandOutput {
+    /// Whether the command exited with code 0.
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    /// Return the output if the command succeeded, an error with its stderr otherwise.
+    pub fn check(self, command: &Command) -> Result<CommandOutput, Box<dyn Error>> {
+        if self.success() {
+            return Ok(self);
+        }
+        let status = match (self.timed_out, self.interrupted, self.code) {
+            (true, _, _) => "timed out".to_string(),
+            (false, true, _) => "was interrupted".to_string(),
+            (false, false, Some(code)) => format!("exited with code {}", code),
+            (false, false, None) => "was killed".to_string(),
+        };
+        Err(format!("`{}` {}: {}", command, status, self.stderr.trim()).into())
+    }
+}
+
+/// A command to run, built from a program and its arguments. Secrets can be passed as
+/// arguments, environment variables or on stdin: they are redacted when the command is shown.
 ///
-/// * `command` - A string slice that holds the command to be executed
+/// ```no_run
+/// use std::time::Duration;
+/// use valis_core::modules::core::Command;
 ///
-pub fn run(command: &str) {
-    let mut tokens = command.split(" ").collect::<Vec<&str>>();
-    let location = tokens[0];
-    tokens.remove(0);
+/// let output = Command::parse("git log -n 1 --format='%h %s'")
+///     .unwrap()
+///     .cwd("/tmp/repo")
+///     .env("GIT_PAGER", "cat")
+///     .timeout(Duration::from_secs(10))
+///     .output()
+///     .unwrap();
+/// println!("{} {}", output.code.unwrap_or(-1), output.stdout);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Command {
+    pub program: String,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub cwd: Option<PathBuf>,
+    pub timeout: Option<Duration>,
+    /// Whether the command only reads, and so also runs in dry-run mode.
+    pub read_only: bool,
+    /// Secret arguments, with the number of `args` before them.
+    secret_args: Vec<(usize, SecretString)>,
+    secret_env: Vec<(String, SecretString)>,
+    stdin: Option<SecretString>,
+}
+
+impl fmt::Display for Command {
+    /// The command line, quoted as for a shell, with the secret arguments redacted.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let words = std::iter::once(shell_words::quote(&self.program).to_string())
+            .chain(self.argv(|_| REDACTED.to_string()).into_iter().map(|arg| {
+                if arg == REDACTED {
+                    arg
+                } else {
+                    shell_words::quote(&arg).to_string()
+                }
+            }))
+            .collect::<Vec<_>>();
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+impl Command {
+    /// A command running `program`, found in the `PATH` if it is not a path.
+    pub fn new(program: &str) -> Command {
+        Command {
+            program: program.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// A command from a command line, split as a POSIX shell would: quotes and backslashes
+    /// are supported, but not variables, pipes or globs.
+    pub fn parse(command_line: &str) -> Result<Command, Box<dyn Error>> {
+        let words = shell_words::split(command_line)?;
+        let (program, args) = words
+            .split_first()
+            .ok_or_else(|| format!("Empty command: {:?}", command_line))?;
+        Ok(Command::new(program).args(args))
+    }
+
+    /// Add an argument.
+    pub fn arg(mut self, arg: &str) -> Command {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    /// Add arguments.
+    pub fn args<S: AsRef<str>>(mut self, args: &[S]) -> Command {
+        self.args
+            .extend(args.iter().map(|arg| arg.as_ref().to_string()));
+        self
+    }
+
+    /// Set an environment variable of the command.
+    pub fn env(mut self, key: &str, value: &str) -> Command {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Run the command in `dir`.
+    pub fn cwd<P: AsRef<Path>>(mut self, dir: P) -> Command {
+        self.cwd = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Kill the command if it runs longer than `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Command {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Mark the command as only reading, e.g. `gpg --decrypt`, so that it also runs in
+    /// dry-run mode.
+    pub fn read_only(mut self) -> Command {
+        self.read_only = true;
+        self
+    }
+
+    /// Add a secret argument, redacted when the command is shown. Prefer
+    /// [`Command::secret_env`] or [`Command::stdin`] when the program supports them, as
+    /// arguments are visible to the other users of the machine, e.g. with `ps`.
+    pub fn secret_arg(mut self, secret: &SecretString) -> Command {
+        self.secret_args.push((self.args.len(), secret.clone()));
+        self
+    }
+
+    /// Set a secret environment variable of the command.
+    pub fn secret_env(mut self, key: &str, secret: &SecretString) -> Command {
+        self.secret_env.push((key.to_string(), secret.clone()));
+        self
+    }
 
-    let mut child = Command::new(location)
-        .args(tokens)
-        .stdout(Stdio::piped())
-        .spawn()
-        .unwrap();
-    let stdout = child.stdout.take().unwrap();
+    /// Write `secret` to the stdin of the command.
+    pub fn stdin(mut self, secret: &SecretString) -> Command {
+        self.stdin = Some(secret.clone());
+        self
+    }
+
+    pub(crate) fn input(&self) -> Option<&SecretString> {
+        self.stdin.as_ref()
+    }
+
+    /// The arguments, with the secret ones mapped by `secret`.
+    fn argv(&self, secret: impl Fn(&SecretString) -> String) -> Vec<String> {
+        let mut argv = Vec::new();
+        let mut secrets = self.secret_args.iter().peekable();
+        for index in 0..=self.args.len() {
+            while let Some((_, value)) = secrets.next_if(|(position, _)| *position == index) {
+                argv.push(secret(value));
+            }
+            if let Some(arg) = self.args.get(index) {
+                argv.push(arg.clone());
+            }
+        }
+        argv
+    }
+
+    pub(crate) fn to_process(&self) -> process::Command {
+        let mut command = process::Command::new(&self.program);
+        command.args(self.argv(|secret| secret.expose().to_string()));
+        command.envs(self.env.iter().map(|(key, value)| (key, value)));
+        command.envs(
+            self.secret_env
+                .iter()
+                .map(|(key, value)| (key, value.expose())),
+        );
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        if self.stdin.is_some() {
+            command.stdin(Stdio::piped());
+        }
+        command
+    }
+
+    /// The process of the command in its own process group, so that it can be killed with
+    /// the processes it starts, which may hold its pipes open.
+    pub(crate) fn to_process_group(&self) -> process::Command {
+        let mut command = self.to_process();
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            command.process_group(0);
+        }
+        command
+    }
+
+    /// Write the secret input of the command to the stdin of `child`, then close it.
+    fn write_input(&self, child: &mut process::Child) {
+        if let (Some(input), Some(mut stdin)) = (self.stdin.clone(), child.stdin.take()) {
+            thread::spawn(move || {
+                let _ = stdin.write_all(input.expose().as_bytes());
+            });
+        }
+    }
+
+    /// Wait for `child`, killing it after the timeout, with its process group if it was
+    /// spawned in one (see [`Command::to_process_group`]).
+    fn wait(&self, child: &mut process::Child, group: bool) -> io::Result<(Option<i32>, bool)> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Ok((child.wait()?.code(), false)),
+        };
+        let start = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok((status.code(), false));
+            }
+            if start.elapsed() >= timeout {
+                if group {
+                    kill_group(child.id());
+                }
+                // The child may have exited with its group already
+                let _ = child.kill();
+                child.wait()?;
+                return Ok((None, true));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
 
-    // Stream output.
-    let lines = BufReader::new(stdout).lines();
-    for line in lines {
-        println!("{}", line.unwrap());
+    /// In dry-run mode, record the command and return a successful output without running it.
+    pub(crate) fn plan(&self) -> Option<CommandOutput> {
+        if !plan::is_dry_run() || self.read_only {
+            return None;
+        }
+        plan::record(plan::Action::Command(self.to_string()));
+        Some(CommandOutput {
+            code: Some(0),
+            ..Default::default()
+        })
+    }
+
+    fn spawn(&self, command: &mut process::Command) -> Result<process::Child, Box<dyn Error>> {
+        command
+            .spawn()
+            .map_err(|e| format!("Could not run `{}`: {}", self, e).into())
+    }
+
+    /// Run the command, capturing its stdout and stderr.
+    pub fn output(&self) -> Result<CommandOutput, Box<dyn Error>> {
+        if let Some(output) = self.plan() {
+            return Ok(output);
+        }
+        // A command with a timeout runs in its own process group, so that the processes it
+        // started, which may hold the pipes open, are killed with it
+        let group = self.timeout.is_some();
+        let mut command = if group {
+            self.to_process_group()
+        } else {
+            self.to_process()
+        };
+        if self.stdin.is_none() {
+            command.stdin(Stdio::null());
+        }
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut child = self.spawn(&mut command)?;
+        self.write_input(&mut child);
+
+        // Read both outputs concurrently, so that neither pipe fills up
+        let read = |mut pipe: Box<dyn Read + Send>| {
+            thread::spawn(move || {
+                let mut buffer = Vec::new();
+                let _ = pipe.read_to_end(&mut buffer);
+                String::from_utf8_lossy(&buffer).to_string()
+            })
+        };
+        let stdout = read(Box::new(child.stdout.take().unwrap()));
+        let stderr = read(Box::new(child.stderr.take().unwrap()));
+        let (code, timed_out) = self.wait(&mut child, group)?;
+        Ok(CommandOutput {
+            code,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+            timed_out,
+            ..Default::default()
+        })
+    }
+
+    /// Run the command with the stdin, stdout and stderr of the current process, so that
+    /// its output is shown as it runs.
+    pub fn status(&self) -> Result<CommandOutput, Box<dyn Error>> {
+        if let Some(output) = self.plan() {
+            return Ok(output);
+        }
+        let mut child = self.spawn(&mut self.to_process())?;
+        self.write_input(&mut child);
+        let (code, timed_out) = self.wait(&mut child, false)?;
+        Ok(CommandOutput {
+            code,
+            timed_out,
+            ..Default::default()
+        })
     }
 }
 
-pub fn run_buffered(command: &str) -> String {
-    let mut tokens = command.split(" ").collect::<Vec<&str>>();
-    let location = tokens[0];
-    tokens.remove(0);
-    let output = Command::new(location)
-        .args(tokens)
-        .output()
-        .expect("Failed to execute process.");
+/// Send `signal` to the process group `pid`, i.e. to a process spawned with
+/// [`Command::to_process_group`] and the processes it started.
+#[cfg(unix)]
+pub(crate) fn signal_group(pid: u32, signal: libc::c_int) {
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), signal);
+    }
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    print!("{}", stdout.to_owned());
+/// Kill the process group `pid`.
+pub(crate) fn kill_group(pid: u32) {
+    #[cfg(unix)]
+    signal_group(pid, libc::SIGKILL);
+    #[cfg(not(unix))]
+    let _ = pid;
+}
 
-    return stdout.to_owned();
+/// Run a command line (see [`Command::parse`]), showing its output as it runs.
+/// Fails if the command can't be run or exits with a non-zero code.
+///
+/// # Arguments
+///
+/// * `command` - A string slice that holds the command to be executed
+///
+pub fn run(command: &str) -> Result<CommandOutput, Box<dyn Error>> {
+    let command = Command::parse(command)?;
+    command.status()?.check(&command)
+}
+
+/// Run a command line (see [`Command::parse`]), capturing its stdout, stderr and exit code.
+pub fn run_buffered(command: &str) -> Result<CommandOutput, Box<dyn Error>> {
+    Command::parse(command)?.output()
 }
 
 /// Return the OS name on which we are running.
@@ -120,10 +477,10 @@ pub fn from_home(partial_path: &str) -> Option<String> {
 /// # Arguments
 /// * `partial_path` - A string slice that holds the partial path of the file.
 pub fn to_path_buf(path: &str) -> Option<PathBuf> {
-    if path.starts_with("~") {
+    if let Some(rest) = path.strip_prefix('~') {
         if let Some(mut home_path) = dirs::home_dir() {
-            if path.len() > 1 {
-                home_path.push(&path[1..]);
+            if !rest.is_empty() {
+                home_path.push(rest.trim_start_matches('/'));
             }
             Some(home_path)
         } else {
@@ -133,3 +490,80 @@ pub fn to_path_buf(path: &str) -> Option<PathBuf> {
         Some(Path::new(path).to_path_buf())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        let command =
+            Command::parse("kopia snapshot create '/home/me/My Documents' --tags=\"a b\"").unwrap();
+        assert_eq!(command.program, "kopia");
+        assert_eq!(
+            command.args,
+            vec!["snapshot", "create", "/home/me/My Documents", "--tags=a b"]
+        );
+        assert_eq!(
+            command.to_string(),
+            "kopia snapshot create '/home/me/My Documents' '--tags=a b'"
+        );
+        assert!(Command::parse("").is_err());
+        assert!(Command::parse("echo 'unclosed").is_err());
+    }
+
+    #[test]
+    fn test_command_output() {
+        let output = Command::new("sh")
+            .args(&[
+                "-c",
+                "echo \"$GREETING\" from $(pwd); echo oops >&2; exit 3",
+            ])
+            .env("GREETING", "hello")
+            .cwd("/")
+            .output()
+            .unwrap();
+        assert_eq!(output.code, Some(3));
+        assert_eq!(output.stdout, "hello from /\n");
+        assert_eq!(output.stderr, "oops\n");
+        assert!(output.check(&Command::new("sh")).is_err());
+
+        let output = Command::new("sleep")
+            .arg("5")
+            .timeout(Duration::from_millis(50))
+            .output()
+            .unwrap();
+        assert!(output.timed_out);
+        assert_eq!(output.code, None);
+        assert!(Command::new("valis-missing-program").output().is_err());
+
+        // The processes started by the command are killed too, and release its pipes
+        let start = Instant::now();
+        let output = Command::new("sh")
+            .args(&["-c", "sleep 5 | cat"])
+            .timeout(Duration::from_millis(50))
+            .output()
+            .unwrap();
+        assert!(output.timed_out);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_command_secrets() {
+        let password = SecretString::from("hunter2");
+        assert_eq!(format!("{:?}", password), "[redacted]");
+        let command = Command::new("sh")
+            .arg("-c")
+            .arg("echo \"$0 $TOKEN $(cat)\"")
+            .secret_arg(&password)
+            .secret_env("TOKEN", &SecretString::from("t0k3n"))
+            .stdin(&SecretString::from("from stdin"));
+        assert_eq!(
+            command.to_string(),
+            "sh -c 'echo \"$0 $TOKEN $(cat)\"' [redacted]"
+        );
+        assert!(!format!("{:?}", command).contains("hunter2"));
+        let output = command.output().unwrap();
+        assert_eq!(output.stdout, "hunter2 t0k3n from stdin\n");
+    }
+}
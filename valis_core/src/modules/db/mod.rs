@@ -1,7 +1,12 @@
 use std::fmt::Error;
+use std::fs;
+use std::path::Path;
 
 use rusqlite::{Connection, Row};
 
+use crate::modules::log;
+use crate::modules::plan;
+
 pub mod serializers;
 
 pub trait DatabaseOperations<T> {
@@ -17,12 +22,25 @@ fn get_sql_schema() -> Vec<String> {
     include_str!("tables.sql").split("---").map(|s| s.to_string()).collect::<Vec<String>>()
 }
 
-pub fn init_db(db: &str) -> Result<(), rusqlite::Error> {
+/// Create the database `db` and its directory if needed, and the tables missing from it.
+pub fn init_db(db: &str) -> Result<(), Box<dyn std::error::Error>> {
+    // In dry-run mode, the schema is recorded as a single action, and only for a new database
+    if plan::is_dry_run() {
+        if !Path::new(db).exists() {
+            plan::record(plan::Action::Schema { db: db.to_string() });
+        }
+        return Ok(());
+    }
+    let parent = Path::new(db).parent();
+    if let Some(parent) = parent.filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Could not create {}: {}", parent.display(), e))?;
+    }
     let conn = Connection::open(db)?;
 
-    get_sql_schema().into_iter().for_each(|sql| {
-        conn.execute(&sql, []).ok().unwrap();
-    });
+    for sql in get_sql_schema() {
+        plan::execute(&conn, &sql, &[])?;
+    }
 
     Ok(())
 }
@@ -31,7 +49,7 @@ pub fn get_connection(db: &str) -> Connection {
     match Connection::open(db) {
         Ok(conn) => conn,
         Err(e) => {
-            println!("Failed to open database: {}", e);
+            log::error(&format!("Failed to open database: {}", e));
             std::process::exit(1);
         }
     }
@@ -45,4 +45,9 @@ CREATE TABLE IF NOT EXISTS sprint
     created_at TEXT NOT NULL,
     updated_at TEXT NOT NULL,
     FOREIGN KEY (project_id) REFERENCES project (id)
-);
\ No newline at end of file
+);---
+CREATE TABLE IF NOT EXISTS todoist_closed_tasks
+(
+    id        VARCHAR PRIMARY KEY,
+    closed_at TEXT NOT NULL
+);
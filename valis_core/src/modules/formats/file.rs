@@ -0,0 +1,72 @@
+use std::error::Error;
+use std::path::Path;
+
+use serde_yaml::Value as YamlValue;
+
+use crate::modules::formats::{json, toml, yaml};
+
+/// The structured file formats which can be edited by path.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Format {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl Format {
+    /// Detect the format of a file from its extension.
+    pub fn from_path(path: &str) -> Result<Format, Box<dyn Error>> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("");
+        match extension.to_lowercase().as_str() {
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "toml" => Ok(Format::Toml),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("Unknown file format: {}", path).into()),
+        }
+    }
+}
+
+/// Get all the values matching `path` in a YAML, TOML or JSON file.
+pub fn get_file_values(file_path: &str, path: &str) -> Result<Vec<YamlValue>, Box<dyn Error>> {
+    match Format::from_path(file_path)? {
+        Format::Yaml => yaml::get_yaml_values(file_path, path),
+        Format::Toml => toml::get_toml_values(file_path, path),
+        Format::Json => json::get_json_values(file_path, path),
+    }
+}
+
+/// Get a value from a YAML, TOML or JSON file, as a `String`.
+pub fn get_file_value(file_path: &str, path: &str) -> Result<String, Box<dyn Error>> {
+    match Format::from_path(file_path)? {
+        Format::Yaml => yaml::get_yaml_value(file_path, path),
+        Format::Toml => toml::get_toml_value(file_path, path),
+        Format::Json => json::get_json_value(file_path, path),
+    }
+}
+
+/// Set the values matching `path` in a YAML, TOML or JSON file, creating the missing keys.
+/// Returns the number of values set.
+pub fn set_file_value(
+    file_path: &str,
+    path: &str,
+    value: &YamlValue,
+) -> Result<usize, Box<dyn Error>> {
+    match Format::from_path(file_path)? {
+        Format::Yaml => yaml::set_yaml_value(file_path, path, value),
+        Format::Toml => toml::set_toml_value(file_path, path, value),
+        Format::Json => json::set_json_value(file_path, path, value),
+    }
+}
+
+/// Delete the values matching `path` in a YAML, TOML or JSON file.
+/// Returns the number of values deleted.
+pub fn delete_file_value(file_path: &str, path: &str) -> Result<usize, Box<dyn Error>> {
+    match Format::from_path(file_path)? {
+        Format::Yaml => yaml::delete_yaml_value(file_path, path),
+        Format::Toml => toml::delete_toml_value(file_path, path),
+        Format::Json => json::delete_json_value(file_path, path),
+    }
+}
@@ -0,0 +1,119 @@
+use std::error::Error;
+use std::fs;
+
+use serde::Serialize;
+use serde_json::ser::PrettyFormatter;
+use serde_json::Value as JsonValue;
+use serde_yaml::Value as YamlValue;
+
+use crate::modules::formats::yaml::{self, scalar_to_string};
+use crate::modules::plan;
+
+/// The indentation of a JSON document, e.g. two spaces for a `package.json`.
+fn detect_indent(contents: &str) -> String {
+    contents
+        .lines()
+        .skip(1)
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .find(|indent| !indent.is_empty())
+        .unwrap_or("  ")
+        .to_string()
+}
+
+/// Render `value` as pretty JSON, indented with `indent`.
+fn to_string_pretty(value: &JsonValue, indent: &str) -> Result<String, Box<dyn Error>> {
+    let mut buffer = Vec::new();
+    let mut serializer = serde_json::Serializer::with_formatter(
+        &mut buffer,
+        PrettyFormatter::with_indent(indent.as_bytes()),
+    );
+    value.serialize(&mut serializer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+/// Edit a JSON document as a YAML value, the value type shared by the `formats` modules.
+/// Key order is kept.
+fn edit_json(
+    contents: &str,
+    edit: impl FnOnce(&mut YamlValue) -> Result<usize, Box<dyn Error>>,
+) -> Result<(String, usize), Box<dyn Error>> {
+    let json: JsonValue = serde_json::from_str(contents)?;
+    let mut doc = serde_yaml::to_value(&json)?;
+    let count = edit(&mut doc)?;
+    let json = serde_json::to_value(&doc)?;
+    let mut updated = to_string_pretty(&json, &detect_indent(contents))?;
+    if contents.ends_with('\n') {
+        updated.push('\n');
+    }
+    Ok((updated, count))
+}
+
+/// Get all the values matching `path` (see [`yaml::parse_yaml_path`]) in a JSON file.
+pub fn get_json_values(file_path: &str, path: &str) -> Result<Vec<YamlValue>, Box<dyn Error>> {
+    let json: JsonValue = serde_json::from_str(&fs::read_to_string(file_path)?)?;
+    yaml::get_values(&serde_yaml::to_value(&json)?, path)
+}
+
+/// Get a JSON value from a file, e.g. `version` in a `package.json`.
+/// # Returns
+/// The value as a `String`. Numbers and booleans are converted to strings.
+pub fn get_json_value(file_path: &str, path: &str) -> Result<String, Box<dyn Error>> {
+    let values = get_json_values(file_path, path)?;
+    let value = values
+        .first()
+        .ok_or_else(|| format!("Key not found in JSON key path: {}", path))?;
+    scalar_to_string(value).ok_or_else(|| "The retrieved JSON value is not a scalar.".into())
+}
+
+/// Set the values matching `path` in a JSON file, creating the missing keys. The order of
+/// the keys and the indentation of the file are kept.
+/// # Returns
+/// The number of values set.
+pub fn set_json_value(
+    file_path: &str,
+    path: &str,
+    value: &YamlValue,
+) -> Result<usize, Box<dyn Error>> {
+    let contents = fs::read_to_string(file_path)?;
+    let (updated, count) = edit_json(&contents, |doc| yaml::set_value(doc, path, value))?;
+    plan::write(file_path, updated)?;
+    Ok(count)
+}
+
+/// Delete the values matching `path` in a JSON file.
+/// # Returns
+/// The number of values deleted.
+pub fn delete_json_value(file_path: &str, path: &str) -> Result<usize, Box<dyn Error>> {
+    let contents = fs::read_to_string(file_path)?;
+    let (updated, count) = edit_json(&contents, |doc| yaml::delete_value(doc, path))?;
+    if count > 0 {
+        plan::write(file_path, updated)?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_json() {
+        let contents = "{\n    \"name\": \"valis\",\n    \"version\": \"0.1.0\",\n    \"scripts\": {\n        \"build\": \"tsc\"\n    }\n}\n";
+        let (updated, count) = edit_json(contents, |doc| {
+            yaml::set_value(doc, "version", &YamlValue::from("0.2.0"))
+        })
+        .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(updated, contents.replace("0.1.0", "0.2.0"));
+
+        let (updated, _) = edit_json(contents, |doc| {
+            yaml::set_value(doc, "scripts.test", &YamlValue::from("jest"))?;
+            yaml::delete_value(doc, "name")
+        })
+        .unwrap();
+        assert_eq!(
+            updated,
+            "{\n    \"version\": \"0.1.0\",\n    \"scripts\": {\n        \"build\": \"tsc\",\n        \"test\": \"jest\"\n    }\n}\n"
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use regex::Regex;
+use rlua::{Context, Error, Table, Value};
+use serde_yaml::Value as YamlValue;
+
+use crate::modules::formats::text::{self, Position};
+use crate::modules::formats::yaml::{self, DocumentSelector};
+use crate::modules::formats::{file, json, toml};
+
+/// Convert a YAML value to a Lua value. Sequences become tables indexed from 1.
+pub fn yaml_to_lua<'lua>(ctx: Context<'lua>, value: &YamlValue) -> rlua::Result<Value<'lua>> {
+    Ok(match value {
+        YamlValue::Null => Value::Nil,
+        YamlValue::Bool(b) => Value::Boolean(*b),
+        YamlValue::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Number(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        YamlValue::String(s) => Value::String(ctx.create_string(s)?),
+        YamlValue::Sequence(seq) => {
+            let table = ctx.create_table()?;
+            for (index, item) in seq.iter().enumerate() {
+                table.set(index + 1, yaml_to_lua(ctx, item)?)?;
+            }
+            Value::Table(table)
+        }
+        YamlValue::Mapping(map) => {
+            let table = ctx.create_table()?;
+            for (key, item) in map.iter() {
+                table.set(yaml_to_lua(ctx, key)?, yaml_to_lua(ctx, item)?)?;
+            }
+            Value::Table(table)
+        }
+    })
+}
+
+/// Convert a Lua value to a YAML value. Tables with keys `1..n` become sequences,
+/// other tables (including empty ones) become mappings.
+pub fn lua_to_yaml(value: Value) -> rlua::Result<YamlValue> {
+    Ok(match value {
+        Value::Nil => YamlValue::Null,
+        Value::Boolean(b) => YamlValue::Bool(b),
+        Value::Integer(i) => YamlValue::from(i),
+        Value::Number(n) => YamlValue::from(n),
+        Value::String(s) => YamlValue::String(s.to_str()?.to_string()),
+        Value::Table(table) => table_to_yaml(table)?,
+        other => {
+            return Err(Error::RuntimeError(format!(
+                "Can't convert a Lua {} to YAML",
+                other.type_name()
+            )))
+        }
+    })
+}
+
+fn table_to_yaml(table: Table) -> rlua::Result<YamlValue> {
+    let len = table.raw_len();
+    let pairs = table
+        .clone()
+        .pairs::<Value, Value>()
+        .collect::<rlua::Result<Vec<(Value, Value)>>>()?;
+    if len > 0 && pairs.len() as i64 == len {
+        let mut seq = Vec::new();
+        for index in 1..=len {
+            seq.push(lua_to_yaml(table.raw_get(index)?)?);
+        }
+        return Ok(YamlValue::Sequence(seq));
+    }
+    let mut map = serde_yaml::Mapping::new();
+    for (key, value) in pairs {
+        map.insert(lua_to_yaml(key)?, lua_to_yaml(value)?);
+    }
+    Ok(YamlValue::Mapping(map))
+}
+
+/// `yaml_get_value(file, path)` returns the value at `path` as a string.
+pub fn yaml_get_value(ctx: &Context) {
+    let f = ctx
+        .create_function(|_, (file_path, path): (String, String)| {
+            yaml::get_yaml_value(&file_path, &path).map_err(|e| Error::external(e.to_string()))
+        })
+        .unwrap();
+    ctx.globals().set("yaml_get_value", f).unwrap();
+}
+
+/// The documents selected by the optional `document` argument: an index from 0, `*` for all
+/// the documents, or a matcher such as `kind=Deployment,metadata.name=api`. Defaults to the
+/// first document.
+fn document_selector(document: Option<String>) -> rlua::Result<DocumentSelector> {
+    match document {
+        Some(document) => document.parse().map_err(Error::RuntimeError),
+        None => Ok(DocumentSelector::Index(0)),
+    }
+}
+
+/// `yaml_get_values(file, path, [document])` returns a list of the typed values matching
+/// `path` in the selected documents.
+pub fn yaml_get_values(ctx: &Context) {
+    let f = ctx
+        .create_function(
+            |ctx, (file_path, path, document): (String, String, Option<String>)| {
+                let values =
+                    yaml::get_yaml_values_in(&file_path, &document_selector(document)?, &path)
+                        .map_err(|e| Error::external(e.to_string()))?;
+                yaml_to_lua(ctx, &YamlValue::Sequence(values))
+            },
+        )
+        .unwrap();
+    ctx.globals().set("yaml_get_values", f).unwrap();
+}
+
+/// `yaml_set_value(file, path, value, [document])` sets the values matching `path` in the
+/// selected documents, creating missing keys. Numbers, booleans, `nil` and tables keep their
+/// type. Only the targeted values are rewritten, so comments and formatting are kept.
+/// Returns the number of values set.
+pub fn yaml_set_value(ctx: &Context) {
+    let f = ctx
+        .create_function(
+            |_, (file_path, path, value, document): (String, String, Value, Option<String>)| {
+                yaml::set_yaml_value_in(
+                    &file_path,
+                    &document_selector(document)?,
+                    &path,
+                    &lua_to_yaml(value)?,
+                )
+                .map_err(|e| Error::external(e.to_string()))
+            },
+        )
+        .unwrap();
+    ctx.globals().set("yaml_set_value", f).unwrap();
+}
+
+/// `yaml_append_value(file, path, value, [document])` appends `value` to the sequences
+/// matching `path` in the selected documents. Returns the number of sequences appended to.
+pub fn yaml_append_value(ctx: &Context) {
+    let f = ctx
+        .create_function(
+            |_, (file_path, path, value, document): (String, String, Value, Option<String>)| {
+                yaml::append_yaml_value_in(
+                    &file_path,
+                    &document_selector(document)?,
+                    &path,
+                    &lua_to_yaml(value)?,
+                )
+                .map_err(|e| Error::external(e.to_string()))
+            },
+        )
+        .unwrap();
+    ctx.globals().set("yaml_append_value", f).unwrap();
+}
+
+/// `yaml_delete_value(file, path, [document])` deletes the values matching `path` in the
+/// selected documents. Returns the number of values deleted.
+pub fn yaml_delete_value(ctx: &Context) {
+    let f = ctx
+        .create_function(
+            |_, (file_path, path, document): (String, String, Option<String>)| {
+                yaml::delete_yaml_value_in(&file_path, &document_selector(document)?, &path)
+                    .map_err(|e| Error::external(e.to_string()))
+            },
+        )
+        .unwrap();
+    ctx.globals().set("yaml_delete_value", f).unwrap();
+}
+
+/// `yaml_documents(file)` returns the list of the documents of a YAML file, as
+/// `{index, kind, name, namespace}` tables.
+pub fn yaml_documents(ctx: &Context) {
+    let f = ctx
+        .create_function(|ctx, file_path: String| {
+            let documents = yaml::list_yaml_documents(&file_path)
+                .map_err(|e| Error::external(e.to_string()))?;
+            let list = ctx.create_table()?;
+            for (position, document) in documents.into_iter().enumerate() {
+                let table = ctx.create_table()?;
+                table.set("index", document.index)?;
+                table.set("kind", document.kind)?;
+                table.set("name", document.name)?;
+                table.set("namespace", document.namespace)?;
+                list.set(position + 1, table)?;
+            }
+            Ok(list)
+        })
+        .unwrap();
+    ctx.globals().set("yaml_documents", f).unwrap();
+}
+
+type GetValue = fn(&str, &str) -> Result<String, Box<dyn std::error::Error>>;
+type SetValue = fn(&str, &str, &YamlValue) -> Result<usize, Box<dyn std::error::Error>>;
+type DeleteValue = fn(&str, &str) -> Result<usize, Box<dyn std::error::Error>>;
+
+/// Register `name(file, path)`, returning the value at `path` as a string.
+fn register_get_value(ctx: &Context, name: &str, get: GetValue) {
+    let f = ctx
+        .create_function(move |_, (file_path, path): (String, String)| {
+            get(&file_path, &path).map_err(|e| Error::external(e.to_string()))
+        })
+        .unwrap();
+    ctx.globals().set(name, f).unwrap();
+}
+
+/// Register `name(file, path, value)`, setting the values matching `path` with their Lua
+/// type and returning the number of values set.
+fn register_set_value(ctx: &Context, name: &str, set: SetValue) {
+    let f = ctx
+        .create_function(
+            move |_, (file_path, path, value): (String, String, Value)| {
+                set(&file_path, &path, &lua_to_yaml(value)?)
+                    .map_err(|e| Error::external(e.to_string()))
+            },
+        )
+        .unwrap();
+    ctx.globals().set(name, f).unwrap();
+}
+
+/// Register `name(file, path)`, deleting the values matching `path` and returning the
+/// number of values deleted.
+fn register_delete_value(ctx: &Context, name: &str, delete: DeleteValue) {
+    let f = ctx
+        .create_function(move |_, (file_path, path): (String, String)| {
+            delete(&file_path, &path).map_err(|e| Error::external(e.to_string()))
+        })
+        .unwrap();
+    ctx.globals().set(name, f).unwrap();
+}
+
+/// `toml_get_value(file, path)`, `toml_set_value(file, path, value)` and
+/// `toml_delete_value(file, path)` edit TOML files, keeping their comments and formatting.
+pub fn toml_functions(ctx: &Context) {
+    register_get_value(ctx, "toml_get_value", toml::get_toml_value);
+    register_set_value(ctx, "toml_set_value", toml::set_toml_value);
+    register_delete_value(ctx, "toml_delete_value", toml::delete_toml_value);
+}
+
+/// `json_get_value(file, path)`, `json_set_value(file, path, value)` and
+/// `json_delete_value(file, path)` edit JSON files, keeping their key order and indentation.
+pub fn json_functions(ctx: &Context) {
+    register_get_value(ctx, "json_get_value", json::get_json_value);
+    register_set_value(ctx, "json_set_value", json::set_json_value);
+    register_delete_value(ctx, "json_delete_value", json::delete_json_value);
+}
+
+/// `file_get_value(file, path)`, `file_set_value(file, path, value)` and
+/// `file_delete_value(file, path)` edit YAML, TOML or JSON files, detected by extension.
+pub fn file_functions(ctx: &Context) {
+    register_get_value(ctx, "file_get_value", file::get_file_value);
+    register_set_value(ctx, "file_set_value", file::set_file_value);
+    register_delete_value(ctx, "file_delete_value", file::delete_file_value);
+}
+
+fn regex(pattern: &str) -> rlua::Result<Regex> {
+    Regex::new(pattern).map_err(|e| Error::external(e.to_string()))
+}
+
+/// Edit a text file, returning the number of lines changed and the diff of the edit.
+fn edit_text(
+    file_path: &str,
+    dry_run: Option<bool>,
+    edit: impl FnOnce(&str) -> (String, usize),
+) -> rlua::Result<(usize, String)> {
+    let edit = text::edit_text_file(file_path, dry_run.unwrap_or(false), edit)
+        .map_err(|e| Error::external(e.to_string()))?;
+    Ok((edit.changes, edit.diff()))
+}
+
+/// Text editing functions, which return the number of lines changed and a diff of the
+/// edit. With `dry_run`, the file is left untouched.
+/// - `replace_line(file, regex, replacement, [dry_run])` replaces the lines matching
+///   `regex`, expanding `$1` or `${name}` in `replacement`.
+/// - `insert_line(file, regex, line, ["after"|"before"], [dry_run])` inserts `line` after
+///   (or before) the last line matching `regex`.
+/// - `ensure_line(file, line, [options])` ensures `line` is present, as Ansible's
+///   `lineinfile`. Options: `regex`, `state` (`present` or `absent`), `after` or `before`
+///   (a regex where to insert the line) and `dry_run`.
+/// - `ensure_block(file, block, [options])` ensures a managed block contains `block`, or
+///   removes it if `block` is `nil`. Options: `marker` (containing `{mark}`) and `dry_run`.
+pub fn text_functions(ctx: &Context) {
+    let replace_line =
+        ctx
+            .create_function(
+                |_,
+                 (file_path, pattern, replacement, dry_run): (
+                    String,
+                    String,
+                    String,
+                    Option<bool>,
+                )| {
+                    let regex = regex(&pattern)?;
+                    edit_text(&file_path, dry_run, |contents| {
+                        text::replace_lines(contents, &regex, &replacement)
+                    })
+                },
+            )
+            .unwrap();
+    ctx.globals().set("replace_line", replace_line).unwrap();
+
+    let insert_line = ctx
+        .create_function(
+            |_,
+             (file_path, pattern, line, position, dry_run): (
+                String,
+                String,
+                String,
+                Option<String>,
+                Option<bool>,
+            )| {
+                let regex = regex(&pattern)?;
+                let position = match position.as_deref() {
+                    None | Some("after") => Position::After,
+                    Some("before") => Position::Before,
+                    Some(other) => {
+                        return Err(Error::RuntimeError(format!("Invalid position: {}", other)))
+                    }
+                };
+                edit_text(&file_path, dry_run, |contents| {
+                    text::insert_line(contents, &regex, &line, position)
+                })
+            },
+        )
+        .unwrap();
+    ctx.globals().set("insert_line", insert_line).unwrap();
+
+    let ensure_line = ctx
+        .create_function(
+            |_, (file_path, line, options): (String, String, Option<Table>)| {
+                let option = |name: &str| -> rlua::Result<Option<String>> {
+                    match &options {
+                        Some(options) => options.get(name),
+                        None => Ok(None),
+                    }
+                };
+                let line_regex = option("regex")?.map(|p| regex(&p)).transpose()?;
+                let insert = match (option("after")?, option("before")?) {
+                    (Some(after), _) => Some((Position::After, regex(&after)?)),
+                    (None, Some(before)) => Some((Position::Before, regex(&before)?)),
+                    (None, None) => None,
+                };
+                let dry_run = match &options {
+                    Some(options) => options.get("dry_run")?,
+                    None => None,
+                };
+                match option("state")?.as_deref() {
+                    None | Some("present") => edit_text(&file_path, dry_run, |contents| {
+                        text::ensure_line_present(
+                            contents,
+                            &line,
+                            line_regex.as_ref(),
+                            insert.as_ref().map(|(position, regex)| (*position, regex)),
+                        )
+                    }),
+                    Some("absent") => {
+                        let absent = match line_regex {
+                            Some(regex) => regex,
+                            None => regex(&format!("^{}$", regex::escape(&line)))?,
+                        };
+                        edit_text(&file_path, dry_run, |contents| {
+                            text::ensure_line_absent(contents, &absent)
+                        })
+                    }
+                    Some(other) => Err(Error::RuntimeError(format!("Invalid state: {}", other))),
+                }
+            },
+        )
+        .unwrap();
+    ctx.globals().set("ensure_line", ensure_line).unwrap();
+
+    let ensure_block = ctx
+        .create_function(
+            |_, (file_path, block, options): (String, Option<String>, Option<Table>)| {
+                let (marker, dry_run) = match &options {
+                    Some(options) => (options.get("marker")?, options.get("dry_run")?),
+                    None => (None, None),
+                };
+                let marker: String =
+                    marker.unwrap_or_else(|| text::MANAGED_BLOCK_MARKER.to_string());
+                edit_text(&file_path, dry_run, |contents| {
+                    text::ensure_block(contents, &marker, block.as_deref())
+                })
+            },
+        )
+        .unwrap();
+    ctx.globals().set("ensure_block", ensure_block).unwrap();
+}
@@ -1,2 +1,7 @@
+pub mod file;
+pub mod json;
+pub mod lua;
 pub mod text;
+pub mod toml;
 pub mod yaml;
+pub mod yaml_patch;
@@ -1,33 +1,443 @@
-use std::fs::{self, File};
-use std::io::{self, BufRead, BufReader, Write};
-use std::path::Path;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
+use regex::Regex;
+
+use crate::modules::plan;
+
+/// The default markers of a managed block, `{mark}` being `BEGIN` or `END`.
+pub const MANAGED_BLOCK_MARKER: &str = "# {mark} VALIS MANAGED BLOCK";
+
+/// Where to insert a line, relative to the last line matching a regex.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Position {
+    Before,
+    After,
+}
+
+/// An edit of a text file: its contents before and after, and the number of lines changed.
+#[derive(Debug, Clone)]
+pub struct TextEdit {
+    pub path: PathBuf,
+    pub before: String,
+    pub after: String,
+    pub changes: usize,
+}
+
+/// The lines of `contents`, and whether it ends with a newline.
+fn split_lines(contents: &str) -> (Vec<String>, bool) {
+    let trailing_newline = contents.ends_with('\n');
+    let contents = contents.strip_suffix('\n').unwrap_or(contents);
+    if contents.is_empty() && !trailing_newline {
+        return (vec![], false);
+    }
+    (
+        contents.split('\n').map(|line| line.to_string()).collect(),
+        trailing_newline,
+    )
+}
+
+/// Join `lines`, ending with a newline if `trailing_newline` is set.
+fn join_lines(lines: &[String], trailing_newline: bool) -> String {
+    let mut contents = lines.join("\n");
+    if !lines.is_empty() && trailing_newline {
+        contents.push('\n');
+    }
+    contents
+}
+
+/// The hunks of a line diff from `before` to `after`, based on their longest common
+/// subsequence of lines.
+fn diff_lines(before: &[String], after: &[String]) -> String {
+    let (n, m) = (before.len(), after.len());
+    let mut common = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            common[i][j] = if before[i] == after[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && before[i] == after[j] {
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let (start_i, start_j) = (i, j);
+        let mut hunk = String::new();
+        while (i < n || j < m) && !(i < n && j < m && before[i] == after[j]) {
+            if j == m || (i < n && common[i + 1][j] >= common[i][j + 1]) {
+                hunk.push_str(&format!("-{}\n", before[i]));
+                i += 1;
+            } else {
+                hunk.push_str(&format!("+{}\n", after[j]));
+                j += 1;
+            }
+        }
+        diff.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n{}",
+            start_i + 1,
+            i - start_i,
+            start_j + 1,
+            j - start_j,
+            hunk
+        ));
+    }
+    diff
+}
+
+impl TextEdit {
+    /// Whether the edit changes the file.
+    pub fn is_changed(&self) -> bool {
+        self.before != self.after
+    }
+
+    /// A unified diff of the edit, for dry runs. Empty if the file is unchanged.
+    pub fn diff(&self) -> String {
+        if !self.is_changed() {
+            return "".to_string();
+        }
+        format!(
+            "--- {}\n+++ {}\n{}",
+            self.path.display(),
+            self.path.display(),
+            diff_lines(&split_lines(&self.before).0, &split_lines(&self.after).0)
+        )
+    }
+
+    /// Write the edited file, if changed.
+    pub fn apply(&self) -> io::Result<()> {
+        if self.is_changed() {
+            write_atomically(&self.path, &self.after)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write `contents` to `path` atomically: through a temporary file in the same directory,
+/// renamed over `path`. The permissions of the existing file are kept. In dry-run mode, the
+/// write is only recorded.
+pub fn write_atomically<C: AsRef<[u8]>>(path: &Path, contents: C) -> io::Result<()> {
+    let contents = contents.as_ref();
+    if plan::is_dry_run() {
+        plan::record(plan::Action::Write {
+            path: path.to_path_buf(),
+            bytes: contents.len(),
+        });
+        return Ok(());
+    }
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(contents)?;
+    file.as_file().sync_all()?;
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(file.path(), metadata.permissions())?;
+    }
+    file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// Replace the lines of `contents` matching `regex` with `replacement`, in which `$1` or
+/// `${name}` are expanded to the groups captured by `regex`.
+/// Returns the new contents and the number of lines changed.
+pub fn replace_lines(contents: &str, regex: &Regex, replacement: &str) -> (String, usize) {
+    let (mut lines, trailing_newline) = split_lines(contents);
+    let mut changes = 0;
+    for line in lines.iter_mut() {
+        if let Some(captures) = regex.captures(line) {
+            let mut replaced = String::new();
+            captures.expand(replacement, &mut replaced);
+            if replaced != *line {
+                *line = replaced;
+                changes += 1;
+            }
+        }
+    }
+    (join_lines(&lines, trailing_newline), changes)
+}
+
+/// Insert `line` before or after the last line of `contents` matching `regex`.
+/// Nothing is inserted if no line matches, or if `line` is already there.
+/// Returns the new contents and the number of lines changed.
+pub fn insert_line(
+    contents: &str,
+    regex: &Regex,
+    line: &str,
+    position: Position,
+) -> (String, usize) {
+    let (mut lines, trailing_newline) = split_lines(contents);
+    let anchor = match lines.iter().rposition(|l| regex.is_match(l)) {
+        Some(anchor) => anchor,
+        None => return (contents.to_string(), 0),
+    };
+    let index = match position {
+        Position::Before => anchor,
+        Position::After => anchor + 1,
+    };
+    let neighbour = match position {
+        Position::Before => index.checked_sub(1).and_then(|i| lines.get(i)),
+        Position::After => lines.get(index),
+    };
+    if neighbour.map(|l| l == line).unwrap_or(false) {
+        return (contents.to_string(), 0);
+    }
+    lines.insert(index, line.to_string());
+    (join_lines(&lines, trailing_newline), 1)
+}
+
+/// Ensure `line` is in `contents`, as Ansible's `lineinfile`: the last line matching
+/// `regex` (or equal to `line` without a regex) is replaced with `line`. Otherwise, `line`
+/// is inserted relative to the last line matching `insert`, or at the end.
+/// Returns the new contents and the number of lines changed.
+pub fn ensure_line_present(
+    contents: &str,
+    line: &str,
+    regex: Option<&Regex>,
+    insert: Option<(Position, &Regex)>,
+) -> (String, usize) {
+    let (mut lines, trailing_newline) = split_lines(contents);
+    let found = match regex {
+        Some(regex) => lines.iter().rposition(|l| regex.is_match(l)),
+        None => lines.iter().rposition(|l| l == line),
+    };
+    if let Some(index) = found {
+        if lines[index] == line {
+            return (contents.to_string(), 0);
+        }
+        lines[index] = line.to_string();
+        return (join_lines(&lines, trailing_newline), 1);
+    }
+    if let Some((position, anchor)) = insert {
+        let (inserted, changes) = insert_line(contents, anchor, line, position);
+        if changes > 0 {
+            return (inserted, changes);
+        }
+    }
+    lines.push(line.to_string());
+    (join_lines(&lines, true), 1)
+}
+
+/// Remove the lines of `contents` matching `regex`.
+/// Returns the new contents and the number of lines removed.
+pub fn ensure_line_absent(contents: &str, regex: &Regex) -> (String, usize) {
+    let (mut lines, trailing_newline) = split_lines(contents);
+    let count = lines.len();
+    lines.retain(|line| !regex.is_match(line));
+    let changes = count - lines.len();
+    if changes == 0 {
+        return (contents.to_string(), 0);
+    }
+    (join_lines(&lines, trailing_newline), changes)
+}
+
+/// The begin and end markers of a managed block, from a `marker` template containing
+/// `{mark}` (see [`MANAGED_BLOCK_MARKER`]).
+fn block_markers(marker: &str) -> (String, String) {
+    (
+        marker.replace("{mark}", "BEGIN"),
+        marker.replace("{mark}", "END"),
+    )
+}
+
+/// Ensure the managed block delimited by `marker` (see [`MANAGED_BLOCK_MARKER`]) contains
+/// `block`, as Ansible's `blockinfile`: the block is replaced if present, appended
+/// otherwise, and removed if `block` is `None`.
+/// Returns the new contents and the number of lines changed.
+pub fn ensure_block(contents: &str, marker: &str, block: Option<&str>) -> (String, usize) {
+    let (begin, end) = block_markers(marker);
+    let (mut lines, trailing_newline) = split_lines(contents);
+    let start = lines.iter().position(|line| line.trim_end() == begin);
+    let stop = start.and_then(|start| {
+        lines[start..]
+            .iter()
+            .position(|line| line.trim_end() == end)
+            .map(|stop| start + stop)
+    });
+    let mut new_lines = vec![];
+    if let Some(block) = block {
+        new_lines.push(begin);
+        new_lines.extend(split_lines(block).0);
+        new_lines.push(end);
+    }
+    let (range, trailing_newline) = match (start, stop) {
+        (Some(start), Some(stop)) => (start..stop + 1, trailing_newline),
+        _ if block.is_none() => return (contents.to_string(), 0),
+        _ => (lines.len()..lines.len(), true),
+    };
+    let old_lines = lines
+        .splice(range, new_lines.clone())
+        .collect::<Vec<String>>();
+    if old_lines == new_lines {
+        return (contents.to_string(), 0);
+    }
+    let changes = diff_lines(&old_lines, &new_lines)
+        .lines()
+        .filter(|line| !line.starts_with("@@"))
+        .count();
+    (join_lines(&lines, trailing_newline), changes)
+}
+
+/// Edit the text file `file_path` with `edit`, and write it unless `dry_run` is set.
+pub fn edit_text_file<P: AsRef<Path>>(
+    file_path: P,
+    dry_run: bool,
+    edit: impl FnOnce(&str) -> (String, usize),
+) -> io::Result<TextEdit> {
+    let path = file_path.as_ref().to_path_buf();
+    let before = fs::read_to_string(&path)?;
+    let (after, changes) = edit(&before);
+    let text_edit = TextEdit {
+        path,
+        before,
+        after,
+        changes,
+    };
+    if !dry_run {
+        text_edit.apply()?;
+    }
+    Ok(text_edit)
+}
+
+/// Replace the lines matching the regex `pattern` in a file with `replacement`, in which
+/// capture groups (`$1`, `${name}`) are expanded. Returns the number of lines changed.
 pub fn replace_matching_line<P: AsRef<Path>>(
     file_path: P,
     pattern: &str,
     replacement: &str,
-) -> io::Result<()> {
-    let file_path = file_path.as_ref();
-    let temp_file_path = file_path.with_extension("temp");
+) -> Result<usize, Box<dyn Error>> {
+    let regex = Regex::new(pattern)?;
+    let edit = edit_text_file(file_path, false, |contents| {
+        replace_lines(contents, &regex, replacement)
+    })?;
+    Ok(edit.changes)
+}
 
-    let input_file = File::open(file_path)?;
-    let reader = BufReader::new(input_file);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let mut output_file = File::create(&temp_file_path)?;
+    const HOSTS: &str = "127.0.0.1 localhost\n# cluster\n10.0.0.1 node1\n10.0.0.2 node2\n";
 
-    for line in reader.lines() {
-        let line = line?;
-        let stripped_line = line.trim();
+    #[test]
+    fn test_replace_and_insert_lines() {
+        let regex = Regex::new(r"^10\.0\.0\.(\d+) (node\d+)$").unwrap();
+        assert_eq!(
+            replace_lines(HOSTS, &regex, "192.168.0.$1 $2"),
+            (
+                "127.0.0.1 localhost\n# cluster\n192.168.0.1 node1\n192.168.0.2 node2\n"
+                    .to_string(),
+                2
+            )
+        );
+        let node = Regex::new("node").unwrap();
+        assert_eq!(
+            insert_line(HOSTS, &node, "10.0.0.3 node3", Position::After).0,
+            format!("{}10.0.0.3 node3\n", HOSTS)
+        );
+        assert_eq!(
+            insert_line(
+                HOSTS,
+                &Regex::new("^#").unwrap(),
+                "# nodes",
+                Position::Before
+            ),
+            (HOSTS.replace("# cluster", "# nodes\n# cluster"), 1)
+        );
+        assert_eq!(
+            insert_line(
+                HOSTS,
+                &Regex::new("node1").unwrap(),
+                "10.0.0.2 node2",
+                Position::After
+            )
+            .1,
+            0
+        );
+    }
 
-        if stripped_line == pattern {
-            writeln!(output_file, "{}", replacement)?;
-        } else {
-            writeln!(output_file, "{}", line)?;
-        }
+    #[test]
+    fn test_ensure_line() {
+        let regex = Regex::new("node2$").unwrap();
+        assert_eq!(
+            ensure_line_present(HOSTS, "10.0.0.9 node2", Some(&regex), None),
+            (HOSTS.replace("10.0.0.2", "10.0.0.9"), 1)
+        );
+        assert_eq!(
+            ensure_line_present(HOSTS, "10.0.0.2 node2", None, None),
+            (HOSTS.to_string(), 0)
+        );
+        let cluster = Regex::new("^# cluster").unwrap();
+        assert_eq!(
+            ensure_line_present(
+                HOSTS,
+                "10.0.0.0 gw",
+                None,
+                Some((Position::After, &cluster))
+            )
+            .0,
+            HOSTS.replace("# cluster\n", "# cluster\n10.0.0.0 gw\n")
+        );
+        assert_eq!(
+            ensure_line_present("a", "b", None, None),
+            ("a\nb\n".to_string(), 1)
+        );
+        assert_eq!(
+            ensure_line_absent(HOSTS, &Regex::new("^10\\.").unwrap()),
+            ("127.0.0.1 localhost\n# cluster\n".to_string(), 2)
+        );
     }
 
-    fs::remove_file(file_path)?;
-    fs::rename(temp_file_path, file_path)?;
+    #[test]
+    fn test_ensure_block() {
+        let (added, changes) = ensure_block(HOSTS, MANAGED_BLOCK_MARKER, Some("10.0.1.1 db\n"));
+        assert_eq!(changes, 3);
+        assert_eq!(
+            added,
+            format!(
+                "{}# BEGIN VALIS MANAGED BLOCK\n10.0.1.1 db\n# END VALIS MANAGED BLOCK\n",
+                HOSTS
+            )
+        );
+        assert_eq!(
+            ensure_block(&added, MANAGED_BLOCK_MARKER, Some("10.0.1.1 db")),
+            (added.clone(), 0)
+        );
+        let (updated, changes) = ensure_block(&added, MANAGED_BLOCK_MARKER, Some("10.0.1.2 db"));
+        assert_eq!(changes, 2);
+        assert_eq!(updated, added.replace("10.0.1.1", "10.0.1.2"));
+        assert_eq!(
+            ensure_block(&updated, MANAGED_BLOCK_MARKER, None),
+            (HOSTS.to_string(), 3)
+        );
+    }
 
-    Ok(())
+    #[test]
+    fn test_edit_text_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hosts");
+        fs::write(&path, HOSTS).unwrap();
+        let regex = Regex::new("node1").unwrap();
+        let edit =
+            edit_text_file(&path, true, |contents| ensure_line_absent(contents, &regex)).unwrap();
+        assert_eq!(edit.changes, 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), HOSTS);
+        assert!(edit.diff().ends_with("@@ -3,1 +3,0 @@\n-10.0.0.1 node1\n"));
+        edit.apply().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), edit.after);
+        assert_eq!(
+            replace_matching_line(&path, "^127", "::1 localhost").unwrap(),
+            1
+        );
+    }
 }
@@ -0,0 +1,444 @@
+use std::error::Error;
+use std::fs;
+
+use serde_yaml::Value as YamlValue;
+use toml_edit::{Array, Document, InlineTable, Item, Table, Value};
+
+use crate::modules::formats::yaml::{parse_yaml_path, scalar_to_string, PathSegment};
+use crate::modules::plan;
+
+/// Convert a TOML value to a YAML value, the value type shared by the `formats` modules.
+/// Datetimes become strings.
+fn value_to_yaml(value: &Value) -> YamlValue {
+    match value {
+        Value::String(s) => YamlValue::from(s.value().as_str()),
+        Value::Integer(i) => YamlValue::from(*i.value()),
+        Value::Float(f) => YamlValue::from(*f.value()),
+        Value::Boolean(b) => YamlValue::from(*b.value()),
+        Value::Datetime(d) => YamlValue::from(d.value().to_string()),
+        Value::Array(array) => YamlValue::Sequence(array.iter().map(value_to_yaml).collect()),
+        Value::InlineTable(table) => {
+            let mut map = serde_yaml::Mapping::new();
+            for (key, value) in table.iter() {
+                map.insert(YamlValue::from(key), value_to_yaml(value));
+            }
+            YamlValue::Mapping(map)
+        }
+    }
+}
+
+/// Convert a TOML item to a YAML value.
+pub fn toml_to_yaml(item: &Item) -> YamlValue {
+    match item {
+        Item::None => YamlValue::Null,
+        Item::Value(value) => value_to_yaml(value),
+        Item::Table(table) => {
+            let mut map = serde_yaml::Mapping::new();
+            for (key, item) in table.iter() {
+                map.insert(YamlValue::from(key), toml_to_yaml(item));
+            }
+            YamlValue::Mapping(map)
+        }
+        Item::ArrayOfTables(tables) => YamlValue::Sequence(
+            tables
+                .iter()
+                .map(|table| toml_to_yaml(&Item::Table(table.clone())))
+                .collect(),
+        ),
+    }
+}
+
+/// Convert a YAML value to a TOML value. Mappings become inline tables. TOML has no null.
+pub fn yaml_to_toml(value: &YamlValue) -> Result<Value, Box<dyn Error>> {
+    Ok(match value {
+        YamlValue::Null => return Err("TOML has no null value".into()),
+        YamlValue::Bool(b) => Value::from(*b),
+        YamlValue::Number(n) => match n.as_i64() {
+            Some(i) => Value::from(i),
+            None => Value::from(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        YamlValue::String(s) => Value::from(s.as_str()),
+        YamlValue::Sequence(seq) => {
+            let mut array = Array::new();
+            for item in seq {
+                array.push(yaml_to_toml(item)?);
+            }
+            Value::Array(array)
+        }
+        YamlValue::Mapping(map) => {
+            let mut table = InlineTable::new();
+            for (key, item) in map {
+                let key = scalar_to_string(key).ok_or("TOML keys must be strings")?;
+                table.insert(&key, yaml_to_toml(item)?);
+            }
+            Value::InlineTable(table)
+        }
+    })
+}
+
+/// Convert an item to a value, so that it can be stored in an array.
+fn item_into_value(item: Item) -> Result<Value, Box<dyn Error>> {
+    match item {
+        Item::Value(value) => Ok(value),
+        Item::Table(table) => Ok(Value::InlineTable(table.into_inline_table())),
+        Item::ArrayOfTables(tables) => Ok(Value::Array(tables.into_array())),
+        Item::None => Err("Missing TOML value".into()),
+    }
+}
+
+/// Replace `item` with `value`, keeping its decoration (whitespace and comments), and its
+/// style for tables.
+fn replace_item(item: &mut Item, value: &YamlValue) -> Result<(), Box<dyn Error>> {
+    match (&mut *item, yaml_to_toml(value)?) {
+        (Item::Table(old), Value::InlineTable(table)) => {
+            let mut table = table.into_table();
+            *table.decor_mut() = old.decor().clone();
+            *old = table;
+        }
+        (Item::Value(old), mut value) => {
+            *value.decor_mut() = old.decor().clone();
+            *old = value;
+        }
+        (_, value) => *item = Item::Value(value),
+    }
+    Ok(())
+}
+
+/// A function applied to the items matching a path.
+type Visitor<'a> = dyn FnMut(&mut Item) -> Result<(), Box<dyn Error>> + 'a;
+
+/// Visit the array element `index` of `array` as an item.
+fn visit_array_element(
+    array: &mut Array,
+    index: usize,
+    segments: &[PathSegment],
+    create: bool,
+    f: &mut Visitor,
+) -> Result<usize, Box<dyn Error>> {
+    let element = array.get_mut(index).ok_or("Index out of bounds")?;
+    let mut item = Item::Value(std::mem::replace(element, Value::from(false)));
+    let count = visit_mut(&mut item, segments, create, f);
+    *array.get_mut(index).unwrap() = item_into_value(item)?;
+    count
+}
+
+/// Visit the table `index` of an array of tables as an item.
+fn visit_array_table(
+    tables: &mut toml_edit::ArrayOfTables,
+    index: usize,
+    segments: &[PathSegment],
+    create: bool,
+    f: &mut Visitor,
+) -> Result<usize, Box<dyn Error>> {
+    let table = tables.get_mut(index).ok_or("Index out of bounds")?;
+    let mut item = Item::Table(std::mem::take(table));
+    let count = visit_mut(&mut item, segments, create, f);
+    *tables.get_mut(index).unwrap() = item
+        .into_table()
+        .map_err(|_| "An array of tables can only contain tables")?;
+    count
+}
+
+/// Visit the items of `item` matching `segments` with `f`, creating the missing keys,
+/// tables and arrays if `create` is set. Returns the number of items visited.
+fn visit_mut(
+    item: &mut Item,
+    segments: &[PathSegment],
+    create: bool,
+    f: &mut Visitor,
+) -> Result<usize, Box<dyn Error>> {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => {
+            f(item)?;
+            return Ok(1);
+        }
+    };
+    let is_table = item.is_table();
+    match (segment, item) {
+        (PathSegment::Key(key), item) if item.is_table_like() => {
+            let table = item.as_table_like_mut().unwrap();
+            if !table.contains_key(key) {
+                if !create {
+                    return Ok(0);
+                }
+                let child = match rest.first() {
+                    None => Item::Value(Value::from(false)),
+                    Some(PathSegment::Key(_)) if is_table => {
+                        let mut table = Table::new();
+                        table.set_implicit(true);
+                        Item::Table(table)
+                    }
+                    Some(PathSegment::Key(_)) => {
+                        Item::Value(Value::InlineTable(InlineTable::new()))
+                    }
+                    Some(_) => Item::Value(Value::Array(Array::new())),
+                };
+                table.insert(key, child);
+            }
+            visit_mut(table.get_mut(key).unwrap(), rest, create, f)
+        }
+        (PathSegment::Wildcard, item) if item.is_table_like() => {
+            let mut count = 0;
+            for (_, child) in item.as_table_like_mut().unwrap().iter_mut() {
+                count += visit_mut(child, rest, create, f)?;
+            }
+            Ok(count)
+        }
+        (PathSegment::Index(index), Item::Value(Value::Array(array))) => {
+            if *index == array.len() && create {
+                array.push(false);
+            } else if *index >= array.len() {
+                return match create {
+                    true => Err(format!("Index {} out of bounds", index).into()),
+                    false => Ok(0),
+                };
+            }
+            visit_array_element(array, *index, rest, create, f)
+        }
+        (PathSegment::Append, Item::Value(Value::Array(array))) if create => {
+            array.push(false);
+            visit_array_element(array, array.len() - 1, rest, create, f)
+        }
+        (PathSegment::Wildcard, Item::Value(Value::Array(array))) => {
+            let mut count = 0;
+            for index in 0..array.len() {
+                count += visit_array_element(array, index, rest, create, f)?;
+            }
+            Ok(count)
+        }
+        (PathSegment::Index(index), Item::ArrayOfTables(tables)) => {
+            if *index == tables.len() && create {
+                tables.push(Table::new());
+            } else if *index >= tables.len() {
+                return match create {
+                    true => Err(format!("Index {} out of bounds", index).into()),
+                    false => Ok(0),
+                };
+            }
+            visit_array_table(tables, *index, rest, create, f)
+        }
+        (PathSegment::Append, Item::ArrayOfTables(tables)) if create => {
+            tables.push(Table::new());
+            visit_array_table(tables, tables.len() - 1, rest, create, f)
+        }
+        (PathSegment::Wildcard, Item::ArrayOfTables(tables)) => {
+            let mut count = 0;
+            for index in 0..tables.len() {
+                count += visit_array_table(tables, index, rest, create, f)?;
+            }
+            Ok(count)
+        }
+        (segment, item) if create => {
+            Err(format!("Can't create {:?} in a TOML {}", segment, item.type_name()).into())
+        }
+        _ => Ok(0),
+    }
+}
+
+/// Collect the items of `item` matching `segments`.
+fn collect(item: &Item, segments: &[PathSegment], values: &mut Vec<YamlValue>) {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => {
+            values.push(toml_to_yaml(item));
+            return;
+        }
+    };
+    match (segment, item) {
+        (PathSegment::Key(key), item) if item.is_table_like() => {
+            if let Some(child) = item.as_table_like().unwrap().get(key) {
+                collect(child, rest, values);
+            }
+        }
+        (PathSegment::Wildcard, item) if item.is_table_like() => {
+            for (_, child) in item.as_table_like().unwrap().iter() {
+                collect(child, rest, values);
+            }
+        }
+        (PathSegment::Index(index), Item::Value(Value::Array(array))) => {
+            if let Some(child) = array.get(*index) {
+                collect(&Item::Value(child.clone()), rest, values);
+            }
+        }
+        (PathSegment::Wildcard, Item::Value(Value::Array(array))) => {
+            for child in array.iter() {
+                collect(&Item::Value(child.clone()), rest, values);
+            }
+        }
+        (PathSegment::Index(index), Item::ArrayOfTables(tables)) => {
+            if let Some(child) = tables.get(*index) {
+                collect(&Item::Table(child.clone()), rest, values);
+            }
+        }
+        (PathSegment::Wildcard, Item::ArrayOfTables(tables)) => {
+            for child in tables.iter() {
+                collect(&Item::Table(child.clone()), rest, values);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Get all the values of `doc` matching `path` (see [`parse_yaml_path`]).
+pub fn get_values(doc: &Document, path: &str) -> Result<Vec<YamlValue>, Box<dyn Error>> {
+    let mut values = Vec::new();
+    collect(doc.as_item(), &parse_yaml_path(path)?, &mut values);
+    Ok(values)
+}
+
+/// Set all the values of `doc` matching `path` to `value`, creating the missing keys.
+/// The formatting and comments of the document are kept. Returns the number of values set.
+pub fn set_value(
+    doc: &mut Document,
+    path: &str,
+    value: &YamlValue,
+) -> Result<usize, Box<dyn Error>> {
+    visit_mut(
+        doc.as_item_mut(),
+        &parse_yaml_path(path)?,
+        true,
+        &mut |item| replace_item(item, value),
+    )
+}
+
+/// Delete all the values of `doc` matching `path`. Returns the number of values deleted.
+pub fn delete_value(doc: &mut Document, path: &str) -> Result<usize, Box<dyn Error>> {
+    let mut segments = parse_yaml_path(path)?;
+    let last = segments
+        .pop()
+        .ok_or_else(|| format!("Invalid TOML path: {}", path))?;
+    let mut deleted = 0;
+    visit_mut(doc.as_item_mut(), &segments, false, &mut |parent| {
+        deleted += match (&last, parent) {
+            (PathSegment::Key(key), parent) if parent.is_table_like() => parent
+                .as_table_like_mut()
+                .unwrap()
+                .remove(key)
+                .map_or(0, |_| 1),
+            (PathSegment::Wildcard, parent) if parent.is_table_like() => {
+                let table = parent.as_table_like_mut().unwrap();
+                let count = table.len();
+                table.clear();
+                count
+            }
+            (PathSegment::Index(index), Item::Value(Value::Array(array)))
+                if *index < array.len() =>
+            {
+                array.remove(*index);
+                1
+            }
+            (PathSegment::Index(index), Item::ArrayOfTables(tables)) if *index < tables.len() => {
+                tables.remove(*index);
+                1
+            }
+            _ => 0,
+        };
+        Ok(())
+    })?;
+    Ok(deleted)
+}
+
+/// Read and parse a TOML file.
+fn read_toml_file(file_path: &str) -> Result<Document, Box<dyn Error>> {
+    Ok(fs::read_to_string(file_path)?.parse::<Document>()?)
+}
+
+/// Get all the values matching `path` in a TOML file.
+pub fn get_toml_values(file_path: &str, path: &str) -> Result<Vec<YamlValue>, Box<dyn Error>> {
+    get_values(&read_toml_file(file_path)?, path)
+}
+
+/// Get a TOML value from a file, e.g. `package.version` in a `Cargo.toml`.
+/// # Returns
+/// The value as a `String`. Numbers and booleans are converted to strings.
+pub fn get_toml_value(file_path: &str, path: &str) -> Result<String, Box<dyn Error>> {
+    let values = get_toml_values(file_path, path)?;
+    let value = values
+        .first()
+        .ok_or_else(|| format!("Key not found in TOML key path: {}", path))?;
+    scalar_to_string(value).ok_or_else(|| "The retrieved TOML value is not a scalar.".into())
+}
+
+/// Set the values matching `path` in a TOML file, creating the missing keys. The rest of
+/// the file is left untouched, comments and formatting included.
+/// # Returns
+/// The number of values set.
+pub fn set_toml_value(
+    file_path: &str,
+    path: &str,
+    value: &YamlValue,
+) -> Result<usize, Box<dyn Error>> {
+    let mut doc = read_toml_file(file_path)?;
+    let count = set_value(&mut doc, path, value)?;
+    plan::write(file_path, doc.to_string())?;
+    Ok(count)
+}
+
+/// Delete the values matching `path` in a TOML file.
+/// # Returns
+/// The number of values deleted.
+pub fn delete_toml_value(file_path: &str, path: &str) -> Result<usize, Box<dyn Error>> {
+    let mut doc = read_toml_file(file_path)?;
+    let count = delete_value(&mut doc, path)?;
+    if count > 0 {
+        plan::write(file_path, doc.to_string())?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CARGO: &str = "[package]\nname = \"valis\" # the crate\nversion = \"0.1.0\"\n\n[dependencies]\nserde = { version = \"1.0\", features = [\"derive\"] }\n\n[[bin]]\nname = \"valis_cli\"\n";
+
+    #[test]
+    fn test_get_and_set_values() {
+        let mut doc = CARGO.parse::<Document>().unwrap();
+        assert_eq!(
+            get_values(&doc, "dependencies.serde.features[0]").unwrap(),
+            vec![YamlValue::from("derive")]
+        );
+        assert_eq!(
+            get_values(&doc, "bin[*].name").unwrap(),
+            vec![YamlValue::from("valis_cli")]
+        );
+        set_value(&mut doc, "package.version", &YamlValue::from("0.2.0")).unwrap();
+        set_value(
+            &mut doc,
+            "dependencies.serde.version",
+            &YamlValue::from("1.1"),
+        )
+        .unwrap();
+        set_value(&mut doc, "package.edition", &YamlValue::from("2021")).unwrap();
+        set_value(
+            &mut doc,
+            "dependencies.serde.features[-]",
+            &YamlValue::from("rc"),
+        )
+        .unwrap();
+        assert_eq!(
+            doc.to_string(),
+            "[package]\nname = \"valis\" # the crate\nversion = \"0.2.0\"\nedition = \"2021\"\n\n[dependencies]\nserde = { version = \"1.1\", features = [\"derive\", \"rc\"] }\n\n[[bin]]\nname = \"valis_cli\"\n"
+        );
+        assert!(set_value(&mut doc, "package.name.x", &YamlValue::from(1)).is_err());
+        assert!(set_value(&mut doc, "package.name", &YamlValue::Null).is_err());
+    }
+
+    #[test]
+    fn test_delete_values() {
+        let mut doc = CARGO.parse::<Document>().unwrap();
+        assert_eq!(delete_value(&mut doc, "package.version").unwrap(), 1);
+        assert_eq!(
+            delete_value(&mut doc, "dependencies.serde.features[0]").unwrap(),
+            1
+        );
+        assert_eq!(delete_value(&mut doc, "missing.key").unwrap(), 0);
+        assert_eq!(delete_value(&mut doc, "bin[0]").unwrap(), 1);
+        assert_eq!(
+            doc.to_string(),
+            "[package]\nname = \"valis\" # the crate\n\n[dependencies]\nserde = { version = \"1.0\", features = [] }\n"
+        );
+    }
+}
@@ -1,108 +1,710 @@
 use std::error::Error;
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
 
+use serde::Deserialize;
 use serde_yaml::Value as YamlValue;
 
-fn update_yaml_value_in_place(
+use crate::modules::formats::yaml_patch::PatchableYaml;
+use crate::modules::plan;
+
+/// A segment of a YAML path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+    /// A mapping key (`metadata`).
+    Key(String),
+    /// A sequence index (`[0]`).
+    Index(usize),
+    /// All the values of a mapping (`*`) or sequence (`[*]`).
+    Wildcard,
+    /// A new element at the end of a sequence (`[-]`).
+    Append,
+}
+
+/// Parse a YAML path, such as `spec.containers[0].image`, `spec.containers[*].name`,
+/// `data.*` or `spec.args[-]`. Keys are separated by `.` and followed by any number of
+/// `[index]`, `[*]` (all elements) or `[-]` (append) selectors. Keys containing `.`, `[` or
+/// `*` are quoted with `"` or `'`, e.g. `metadata.labels."app.kubernetes.io/name"`.
+pub fn parse_yaml_path(path: &str) -> Result<Vec<PathSegment>, Box<dyn Error>> {
+    let mut segments = Vec::new();
+    let mut rest = path;
+    loop {
+        match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let end = rest[1..]
+                    .find(quote)
+                    .ok_or_else(|| format!("Unclosed quote in YAML path: {}", path))?;
+                segments.push(PathSegment::Key(rest[1..end + 1].to_string()));
+                rest = &rest[end + 2..];
+            }
+            _ => {
+                let end = rest.find(['.', '[']).unwrap_or(rest.len());
+                match &rest[..end] {
+                    "" if !rest.starts_with('[') => {
+                        return Err(format!("Empty segment in YAML path: {}", path).into())
+                    }
+                    "" => {}
+                    "*" => segments.push(PathSegment::Wildcard),
+                    key => segments.push(PathSegment::Key(key.to_string())),
+                }
+                rest = &rest[end..];
+            }
+        }
+        while rest.starts_with('[') {
+            let end = rest
+                .find(']')
+                .ok_or_else(|| format!("Unclosed '[' in YAML path: {}", path))?;
+            let selector = &rest[1..end];
+            segments.push(match selector {
+                "*" => PathSegment::Wildcard,
+                "-" => PathSegment::Append,
+                index => PathSegment::Index(
+                    index
+                        .parse()
+                        .map_err(|_| format!("Invalid index '{}' in YAML path: {}", index, path))?,
+                ),
+            });
+            rest = &rest[end + 1..];
+        }
+        match rest.chars().next() {
+            None => return Ok(segments),
+            Some('.') => rest = &rest[1..],
+            Some(_) => return Err(format!("Invalid YAML path: {}", path).into()),
+        }
+    }
+}
+
+/// Parse `value` as a YAML value, so that `3` is an integer, `true` a boolean, `null` a null
+/// and `{a: 1}` a mapping. Values which are not valid YAML are kept as strings.
+pub fn parse_yaml_value(value: &str) -> YamlValue {
+    serde_yaml::from_str(value).unwrap_or_else(|_| YamlValue::String(value.to_string()))
+}
+
+/// An empty container for the `next` segment of a path being created.
+fn container_for(next: &PathSegment) -> YamlValue {
+    match next {
+        PathSegment::Index(_) | PathSegment::Append => YamlValue::Sequence(vec![]),
+        _ => YamlValue::Mapping(serde_yaml::Mapping::new()),
+    }
+}
+
+/// Apply `f` to all the nodes of `node` matching `segments`, and return how many there were.
+/// With `create`, missing keys are created, as are sequence elements at the end of a sequence.
+fn visit_mut(
+    node: &mut YamlValue,
+    segments: &[PathSegment],
+    create: bool,
+    f: &mut dyn FnMut(&mut YamlValue),
+) -> Result<usize, Box<dyn Error>> {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => {
+            f(node);
+            return Ok(1);
+        }
+    };
+    if create && node.is_null() {
+        *node = container_for(segment);
+    }
+    match (segment, node) {
+        (PathSegment::Key(key), YamlValue::Mapping(map)) => {
+            let key = YamlValue::String(key.to_string());
+            if !map.contains_key(&key) {
+                if !create {
+                    return Ok(0);
+                }
+                let child = match rest.first() {
+                    Some(next) => container_for(next),
+                    None => YamlValue::Null,
+                };
+                map.insert(key.clone(), child);
+            }
+            visit_mut(map.get_mut(&key).unwrap(), rest, create, f)
+        }
+        (PathSegment::Index(index), YamlValue::Sequence(seq)) => {
+            if *index == seq.len() && create {
+                seq.push(match rest.first() {
+                    Some(next) => container_for(next),
+                    None => YamlValue::Null,
+                });
+            }
+            match seq.get_mut(*index) {
+                Some(child) => visit_mut(child, rest, create, f),
+                None if create => Err(format!("Index {} out of bounds", index).into()),
+                None => Ok(0),
+            }
+        }
+        (PathSegment::Append, YamlValue::Sequence(seq)) => {
+            seq.push(match rest.first() {
+                Some(next) => container_for(next),
+                None => YamlValue::Null,
+            });
+            let child = seq.last_mut().unwrap();
+            visit_mut(child, rest, create, f)
+        }
+        (PathSegment::Wildcard, YamlValue::Mapping(map)) => {
+            let mut count = 0;
+            for (_, child) in map.iter_mut() {
+                count += visit_mut(child, rest, create, f)?;
+            }
+            Ok(count)
+        }
+        (PathSegment::Wildcard, YamlValue::Sequence(seq)) => {
+            let mut count = 0;
+            for child in seq.iter_mut() {
+                count += visit_mut(child, rest, create, f)?;
+            }
+            Ok(count)
+        }
+        (segment, _) if create => {
+            Err(format!("Can't create {:?} in a scalar YAML value", segment).into())
+        }
+        _ => Ok(0),
+    }
+}
+
+/// Collect all the nodes of `node` matching `segments`.
+fn collect<'a>(node: &'a YamlValue, segments: &[PathSegment], values: &mut Vec<&'a YamlValue>) {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => {
+            values.push(node);
+            return;
+        }
+    };
+    match (segment, node) {
+        (PathSegment::Key(key), YamlValue::Mapping(map)) => {
+            if let Some(child) = map.get(&YamlValue::String(key.to_string())) {
+                collect(child, rest, values);
+            }
+        }
+        (PathSegment::Index(index), YamlValue::Sequence(seq)) => {
+            if let Some(child) = seq.get(*index) {
+                collect(child, rest, values);
+            }
+        }
+        (PathSegment::Wildcard, YamlValue::Mapping(map)) => map
+            .iter()
+            .for_each(|(_, child)| collect(child, rest, values)),
+        (PathSegment::Wildcard, YamlValue::Sequence(seq)) => {
+            seq.iter().for_each(|child| collect(child, rest, values))
+        }
+        _ => {}
+    }
+}
+
+/// Get all the values of `doc` matching `path`.
+pub fn get_values(doc: &YamlValue, path: &str) -> Result<Vec<YamlValue>, Box<dyn Error>> {
+    let mut values = Vec::new();
+    collect(doc, &parse_yaml_path(path)?, &mut values);
+    Ok(values.into_iter().cloned().collect())
+}
+
+/// Set all the values of `doc` matching `path` to `value`, creating the missing keys.
+/// Returns the number of values set.
+pub fn set_value(
     doc: &mut YamlValue,
     path: &str,
-    new_value: &str,
-) -> Result<(), Box<dyn Error>> {
-    let path_segments: Vec<&str> = path.split('.').collect();
-    let mut current_node = doc;
-
-    for segment in path_segments {
-        match current_node {
-            YamlValue::Mapping(ref mut map) => {
-                if let Some(value) = map.get_mut(&YamlValue::String(segment.to_string())) {
-                    current_node = value;
-                } else {
-                    return Err(format!("Invalid path segment: {}", segment).into());
-                }
+    value: &YamlValue,
+) -> Result<usize, Box<dyn Error>> {
+    visit_mut(doc, &parse_yaml_path(path)?, true, &mut |node| {
+        *node = value.clone()
+    })
+}
+
+/// Append `value` to the sequences of `doc` matching `path`, creating the sequence if missing.
+/// Returns the number of sequences appended to.
+pub fn append_value(
+    doc: &mut YamlValue,
+    path: &str,
+    value: &YamlValue,
+) -> Result<usize, Box<dyn Error>> {
+    let mut segments = parse_yaml_path(path)?;
+    segments.push(PathSegment::Append);
+    visit_mut(doc, &segments, true, &mut |node| *node = value.clone())
+}
+
+/// Delete all the values of `doc` matching `path`. Returns the number of values deleted.
+pub fn delete_value(doc: &mut YamlValue, path: &str) -> Result<usize, Box<dyn Error>> {
+    let mut segments = parse_yaml_path(path)?;
+    let last = segments
+        .pop()
+        .ok_or_else(|| format!("Invalid YAML path: {}", path))?;
+    let mut deleted = 0;
+    visit_mut(doc, &segments, false, &mut |parent| {
+        deleted += match (&last, parent) {
+            (PathSegment::Key(key), YamlValue::Mapping(map)) => {
+                // `Mapping::remove` swaps the last key in: rebuild the mapping to keep the order
+                let key = YamlValue::String(key.to_string());
+                let count = map.len();
+                *map = std::mem::take(map)
+                    .into_iter()
+                    .filter(|(k, _)| *k != key)
+                    .collect();
+                count - map.len()
+            }
+            (PathSegment::Index(index), YamlValue::Sequence(seq)) if *index < seq.len() => {
+                seq.remove(*index);
+                1
             }
-            _ => return Err(format!("Invalid path segment: {}", segment).into()),
+            (PathSegment::Wildcard, YamlValue::Mapping(map)) => {
+                let count = map.len();
+                map.clear();
+                count
+            }
+            (PathSegment::Wildcard, YamlValue::Sequence(seq)) => {
+                let count = seq.len();
+                seq.clear();
+                count
+            }
+            _ => 0,
+        }
+    })?;
+    Ok(deleted)
+}
+
+/// `DocumentSelector` selects the documents of a multi-document YAML file.
+/// - `All`: Every document.
+/// - `Index`: The document at this index, starting from 0.
+/// - `Matcher`: The documents matching every `path=value` pair, e.g.
+///   `kind=Deployment,metadata.name=api`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DocumentSelector {
+    All,
+    Index(usize),
+    Matcher(Vec<(String, String)>),
+}
+
+impl FromStr for DocumentSelector {
+    type Err = String;
+
+    /// Parse `*` (or an empty string), an index, or a comma-separated list of `path=value`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() || s == "*" {
+            return Ok(DocumentSelector::All);
+        }
+        if let Ok(index) = s.parse() {
+            return Ok(DocumentSelector::Index(index));
         }
+        s.split(',')
+            .map(|pair| match pair.split_once('=') {
+                Some((path, value)) => Ok((path.trim().to_string(), value.trim().to_string())),
+                None => Err(format!("Invalid YAML document matcher: {}", pair)),
+            })
+            .collect::<Result<Vec<(String, String)>, String>>()
+            .map(DocumentSelector::Matcher)
     }
+}
 
-    *current_node = YamlValue::String(new_value.to_string());
-    Ok(())
+impl DocumentSelector {
+    /// Whether the document `doc`, at `index` in its file, is selected.
+    pub fn matches(&self, index: usize, doc: &YamlValue) -> bool {
+        match self {
+            DocumentSelector::All => true,
+            DocumentSelector::Index(selected) => *selected == index,
+            DocumentSelector::Matcher(pairs) => pairs.iter().all(|(path, expected)| {
+                get_values(doc, path)
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|value| scalar_to_string(value).as_deref() == Some(expected))
+            }),
+        }
+    }
+}
+
+/// A scalar value as a string, e.g. `3` for the number 3. `None` for other values.
+pub(crate) fn scalar_to_string(value: &YamlValue) -> Option<String> {
+    match value {
+        YamlValue::String(value) => Some(value.clone()),
+        YamlValue::Number(value) => Some(value.to_string()),
+        YamlValue::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// The indexes of the documents of `docs` selected by `selector`.
+pub fn select_documents(docs: &[YamlValue], selector: &DocumentSelector) -> Vec<usize> {
+    docs.iter()
+        .enumerate()
+        .filter(|(index, doc)| selector.matches(*index, doc))
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// A document of a multi-document YAML file, identified by its Kubernetes kind and name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct YamlDocumentInfo {
+    pub index: usize,
+    pub kind: Option<String>,
+    pub name: Option<String>,
+    pub namespace: Option<String>,
+}
+
+/// Describe the documents of `docs`.
+pub fn describe_documents(docs: &[YamlValue]) -> Vec<YamlDocumentInfo> {
+    let first = |doc: &YamlValue, path: &str| {
+        get_values(doc, path)
+            .ok()
+            .and_then(|values| values.first().and_then(scalar_to_string))
+    };
+    docs.iter()
+        .enumerate()
+        .map(|(index, doc)| YamlDocumentInfo {
+            index,
+            kind: first(doc, "kind"),
+            name: first(doc, "metadata.name"),
+            namespace: first(doc, "metadata.namespace"),
+        })
+        .collect()
+}
+
+/// Read a YAML file.
+fn read_yaml_source(file_path: &str) -> Result<String, Box<dyn Error>> {
+    let mut file = File::open(file_path)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+/// Read and parse all the documents of a YAML file.
+pub fn read_yaml_documents(file_path: &str) -> Result<Vec<YamlValue>, Box<dyn Error>> {
+    let contents = read_yaml_source(file_path)?;
+    let mut docs = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(&contents) {
+        docs.push(YamlValue::deserialize(document)?);
+    }
+    Ok(docs)
+}
+
+/// Edit the documents of a YAML file selected by `selector` in place with `edit`, which
+/// only rewrites the targeted nodes: comments, quoting styles, anchors and key order are kept.
+/// The documents are selected from the same parse as the edits, so they can't disagree on
+/// where each document starts.
+fn patch_yaml_file(
+    file_path: &str,
+    selector: &DocumentSelector,
+    edit: impl Fn(&mut PatchableYaml, usize) -> Result<usize, Box<dyn Error>>,
+) -> Result<usize, Box<dyn Error>> {
+    let mut yaml = PatchableYaml::parse(&read_yaml_source(file_path)?)?;
+    let mut count = 0;
+    for index in select_documents(&yaml.values(), selector) {
+        count += edit(&mut yaml, index)?;
+    }
+    if count > 0 {
+        plan::write(file_path, yaml.source())?;
+    }
+    Ok(count)
+}
+
+/// List the documents of a YAML file, with their kind and name.
+pub fn list_yaml_documents(file_path: &str) -> Result<Vec<YamlDocumentInfo>, Box<dyn Error>> {
+    Ok(describe_documents(&read_yaml_documents(file_path)?))
 }
 
 /// Update a YAML file with the provided key-value pairs.
+/// The value is always written as a string, see [`set_yaml_value`] for typed values.
 /// # Arguments
 /// * `file_path` - The path to the YAML file.
-/// * `path` - The dot-separated path to the YAML value to update.
+/// * `path` - The path to the YAML value to update (see [`parse_yaml_path`]).
 /// * `new_value` - The new value to set.
 pub fn update_yaml_value(
     file_path: &str,
     path: &str,
     new_value: &str,
 ) -> Result<(), Box<dyn Error>> {
-    // Read the YAML file
-    let mut file = File::open(file_path)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
+    set_yaml_value(file_path, path, &YamlValue::String(new_value.to_string()))?;
+    Ok(())
+}
 
-    // Parse the YAML content
-    let mut doc: YamlValue = serde_yaml::from_str(&contents)?;
+/// Set the values matching `path` in the first document of a YAML file, creating the
+/// missing keys. The rest of the file is left untouched, comments and formatting included.
+/// # Arguments
+/// * `file_path` - The path to the YAML file.
+/// * `path` - The path to the YAML values to set (see [`parse_yaml_path`]).
+/// * `value` - The new value, e.g. from [`parse_yaml_value`].
+/// # Returns
+/// The number of values set.
+pub fn set_yaml_value(
+    file_path: &str,
+    path: &str,
+    value: &YamlValue,
+) -> Result<usize, Box<dyn Error>> {
+    set_yaml_value_in(file_path, &DocumentSelector::Index(0), path, value)
+}
 
-    // Update the YAML value in-place
-    update_yaml_value_in_place(&mut doc, path, new_value)?;
+/// Set the values matching `path` in the documents of a YAML file selected by `selector`.
+/// # Returns
+/// The number of values set, across all the documents.
+pub fn set_yaml_value_in(
+    file_path: &str,
+    selector: &DocumentSelector,
+    path: &str,
+    value: &YamlValue,
+) -> Result<usize, Box<dyn Error>> {
+    patch_yaml_file(file_path, selector, |yaml, index| {
+        yaml.set(index, path, value)
+    })
+}
 
-    // Write the updated content back to the file
-    let mut file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .open(file_path)?;
-    let updated_contents = serde_yaml::to_string(&doc)?;
-    file.write_all(updated_contents.as_bytes())?;
+/// Append `value` to the sequences matching `path` in the first document of a YAML file,
+/// keeping its formatting.
+/// # Returns
+/// The number of sequences appended to.
+pub fn append_yaml_value(
+    file_path: &str,
+    path: &str,
+    value: &YamlValue,
+) -> Result<usize, Box<dyn Error>> {
+    append_yaml_value_in(file_path, &DocumentSelector::Index(0), path, value)
+}
 
-    Ok(())
+/// Append `value` to the sequences matching `path` in the documents selected by `selector`.
+pub fn append_yaml_value_in(
+    file_path: &str,
+    selector: &DocumentSelector,
+    path: &str,
+    value: &YamlValue,
+) -> Result<usize, Box<dyn Error>> {
+    patch_yaml_file(file_path, selector, |yaml, index| {
+        yaml.append(index, path, value)
+    })
+}
+
+/// Delete the values matching `path` in the first document of a YAML file, keeping its
+/// formatting.
+/// # Returns
+/// The number of values deleted.
+pub fn delete_yaml_value(file_path: &str, path: &str) -> Result<usize, Box<dyn Error>> {
+    delete_yaml_value_in(file_path, &DocumentSelector::Index(0), path)
+}
+
+/// Delete the values matching `path` in the documents selected by `selector`.
+pub fn delete_yaml_value_in(
+    file_path: &str,
+    selector: &DocumentSelector,
+    path: &str,
+) -> Result<usize, Box<dyn Error>> {
+    patch_yaml_file(file_path, selector, |yaml, index| yaml.delete(index, path))
+}
+
+/// Get all the values matching `path` in the first document of a YAML file.
+pub fn get_yaml_values(file_path: &str, path: &str) -> Result<Vec<YamlValue>, Box<dyn Error>> {
+    get_yaml_values_in(file_path, &DocumentSelector::Index(0), path)
+}
+
+/// Get all the values matching `path` in the documents selected by `selector`.
+pub fn get_yaml_values_in(
+    file_path: &str,
+    selector: &DocumentSelector,
+    path: &str,
+) -> Result<Vec<YamlValue>, Box<dyn Error>> {
+    let docs = read_yaml_documents(file_path)?;
+    let mut values = Vec::new();
+    for index in select_documents(&docs, selector) {
+        values.extend(get_values(&docs[index], path)?);
+    }
+    Ok(values)
 }
 
 /// Get a YAML value from a file.
 /// # Arguments
 /// * `file_path` - The path to the YAML file.
-/// * `yaml_key_path` - The dot-separated path to the YAML value to retrieve.
+/// * `yaml_key_path` - The path to the YAML value to retrieve (see [`parse_yaml_path`]).
 /// # Returns
-/// The YAML value as a `String`.
+/// The YAML value as a `String`. Numbers and booleans are converted to strings.
 pub fn get_yaml_value(
     file_path: &str,
     yaml_key_path: &str,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    // Read the YAML file
-    let mut file = File::open(file_path)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
+    let values = get_yaml_values(file_path, yaml_key_path)?;
+    let value = values
+        .first()
+        .ok_or_else(|| format!("Key not found in YAML key path: {}", yaml_key_path))?;
 
-    // Parse the YAML
-    let yaml_data: YamlValue = serde_yaml::from_str(&contents)?;
+    // Convert the retrieved YAML value to a String
+    scalar_to_string(value).ok_or_else(|| "The retrieved YAML value is not a scalar.".into())
+}
 
-    // Split the dot-separated key path
-    let keys: Vec<&str> = yaml_key_path.split('.').collect();
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
 
-    // Traverse and get the YAML value
-    let mut current_node = &yaml_data;
-    for key in keys.into_iter() {
-        if let YamlValue::Mapping(ref map) = current_node {
-            current_node = map
-                .get(&YamlValue::String(key.to_string()))
-                .ok_or_else(|| {
-                    format!(
-                        "Key '{}' not found in YAML key path: {}",
-                        key, yaml_key_path
-                    )
-                })?;
-        } else {
-            return Err(format!("Invalid YAML key path: {}", yaml_key_path).into());
-        }
+    use super::*;
+
+    fn doc() -> YamlValue {
+        serde_yaml::from_str(
+            "spec:\n  replicas: 1\n  containers:\n  - name: api\n    image: api:1\n  - name: sidecar\n    image: proxy:1\n",
+        )
+        .unwrap()
     }
 
-    // Convert the retrieved YAML value to a String
-    if let YamlValue::String(value) = current_node {
-        Ok(value.clone())
-    } else {
-        Err("The retrieved YAML value is not a string.".into())
+    #[test]
+    fn test_parse_yaml_path() {
+        assert_eq!(
+            parse_yaml_path("spec.containers[0].image").unwrap(),
+            vec![
+                PathSegment::Key("spec".to_string()),
+                PathSegment::Key("containers".to_string()),
+                PathSegment::Index(0),
+                PathSegment::Key("image".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse_yaml_path("data.*.x[*][-]").unwrap(),
+            vec![
+                PathSegment::Key("data".to_string()),
+                PathSegment::Wildcard,
+                PathSegment::Key("x".to_string()),
+                PathSegment::Wildcard,
+                PathSegment::Append,
+            ]
+        );
+        assert_eq!(
+            parse_yaml_path("metadata.labels.\"app.kubernetes.io/name\"").unwrap(),
+            vec![
+                PathSegment::Key("metadata".to_string()),
+                PathSegment::Key("labels".to_string()),
+                PathSegment::Key("app.kubernetes.io/name".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse_yaml_path("'*'[0].'a[b]'").unwrap(),
+            vec![
+                PathSegment::Key("*".to_string()),
+                PathSegment::Index(0),
+                PathSegment::Key("a[b]".to_string()),
+            ]
+        );
+        assert!(parse_yaml_path("a.\"b").is_err());
+        assert!(parse_yaml_path("a.\"b\"c").is_err());
+        assert!(parse_yaml_path("a[0]b").is_err());
+        assert!(parse_yaml_path("a.").is_err());
+        assert!(parse_yaml_path("a..b").is_err());
+        assert!(parse_yaml_path("a[x]").is_err());
+        assert!(parse_yaml_path("a[0").is_err());
+    }
+
+    #[test]
+    fn test_get_and_set_values() {
+        let mut doc = doc();
+        assert_eq!(
+            get_values(&doc, "spec.containers[*].name").unwrap(),
+            vec![YamlValue::from("api"), YamlValue::from("sidecar")]
+        );
+        assert_eq!(
+            set_value(&mut doc, "spec.replicas", &parse_yaml_value("3")).unwrap(),
+            1
+        );
+        assert_eq!(
+            get_values(&doc, "spec.replicas").unwrap(),
+            vec![YamlValue::from(3)]
+        );
+        assert_eq!(
+            set_value(
+                &mut doc,
+                "spec.containers[*].image",
+                &YamlValue::from("x:2")
+            )
+            .unwrap(),
+            2
+        );
+        set_value(&mut doc, "metadata.labels.app", &YamlValue::from("api")).unwrap();
+        set_value(&mut doc, "spec.args[0]", &YamlValue::from("--verbose")).unwrap();
+        assert_eq!(
+            get_values(&doc, "metadata.labels.app").unwrap(),
+            vec![YamlValue::from("api")]
+        );
+        set_value(
+            &mut doc,
+            "metadata.labels.\"app.kubernetes.io/name\"",
+            &YamlValue::from("api"),
+        )
+        .unwrap();
+        assert_eq!(
+            get_values(&doc, "metadata.labels").unwrap(),
+            vec![parse_yaml_value("{app: api, app.kubernetes.io/name: api}")]
+        );
+        assert_eq!(
+            get_values(&doc, "spec.args").unwrap(),
+            vec![parse_yaml_value("[--verbose]")]
+        );
+        assert!(set_value(&mut doc, "spec.replicas.x", &YamlValue::Null).is_err());
+        assert!(set_value(&mut doc, "spec.args[5]", &YamlValue::Null).is_err());
+    }
+
+    #[test]
+    fn test_append_and_delete_values() {
+        let mut doc = doc();
+        append_value(
+            &mut doc,
+            "spec.containers",
+            &parse_yaml_value("{name: init, image: busybox}"),
+        )
+        .unwrap();
+        assert_eq!(
+            get_values(&doc, "spec.containers[2].name").unwrap(),
+            vec![YamlValue::from("init")]
+        );
+        append_value(&mut doc, "spec.volumes", &YamlValue::from("data")).unwrap();
+        assert_eq!(
+            get_values(&doc, "spec.volumes").unwrap(),
+            vec![parse_yaml_value("[data]")]
+        );
+        assert_eq!(delete_value(&mut doc, "spec.containers[0]").unwrap(), 1);
+        assert_eq!(
+            delete_value(&mut doc, "spec.containers[*].image").unwrap(),
+            2
+        );
+        assert_eq!(delete_value(&mut doc, "spec.missing").unwrap(), 0);
+        assert_eq!(
+            get_values(&doc, "spec.containers").unwrap(),
+            vec![parse_yaml_value("[{name: sidecar}, {name: init}]")]
+        );
+    }
+
+    #[test]
+    fn test_documents() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(
+            b"# api\nkind: Deployment\nmetadata:\n  name: api\nspec:\n  replicas: 1\n---\nkind: Service\nmetadata:\n  name: api\n---\nkind: Deployment\nmetadata:\n  name: worker # background jobs\nspec:\n  replicas: 1\n",
+        )
+        .unwrap();
+        let path = file.path().to_str().unwrap();
+
+        assert_eq!(
+            "kind=Deployment, metadata.name=api".parse::<DocumentSelector>(),
+            Ok(DocumentSelector::Matcher(vec![
+                ("kind".to_string(), "Deployment".to_string()),
+                ("metadata.name".to_string(), "api".to_string()),
+            ]))
+        );
+        assert_eq!("2".parse(), Ok(DocumentSelector::Index(2)));
+        assert!("kind".parse::<DocumentSelector>().is_err());
+
+        let documents = list_yaml_documents(path).unwrap();
+        assert_eq!(documents.len(), 3);
+        assert_eq!(documents[1].kind.as_deref(), Some("Service"));
+        assert_eq!(documents[2].name.as_deref(), Some("worker"));
+
+        let deployments = "kind=Deployment".parse().unwrap();
+        assert_eq!(
+            set_yaml_value_in(path, &deployments, "spec.replicas", &YamlValue::from(3)).unwrap(),
+            2
+        );
+        assert_eq!(
+            get_yaml_values_in(path, &DocumentSelector::All, "spec.replicas").unwrap(),
+            vec![YamlValue::from(3), YamlValue::from(3)]
+        );
+        assert_eq!(get_yaml_value(path, "metadata.name").unwrap(), "api");
+        let contents = std::fs::read_to_string(path).unwrap();
+        assert!(contents.contains("  name: worker # background jobs\n"));
+        assert_eq!(contents.matches("replicas: 3").count(), 2);
+
+        let empty = tempfile::NamedTempFile::new().unwrap();
+        let path = empty.path().to_str().unwrap();
+        assert_eq!(set_yaml_value(path, "a.b", &YamlValue::from(1)).unwrap(), 1);
+        assert_eq!(get_yaml_value(path, "a.b").unwrap(), "1");
     }
 }
@@ -0,0 +1,878 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use serde_yaml::Value as YamlValue;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, TScalarStyle};
+use yaml_rust::Yaml;
+
+use crate::modules::formats::yaml::{parse_yaml_path, parse_yaml_value, PathSegment};
+
+/// A node of a YAML document, with its location in the source.
+#[derive(Debug, Clone)]
+struct Node {
+    kind: NodeKind,
+    /// The byte offsets of the node in the source.
+    start: usize,
+    end: usize,
+    /// The column of the start of the node.
+    col: usize,
+    /// Whether the node is a flow collection (`[a, b]` or `{a: b}`).
+    flow: bool,
+    /// The id of the anchor of the node (`&anchor`), 0 if it has none.
+    anchor: usize,
+}
+
+#[derive(Debug, Clone)]
+enum NodeKind {
+    /// A scalar, with its style. Empty scalars (`key:`) have an empty span after the `:`.
+    Scalar(String, TScalarStyle),
+    Mapping(Vec<(Node, Node)>),
+    Sequence(Vec<Node>),
+    /// An alias (`*anchor`) to the node with this anchor id, which can't be edited in place.
+    Alias(usize),
+    /// A scalar whose span can't be found, e.g. a multi-line plain scalar.
+    Unsupported(String),
+}
+
+/// A replacement of the source between two byte offsets.
+#[derive(Debug, Clone)]
+struct Edit {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+#[derive(Default)]
+struct EventCollector {
+    events: Vec<(Event, Marker)>,
+}
+
+impl MarkedEventReceiver for EventCollector {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        self.events.push((ev, mark));
+    }
+}
+
+/// A YAML source which can be edited without reformatting it: only the targeted nodes
+/// are rewritten, so comments, quoting styles, anchors and key order are kept.
+pub struct PatchableYaml {
+    source: String,
+    documents: Vec<Node>,
+}
+
+/// The byte offset of the end of the line containing `offset`.
+fn line_end(source: &str, offset: usize) -> usize {
+    source[offset..]
+        .find('\n')
+        .map_or(source.len(), |end| offset + end)
+}
+
+/// The byte offset of the start of the line containing `offset`.
+fn line_start(source: &str, offset: usize) -> usize {
+    source[..offset].rfind('\n').map_or(0, |start| start + 1)
+}
+
+/// The indentation of the line containing `offset`.
+fn line_indent(source: &str, offset: usize) -> usize {
+    let start = line_start(source, offset);
+    source[start..].len() - source[start..].trim_start_matches(' ').len()
+}
+
+/// The end of a quoted scalar starting at `start`.
+fn quoted_end(source: &str, start: usize, quote: char) -> Option<usize> {
+    let mut chars = source[start..].char_indices().skip(1).peekable();
+    while let Some((index, c)) = chars.next() {
+        if quote == '"' && c == '\\' {
+            chars.next();
+        } else if c == quote {
+            if quote == '\'' && chars.peek().map(|(_, c)| *c) == Some('\'') {
+                chars.next();
+            } else {
+                return Some(start + index + 1);
+            }
+        }
+    }
+    None
+}
+
+/// The end of a block scalar (`|` or `>`) whose indicator is at `start`: the end of its
+/// last line indented more than the line of the indicator.
+fn block_scalar_end(source: &str, start: usize) -> usize {
+    let indent = line_indent(source, start);
+    let mut end = line_end(source, start);
+    let mut offset = end;
+    while offset < source.len() {
+        let next = line_end(source, offset + 1);
+        let line = &source[offset + 1..next];
+        if line.trim().is_empty() {
+            offset = next;
+            continue;
+        }
+        if line.len() - line.trim_start_matches(' ').len() <= indent {
+            break;
+        }
+        end = next;
+        offset = next;
+    }
+    end
+}
+
+/// Whether `s` can be written as a plain scalar and read back as the same string.
+fn is_plain_safe(s: &str) -> bool {
+    !s.is_empty()
+        && s.trim() == s
+        && !s.contains('\n')
+        && !s.contains(": ")
+        && !s.contains(" #")
+        && !s.ends_with(':')
+        && !s.starts_with(|c| ",[]{}#&*!|>'\"%@`".contains(c))
+        // `-`, `?` and `:` are indicators when followed by a space
+        && !(s.starts_with(['-', '?', ':']) && s[1..].starts_with([' ', '\t']) || s.len() == 1 && s.starts_with(['-', '?', ':']))
+        && parse_yaml_value(s) == YamlValue::String(s.to_string())
+}
+
+/// Render a scalar value, keeping the quoting style of the scalar it replaces when possible.
+fn render_scalar(value: &YamlValue, style: TScalarStyle, source: &str, start: usize) -> String {
+    let s = match value {
+        YamlValue::String(s) => s,
+        YamlValue::Null => return "null".to_string(),
+        other => return render_flow(other),
+    };
+    match style {
+        TScalarStyle::SingleQuoted if !s.contains('\n') => format!("'{}'", s.replace('\'', "''")),
+        TScalarStyle::Literal | TScalarStyle::Foled => {
+            let indicator_end = source[start..]
+                .find(|c: char| c.is_whitespace())
+                .map_or(source.len(), |end| start + end);
+            let indent = " ".repeat(line_indent(source, start) + 2);
+            let lines = s
+                .trim_end_matches('\n')
+                .split('\n')
+                .map(|line| format!("{}{}", indent, line))
+                .collect::<Vec<String>>()
+                .join("\n");
+            format!("{}\n{}", &source[start..indicator_end], lines)
+        }
+        TScalarStyle::Plain if is_plain_safe(s) => s.to_string(),
+        _ => serde_json::to_string(s).unwrap(),
+    }
+}
+
+/// Render a value in flow style, e.g. `{"a": [1, 2]}` (JSON is valid flow YAML).
+fn render_flow(value: &YamlValue) -> String {
+    match value {
+        YamlValue::String(s) if is_plain_safe(s) => s.to_string(),
+        YamlValue::Null => "null".to_string(),
+        other => serde_json::to_string(other).unwrap_or_else(|_| "null".to_string()),
+    }
+}
+
+/// Render a value in block style, with its lines after the first indented by `indent`.
+fn render_block(value: &YamlValue, indent: usize) -> String {
+    if !matches!(value, YamlValue::Mapping(_) | YamlValue::Sequence(_)) {
+        return render_flow(value);
+    }
+    let rendered = serde_yaml::to_string(value).unwrap_or_default();
+    let rendered = rendered.trim_start_matches("---\n").trim_end();
+    rendered
+        .split('\n')
+        .enumerate()
+        .map(|(index, line)| {
+            if index == 0 {
+                line.to_string()
+            } else {
+                format!("{}{}", " ".repeat(indent), line)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Whether `value` is a collection written in block style, i.e. a non-empty one.
+fn is_block(value: &YamlValue) -> bool {
+    match value {
+        YamlValue::Mapping(map) => !map.is_empty(),
+        YamlValue::Sequence(seq) => !seq.is_empty(),
+        _ => false,
+    }
+}
+
+/// Render `value` after the `:` of a key of a block mapping indented by `indent`:
+/// collections go on the next lines, in block style.
+fn render_mapping_value(value: &YamlValue, indent: usize) -> String {
+    if is_block(value) {
+        format!(
+            "\n{}{}",
+            " ".repeat(indent + 2),
+            render_block(value, indent + 2)
+        )
+    } else {
+        format!(" {}", render_flow(value))
+    }
+}
+
+/// Render `key: value` for a new entry of a block mapping indented by `indent`.
+fn render_entry(key: &str, value: &YamlValue, indent: usize) -> String {
+    let key = render_scalar(&YamlValue::from(key), TScalarStyle::Plain, "", 0);
+    format!("{}:{}", key, render_mapping_value(value, indent))
+}
+
+/// The value of `node`, resolving its aliases with the values of the `anchors` seen so far.
+fn to_value(node: &Node, anchors: &mut HashMap<usize, YamlValue>) -> YamlValue {
+    let value = match &node.kind {
+        NodeKind::Scalar(s, TScalarStyle::Plain) => match Yaml::from_str(s) {
+            Yaml::Boolean(b) => YamlValue::from(b),
+            Yaml::Integer(i) => YamlValue::from(i),
+            Yaml::Real(real) => real
+                .parse::<f64>()
+                .map_or(YamlValue::String(real), YamlValue::from),
+            Yaml::String(s) => YamlValue::String(s),
+            _ => YamlValue::Null,
+        },
+        NodeKind::Scalar(s, _) | NodeKind::Unsupported(s) => YamlValue::String(s.clone()),
+        NodeKind::Mapping(pairs) => YamlValue::Mapping(
+            pairs
+                .iter()
+                .map(|(key, value)| (to_value(key, anchors), to_value(value, anchors)))
+                .collect(),
+        ),
+        NodeKind::Sequence(items) => {
+            YamlValue::Sequence(items.iter().map(|item| to_value(item, anchors)).collect())
+        }
+        NodeKind::Alias(id) => anchors.get(id).cloned().unwrap_or(YamlValue::Null),
+    };
+    if node.anchor != 0 {
+        anchors.insert(node.anchor, value.clone());
+    }
+    value
+}
+
+/// Nest `value` under the keys of `segments`, e.g. `a.b` and `1` give `{a: {b: 1}}`.
+fn nest(segments: &[PathSegment], value: &YamlValue) -> Result<YamlValue, Box<dyn Error>> {
+    let mut nested = value.clone();
+    for segment in segments.iter().rev() {
+        nested = match segment {
+            PathSegment::Key(key) => {
+                let mut map = serde_yaml::Mapping::new();
+                map.insert(YamlValue::from(key.as_str()), nested);
+                YamlValue::Mapping(map)
+            }
+            PathSegment::Index(0) | PathSegment::Append => YamlValue::Sequence(vec![nested]),
+            other => return Err(format!("Can't create {:?} in YAML", other).into()),
+        };
+    }
+    Ok(nested)
+}
+
+/// Where a path leads under a node.
+enum Target<'a, 'p> {
+    /// A node matching the whole path, with its parent.
+    Found(&'a Node, Option<&'a Node>),
+    /// A node, with its parent, which lacks these last segments of the path.
+    Missing(&'a Node, Option<&'a Node>, &'p [PathSegment]),
+}
+
+/// Collect where `segments` lead under `node`, whose parent is `parent`.
+fn walk<'a, 'p>(
+    node: &'a Node,
+    parent: Option<&'a Node>,
+    segments: &'p [PathSegment],
+    targets: &mut Vec<Target<'a, 'p>>,
+) {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return targets.push(Target::Found(node, parent)),
+    };
+    match (segment, &node.kind) {
+        (PathSegment::Key(key), NodeKind::Mapping(pairs)) => {
+            let mut children = pairs
+                .iter()
+                .filter(|(k, _)| matches!(&k.kind, NodeKind::Scalar(s, _) if s == key))
+                .peekable();
+            if children.peek().is_none() {
+                return targets.push(Target::Missing(node, parent, segments));
+            }
+            children.for_each(|(_, child)| walk(child, Some(node), rest, targets));
+        }
+        (PathSegment::Index(index), NodeKind::Sequence(items)) => match items.get(*index) {
+            Some(child) => walk(child, Some(node), rest, targets),
+            None => targets.push(Target::Missing(node, parent, segments)),
+        },
+        (PathSegment::Wildcard, NodeKind::Mapping(pairs)) => pairs
+            .iter()
+            .for_each(|(_, child)| walk(child, Some(node), rest, targets)),
+        (PathSegment::Wildcard, NodeKind::Sequence(items)) => items
+            .iter()
+            .for_each(|child| walk(child, Some(node), rest, targets)),
+        _ => targets.push(Target::Missing(node, parent, segments)),
+    }
+}
+
+impl PatchableYaml {
+    /// Parse a YAML source.
+    pub fn parse(source: &str) -> Result<Self, Box<dyn Error>> {
+        let mut collector = EventCollector::default();
+        Parser::new(source.chars()).load(&mut collector, true)?;
+
+        // Markers are char offsets
+        let mut offsets = source
+            .char_indices()
+            .map(|(i, _)| i)
+            .collect::<Vec<usize>>();
+        offsets.push(source.len());
+        let events = collector
+            .events
+            .into_iter()
+            .map(|(event, mark)| (event, offsets[mark.index()], mark.col()))
+            .collect::<Vec<(Event, usize, usize)>>();
+
+        let mut documents = Vec::new();
+        let mut position = 0;
+        while position < events.len() {
+            if let Event::DocumentStart = events[position].0 {
+                position += 1;
+                documents.push(Self::build(source, &events, &mut position)?);
+            } else {
+                position += 1;
+            }
+        }
+        if documents.is_empty() {
+            // An empty source, or only comments, is an empty document
+            documents.push(Node {
+                kind: NodeKind::Scalar("~".to_string(), TScalarStyle::Plain),
+                start: source.len(),
+                end: source.len(),
+                col: 0,
+                flow: false,
+                anchor: 0,
+            });
+        }
+        Ok(PatchableYaml {
+            source: source.to_string(),
+            documents,
+        })
+    }
+
+    /// The (edited) source.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The values of the documents, e.g. to select the documents to edit.
+    pub fn values(&self) -> Vec<YamlValue> {
+        self.documents
+            .iter()
+            .map(|document| to_value(document, &mut HashMap::new()))
+            .collect()
+    }
+
+    fn build(
+        source: &str,
+        events: &[(Event, usize, usize)],
+        position: &mut usize,
+    ) -> Result<Node, Box<dyn Error>> {
+        let (event, start, col) = events
+            .get(*position)
+            .ok_or("Unexpected end of YAML events")?;
+        let (start, col) = (*start, *col);
+        *position += 1;
+        // The column of a byte offset, for the starts which aren't the mark of the event
+        let col_of = |offset: usize| source[line_start(source, offset)..offset].chars().count();
+        match event {
+            Event::Scalar(value, style, anchor, _) => {
+                let rest = &source[start..];
+                let (start, end) = match style {
+                    TScalarStyle::SingleQuoted => (start, quoted_end(source, start, '\'')),
+                    TScalarStyle::DoubleQuoted => (start, quoted_end(source, start, '"')),
+                    // The mark of a block scalar is its first line, after the `|` or `>`
+                    TScalarStyle::Literal | TScalarStyle::Foled => {
+                        let indicator = source[..start]
+                            .rfind(['|', '>'])
+                            .ok_or("Missing YAML block scalar indicator")?;
+                        (indicator, Some(block_scalar_end(source, indicator)))
+                    }
+                    // Empty scalars are located by their mapping
+                    _ if value == "~" && !rest.starts_with('~') => (start, Some(start)),
+                    _ if rest.starts_with(value.as_str()) => (start, Some(start + value.len())),
+                    _ => (start, None),
+                };
+                let kind = match end {
+                    Some(_) => NodeKind::Scalar(value.to_string(), *style),
+                    None => NodeKind::Unsupported(value.to_string()),
+                };
+                Ok(Node {
+                    kind,
+                    start,
+                    end: end.unwrap_or(start),
+                    col: col_of(start),
+                    flow: false,
+                    anchor: *anchor,
+                })
+            }
+            Event::Alias(id) => {
+                let end = source[start + 1..]
+                    .find(|c: char| c.is_whitespace() || ",]}".contains(c))
+                    .map_or(source.len(), |end| start + 1 + end);
+                Ok(Node {
+                    kind: NodeKind::Alias(*id),
+                    start,
+                    end,
+                    col,
+                    flow: false,
+                    anchor: 0,
+                })
+            }
+            Event::SequenceStart(anchor) => {
+                let mut items: Vec<Node> = Vec::new();
+                loop {
+                    match events.get(*position) {
+                        Some((Event::SequenceEnd, end_mark, _)) => {
+                            *position += 1;
+                            // The marks of collections aren't reliable: locate them by
+                            // their brackets, or their first item
+                            let flow = source[*end_mark..].starts_with(']');
+                            let (start, end) = if flow {
+                                let bracket = start + source[start..].find('[').unwrap_or(0);
+                                (bracket, end_mark + 1)
+                            } else {
+                                let first = items.first().ok_or("Empty YAML block sequence")?;
+                                let dash = source[..first.start]
+                                    .rfind('-')
+                                    .ok_or("Missing YAML list marker")?;
+                                (dash, items.last().unwrap().end)
+                            };
+                            return Ok(Node {
+                                kind: NodeKind::Sequence(items),
+                                start,
+                                end,
+                                col: col_of(start),
+                                flow,
+                                anchor: *anchor,
+                            });
+                        }
+                        Some(_) => items.push(Self::build(source, events, position)?),
+                        None => return Err("Unclosed YAML sequence".into()),
+                    }
+                }
+            }
+            Event::MappingStart(anchor) => {
+                let mut pairs: Vec<(Node, Node)> = Vec::new();
+                loop {
+                    match events.get(*position) {
+                        Some((Event::MappingEnd, end_mark, _)) => {
+                            *position += 1;
+                            let flow = source[*end_mark..].starts_with('}');
+                            let (start, end) = if flow {
+                                let brace = start + source[start..].find('{').unwrap_or(0);
+                                (brace, end_mark + 1)
+                            } else {
+                                let (first, _) = pairs.first().ok_or("Empty YAML block mapping")?;
+                                (first.start, pairs.last().unwrap().1.end)
+                            };
+                            return Ok(Node {
+                                kind: NodeKind::Mapping(pairs),
+                                start,
+                                end,
+                                col: col_of(start),
+                                flow,
+                                anchor: *anchor,
+                            });
+                        }
+                        Some(_) => {
+                            let key = Self::build(source, events, position)?;
+                            let mut value = Self::build(source, events, position)?;
+                            if value.start == value.end && value.start != key.end {
+                                // An empty value: locate it right after the `:`
+                                if let Some(colon) = source[key.end..].find(':') {
+                                    value.start = key.end + colon + 1;
+                                    value.end = value.start;
+                                }
+                            }
+                            pairs.push((key, value));
+                        }
+                        None => return Err("Unclosed YAML mapping".into()),
+                    }
+                }
+            }
+            other => Err(format!("Unexpected YAML event: {:?}", other).into()),
+        }
+    }
+
+    /// Apply `edits` to the source, and parse it again.
+    fn apply(&mut self, mut edits: Vec<Edit>) -> Result<usize, Box<dyn Error>> {
+        let count = edits.len();
+        edits.sort_by_key(|edit| std::cmp::Reverse(edit.start));
+        let mut source = self.source.clone();
+        let mut previous_start = usize::MAX;
+        for edit in edits {
+            if edit.end > previous_start {
+                return Err("Overlapping YAML edits".into());
+            }
+            source.replace_range(edit.start..edit.end, &edit.text);
+            previous_start = edit.start;
+        }
+        *self = PatchableYaml::parse(&source)?;
+        Ok(count)
+    }
+
+    /// The edit replacing `node`, whose parent is `parent`, with `value`.
+    fn replace(
+        &self,
+        node: &Node,
+        parent: Option<&Node>,
+        value: &YamlValue,
+    ) -> Result<Edit, Box<dyn Error>> {
+        let block = is_block(value) && !matches!(parent, Some(parent) if parent.flow);
+        let mut start = node.start;
+        let text = match (&node.kind, parent.map(|parent| &parent.kind)) {
+            (NodeKind::Alias(_), _) => return Err("Can't edit an alias in place".into()),
+            (NodeKind::Unsupported(_), _) => {
+                return Err("Can't edit a multi-line plain scalar in place".into())
+            }
+            // `key: value` becomes `key:` followed by the indented collection
+            (NodeKind::Scalar(..), Some(NodeKind::Mapping(_))) if block => {
+                start = self.source[..node.start].trim_end_matches(' ').len();
+                render_mapping_value(value, parent.unwrap().col)
+            }
+            // An empty document
+            (NodeKind::Scalar(..), None) if node.start == node.end => {
+                let separator = if self.source[..start].ends_with('\n') || start == 0 {
+                    ""
+                } else {
+                    "\n"
+                };
+                format!("{}{}\n", separator, render_block(value, 0))
+            }
+            // `key:` becomes `key: value`
+            (NodeKind::Scalar(..), _) if node.start == node.end => {
+                format!(" {}", render_flow(value))
+            }
+            (NodeKind::Scalar(_, style), _) if !is_block(value) => {
+                render_scalar(value, *style, &self.source, node.start)
+            }
+            _ if block && !node.flow => render_block(value, node.col),
+            _ => render_flow(value),
+        };
+        Ok(Edit {
+            start,
+            end: node.end,
+            text,
+        })
+    }
+
+    /// The edit adding `key: value` to `mapping`.
+    fn insert_entry(
+        &self,
+        mapping: &Node,
+        key: &str,
+        value: &YamlValue,
+    ) -> Result<Edit, Box<dyn Error>> {
+        let pairs = match &mapping.kind {
+            NodeKind::Mapping(pairs) => pairs,
+            _ => return Err("Can't add a key to a non-mapping YAML value".into()),
+        };
+        if mapping.flow {
+            let separator = if pairs.is_empty() { "" } else { ", " };
+            let key = render_scalar(&YamlValue::from(key), TScalarStyle::Plain, "", 0);
+            return Ok(Edit {
+                start: mapping.end - 1,
+                end: mapping.end - 1,
+                text: format!("{}{}: {}", separator, key, render_flow(value)),
+            });
+        }
+        if pairs.is_empty() {
+            return Err("Can't add a key to an empty YAML document in place".into());
+        }
+        let at = line_end(&self.source, mapping.end);
+        Ok(Edit {
+            start: at,
+            end: at,
+            text: format!(
+                "\n{}{}",
+                " ".repeat(mapping.col),
+                render_entry(key, value, mapping.col)
+            ),
+        })
+    }
+
+    /// The edit adding `value` at the end of `sequence`.
+    fn push_item(&self, sequence: &Node, value: &YamlValue) -> Result<Edit, Box<dyn Error>> {
+        let items = match &sequence.kind {
+            NodeKind::Sequence(items) => items,
+            _ => return Err("Can't append to a non-sequence YAML value".into()),
+        };
+        if sequence.flow {
+            let separator = if items.is_empty() { "" } else { ", " };
+            return Ok(Edit {
+                start: sequence.end - 1,
+                end: sequence.end - 1,
+                text: format!("{}{}", separator, render_flow(value)),
+            });
+        }
+        let at = line_end(&self.source, sequence.end);
+        Ok(Edit {
+            start: at,
+            end: at,
+            text: format!(
+                "\n{}- {}",
+                " ".repeat(sequence.col),
+                render_block(value, sequence.col + 2)
+            ),
+        })
+    }
+
+    /// The edit removing the whole lines from `start` to `end`, which must not share
+    /// their lines with other nodes.
+    fn remove_lines(&self, start: usize, end: usize) -> Result<Edit, Box<dyn Error>> {
+        let first = line_start(&self.source, start);
+        let last = line_end(&self.source, end);
+        let after = self.source[end..last].trim();
+        if !self.source[first..start].trim().is_empty()
+            || !(after.is_empty() || after.starts_with('#'))
+        {
+            return Err("Can't delete a YAML value sharing its line in place".into());
+        }
+        Ok(Edit {
+            start: first,
+            end: (last + 1).min(self.source.len()),
+            text: "".to_string(),
+        })
+    }
+
+    /// The document `document`.
+    fn document(&self, document: usize) -> Result<&Node, Box<dyn Error>> {
+        self.documents
+            .get(document)
+            .ok_or_else(|| format!("No YAML document {}", document).into())
+    }
+
+    /// The edit creating the `missing` segments of a path under `node`, and setting `value`
+    /// at their end.
+    fn create(
+        &self,
+        node: &Node,
+        parent: Option<&Node>,
+        missing: &[PathSegment],
+        value: &YamlValue,
+    ) -> Result<Edit, Box<dyn Error>> {
+        let (segment, rest) = missing.split_first().ok_or("Nothing to create in YAML")?;
+        match (segment, &node.kind) {
+            (PathSegment::Key(key), NodeKind::Mapping(_)) => {
+                self.insert_entry(node, key, &nest(rest, value)?)
+            }
+            (PathSegment::Index(index), NodeKind::Sequence(items)) if *index == items.len() => {
+                self.push_item(node, &nest(rest, value)?)
+            }
+            (PathSegment::Index(index), NodeKind::Sequence(_)) => {
+                Err(format!("Index {} out of bounds", index).into())
+            }
+            (PathSegment::Append, NodeKind::Sequence(_)) => {
+                self.push_item(node, &nest(rest, value)?)
+            }
+            // A null, such as `key:`, becomes `key: {nested: value}`
+            (_, NodeKind::Scalar(s, TScalarStyle::Plain)) if Yaml::from_str(s).is_null() => {
+                self.replace(node, parent, &nest(missing, value)?)
+            }
+            (segment, _) => {
+                Err(format!("Can't create {:?} in a scalar YAML value", segment).into())
+            }
+        }
+    }
+
+    /// Set the values matching `segments` in the document `document`, creating the
+    /// missing ones.
+    fn set_path(
+        &mut self,
+        document: usize,
+        segments: &[PathSegment],
+        value: &YamlValue,
+    ) -> Result<usize, Box<dyn Error>> {
+        let mut targets = Vec::new();
+        walk(self.document(document)?, None, segments, &mut targets);
+        let edits = targets
+            .into_iter()
+            .map(|target| match target {
+                Target::Found(node, parent) => self.replace(node, parent, value),
+                Target::Missing(node, parent, missing) => self.create(node, parent, missing, value),
+            })
+            .collect::<Result<Vec<Edit>, Box<dyn Error>>>()?;
+        self.apply(edits)
+    }
+
+    /// Set the values matching `path` in the document `document`, creating missing keys.
+    /// An empty or null document is taken as an empty mapping.
+    /// Returns the number of edits.
+    pub fn set(
+        &mut self,
+        document: usize,
+        path: &str,
+        value: &YamlValue,
+    ) -> Result<usize, Box<dyn Error>> {
+        self.set_path(document, &parse_yaml_path(path)?, value)
+    }
+
+    /// Append `value` to the sequences matching `path` in the document `document`,
+    /// creating the sequence if missing. Returns the number of edits.
+    pub fn append(
+        &mut self,
+        document: usize,
+        path: &str,
+        value: &YamlValue,
+    ) -> Result<usize, Box<dyn Error>> {
+        let mut segments = parse_yaml_path(path)?;
+        segments.push(PathSegment::Append);
+        self.set_path(document, &segments, value)
+    }
+
+    /// Delete the values matching `path` in the document `document`, with their key or
+    /// list marker. Returns the number of values deleted.
+    pub fn delete(&mut self, document: usize, path: &str) -> Result<usize, Box<dyn Error>> {
+        let segments = parse_yaml_path(path)?;
+        let mut targets = Vec::new();
+        walk(self.document(document)?, None, &segments, &mut targets);
+        let mut edits = Vec::new();
+        for target in targets {
+            let (node, parent) = match target {
+                Target::Found(node, parent) => (node, parent),
+                Target::Missing(..) => continue,
+            };
+            let parent = parent.ok_or("Can't delete a whole YAML document")?;
+            if parent.flow {
+                return Err("Can't delete from a flow collection in place".into());
+            }
+            let start = match &parent.kind {
+                NodeKind::Mapping(pairs) => pairs
+                    .iter()
+                    .find(|(_, value)| std::ptr::eq(value, node))
+                    .map(|(key, _)| key.start)
+                    .unwrap(),
+                // The `-` marker precedes the item on its line
+                _ => self.source[..node.start]
+                    .rfind('-')
+                    .ok_or("Missing YAML list marker")?,
+            };
+            edits.push(self.remove_lines(start, node.end)?);
+        }
+        self.apply(edits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUES: &str = "# Helm values\nimage:\n  repository: 'api' # the image\n  tag: \"1.0\"\nreplicas: 1\nresources:\ndefaults: &defaults\n  timeout: 30\nservice:\n  <<: *defaults\n  ports: [80]\nargs:\n  - --verbose\n  - --port=80\nscript: |\n  echo hello\n  echo world\nlast: true\n";
+
+    fn patch(f: impl Fn(&mut PatchableYaml) -> Result<usize, Box<dyn Error>>) -> String {
+        let mut yaml = PatchableYaml::parse(VALUES).unwrap();
+        f(&mut yaml).unwrap();
+        yaml.source().to_string()
+    }
+
+    #[test]
+    fn test_set_scalars() {
+        assert_eq!(
+            patch(|yaml| yaml.set(0, "image.repository", &YamlValue::from("web"))),
+            VALUES.replace("'api' # the image", "'web' # the image")
+        );
+        assert_eq!(
+            patch(|yaml| yaml.set(0, "image.tag", &YamlValue::from("2.0"))),
+            VALUES.replace("\"1.0\"", "\"2.0\"")
+        );
+        assert_eq!(
+            patch(|yaml| yaml.set(0, "replicas", &parse_yaml_value("3"))),
+            VALUES.replace("replicas: 1", "replicas: 3")
+        );
+        assert_eq!(
+            patch(|yaml| yaml.set(0, "last", &YamlValue::from("true"))),
+            VALUES.replace("last: true", "last: \"true\"")
+        );
+        assert_eq!(
+            patch(|yaml| yaml.set(0, "resources", &parse_yaml_value("{cpu: 1}"))),
+            VALUES.replace("resources:\n", "resources:\n  cpu: 1\n")
+        );
+        assert_eq!(
+            patch(|yaml| yaml.set(0, "replicas", &parse_yaml_value("{min: 1, max: 3}"))),
+            VALUES.replace("replicas: 1\n", "replicas:\n  min: 1\n  max: 3\n")
+        );
+        assert_eq!(
+            patch(|yaml| yaml.set(0, "args[0]", &parse_yaml_value("{name: a}"))),
+            VALUES.replace("  - --verbose\n", "  - name: a\n")
+        );
+        assert_eq!(
+            patch(|yaml| yaml.set(0, "service.ports[0]", &parse_yaml_value("{port: 80}"))),
+            VALUES.replace("[80]", "[{\"port\":80}]")
+        );
+        assert_eq!(
+            patch(|yaml| yaml.set(0, "script", &YamlValue::from("echo bye\n"))),
+            VALUES.replace("  echo hello\n  echo world\n", "  echo bye\n")
+        );
+        assert_eq!(
+            patch(|yaml| yaml.set(0, "args[*]", &YamlValue::from("-q"))),
+            VALUES.replace("--verbose", "-q").replace("--port=80", "-q")
+        );
+        let mut yaml = PatchableYaml::parse(VALUES).unwrap();
+        assert!(yaml.set(0, "service.<<", &YamlValue::from(1)).is_err());
+    }
+
+    #[test]
+    fn test_empty_documents() {
+        for (source, expected) in [
+            ("", "a:\n  b: 1\n"),
+            ("# empty", "# empty\na:\n  b: 1\n"),
+            ("null\n", "a:\n  b: 1\n"),
+            ("---\n~\n", "---\na:\n  b: 1\n"),
+        ] {
+            let mut yaml = PatchableYaml::parse(source).unwrap();
+            assert_eq!(yaml.set(0, "a.b", &YamlValue::from(1)).unwrap(), 1);
+            assert_eq!(yaml.source(), expected);
+        }
+        let mut yaml = PatchableYaml::parse("").unwrap();
+        yaml.append(0, "args", &YamlValue::from("-q")).unwrap();
+        assert_eq!(yaml.source(), "args:\n  - \"-q\"\n");
+    }
+
+    #[test]
+    fn test_values() {
+        let source =
+            "a: &one 1\nb: *one\nc: [0x10, '3', 1.5, null, ~]\nd: |\n  x\n---\nkind: Service\n";
+        let yaml = PatchableYaml::parse(source).unwrap();
+        let expected = serde_yaml::Deserializer::from_str(source)
+            .map(|document| serde::Deserialize::deserialize(document).unwrap())
+            .collect::<Vec<YamlValue>>();
+        assert_eq!(yaml.values(), expected);
+    }
+
+    #[test]
+    fn test_create_append_and_delete() {
+        assert_eq!(
+            patch(|yaml| yaml.set(0, "image.pullPolicy", &YamlValue::from("Always"))),
+            VALUES.replace("  tag: \"1.0\"\n", "  tag: \"1.0\"\n  pullPolicy: Always\n")
+        );
+        assert_eq!(
+            patch(|yaml| yaml.set(0, "ingress.hosts[0]", &YamlValue::from("a.com"))),
+            format!("{}ingress:\n  hosts:\n    - a.com\n", VALUES)
+        );
+        assert_eq!(
+            patch(|yaml| yaml.append(0, "args", &YamlValue::from("--debug"))),
+            VALUES.replace("  - --port=80\n", "  - --port=80\n  - --debug\n")
+        );
+        assert_eq!(
+            patch(|yaml| yaml.append(0, "service.ports", &YamlValue::from(443))),
+            VALUES.replace("[80]", "[80, 443]")
+        );
+        assert_eq!(
+            patch(|yaml| yaml.delete(0, "image.repository")),
+            VALUES.replace("  repository: 'api' # the image\n", "")
+        );
+        assert_eq!(
+            patch(|yaml| yaml.delete(0, "args[0]")),
+            VALUES.replace("  - --verbose\n", "")
+        );
+        assert_eq!(
+            patch(|yaml| yaml.delete(0, "script")),
+            VALUES.replace("script: |\n  echo hello\n  echo world\n", "")
+        );
+    }
+}
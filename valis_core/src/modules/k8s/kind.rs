@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 
 use super::super::core;
+use crate::modules::{config, log};
 
 // use crate::modules::script::engine::FromLuaTable;
 
@@ -31,27 +32,33 @@ pub struct KindConfig {
 
 /// `default` is a function that returns a default [`KindConfig`] struct.
 /// The default [`KindConfig`] struct has the following values:
-/// version: Default is "1.22.15"
+/// version: The `kind.version` setting, "1.22.15" by default
 /// config: None
-/// context: "kind"
+/// context: The `kind.context` setting, "kind" by default
 impl Default for KindConfig {
     fn default() -> Self {
+        let settings = &config::get().kind;
         KindConfig {
-            version: "1.22.15".to_owned(),
+            version: settings.version.clone(),
             config: None,
-            context: "kind".to_owned(),
+            context: settings.context.clone(),
         }
     }
 }
 
 /// `start` is a function that starts a kind cluster by providing the [`KindConfig`] struct.
 pub fn start(config: KindConfig) {
-    let mut command = "kind create cluster".to_owned();
-    command.push_str(&format!(" --image=kindest/node:v{}", config.version));
+    let mut command = core::Command::new("kind").args(&[
+        "create",
+        "cluster",
+        &format!("--image=kindest/node:v{}", config.version),
+    ]);
 
-    if config.config.is_some() {
-        command.push_str(&format!("--config {:?}", config.config))
+    if let Some(config_path) = &config.config {
+        command = command.arg("--config").arg(&config_path.to_string_lossy());
     }
 
-    core::run(&command);
+    if let Err(e) = command.status().and_then(|output| output.check(&command)) {
+        log::warn(&format!("Could not start the kind cluster: {}", e));
+    }
 }
@@ -1,5 +1,344 @@
+//! Levelled logging, to the console and optionally to a file, as text or as JSON lines.
+//!
+//! Messages logged while a [`Span`] is entered, e.g. during a Humble build, are tagged with
+//! the span's name and fields.
+//!
+//! ```
+//! use valis_core::modules::log::{self, Format, Level, LogConfig};
+//!
+//! log::init(LogConfig {
+//!     level: Level::Debug,
+//!     format: Format::Json,
+//!     ..Default::default()
+//! })
+//! .unwrap();
+//! let _span = log::span("backup", &[("bucket", "photos")]);
+//! log::info("snapshot created");
+//! ```
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use chrono::Utc;
 use colored::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+/// The severity of a message, from the most to the least severe.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    #[serde(alias = "warning")]
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Level, String> {
+        match s.to_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" | "warning" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!("Unknown log level: {}", s)),
+        }
+    }
+}
+
+/// How messages are written: as text for people, or as JSON lines for machines.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("Unknown log format: {}", s)),
+        }
+    }
+}
+
+/// The logging configuration.
+/// - `level`: The least severe level logged, `Info` by default.
+/// - `format`: The format of the messages, `Text` by default.
+/// - `file`: A file the messages are also appended to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub level: Level,
+    pub format: Format,
+    pub file: Option<PathBuf>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: Level::Info,
+            format: Format::Text,
+            file: None,
+        }
+    }
+}
+
+impl LogConfig {
+    /// Set the level from the `--quiet` and `--verbose` flags: only warnings and errors
+    /// when quiet, debug messages with one `-v` and trace messages with two. The level is
+    /// kept without either flag.
+    pub fn with_verbosity(mut self, quiet: bool, verbose: u64) -> LogConfig {
+        self.level = match (quiet, verbose) {
+            (true, _) => Level::Warn,
+            (false, 0) => self.level,
+            (false, 1) => Level::Debug,
+            (false, _) => Level::Trace,
+        };
+        self
+    }
+}
+
+struct Logger {
+    config: LogConfig,
+    file: Option<File>,
+}
+
+static LOGGER: Mutex<Option<Logger>> = Mutex::new(None);
+
+/// Configure logging for the whole process. Messages are logged to the console with the
+/// default configuration until then.
+pub fn init(config: LogConfig) -> io::Result<()> {
+    let file = match &config.file {
+        Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+        None => None,
+    };
+    *LOGGER.lock().unwrap_or_else(|e| e.into_inner()) = Some(Logger { config, file });
+    Ok(())
+}
+
+struct SpanData {
+    name: String,
+    fields: Vec<(String, String)>,
+}
 
+thread_local! {
+    static SPANS: RefCell<Vec<SpanData>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A span of work entered on the current thread, left when dropped. Its duration is logged
+/// at the debug level.
+pub struct Span {
+    name: String,
+    start: Instant,
+}
+
+/// Enter a span named `name`, e.g. `humble` for a site build, with fields added to the
+/// messages logged in it.
+#[must_use = "the span is left when dropped"]
+pub fn span(name: &str, fields: &[(&str, &str)]) -> Span {
+    SPANS.with(|spans| {
+        spans.borrow_mut().push(SpanData {
+            name: name.to_string(),
+            fields: fields
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        })
+    });
+    log(Level::Trace, &format!("{} started", name), &[]);
+    Span {
+        name: name.to_string(),
+        start: Instant::now(),
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let elapsed = format!("{:.3}s", self.start.elapsed().as_secs_f64());
+        log(
+            Level::Debug,
+            &format!("{} finished", self.name),
+            &[("elapsed", &elapsed)],
+        );
+        SPANS.with(|spans| spans.borrow_mut().pop());
+    }
+}
+
+fn format_text(
+    level: Level,
+    message: &str,
+    spans: &[SpanData],
+    fields: &[(String, String)],
+) -> String {
+    let mut line = match level {
+        Level::Error => format!("❌ {}", message),
+        Level::Warn => format!("⚠️ {}", message),
+        Level::Info => format!("🤖 {}, {}", "ok".green(), message),
+        Level::Debug => format!("🔍 {}", message.dimmed()),
+        Level::Trace => format!("🔬 {}", message.dimmed()),
+    };
+    if !spans.is_empty() {
+        let names = spans
+            .iter()
+            .map(|span| span.name.as_str())
+            .collect::<Vec<_>>()
+            .join(":");
+        line = format!("{} {}", format!("[{}]", names).cyan(), line);
+    }
+    for (key, value) in fields {
+        line.push_str(&format!(" {}={}", key.dimmed(), value));
+    }
+    line
+}
+
+fn format_json(
+    time: &str,
+    level: Level,
+    message: &str,
+    spans: &[SpanData],
+    fields: &[(String, String)],
+) -> String {
+    let fields = fields
+        .iter()
+        .map(|(key, value)| (key.clone(), Value::from(value.as_str())))
+        .collect::<Map<_, _>>();
+    json!({
+        "time": time,
+        "level": level.name(),
+        "message": message,
+        "spans": spans.iter().map(|span| span.name.as_str()).collect::<Vec<_>>(),
+        "fields": fields,
+    })
+    .to_string()
+}
+
+/// Log `message` at `level`, with `fields` after the fields of the entered spans.
+pub fn log(level: Level, message: &str, fields: &[(&str, &str)]) {
+    let mut guard = LOGGER.lock().unwrap_or_else(|e| e.into_inner());
+    let logger = guard.get_or_insert_with(|| Logger {
+        config: LogConfig::default(),
+        file: None,
+    });
+    if level > logger.config.level {
+        return;
+    }
+    let time = Utc::now().to_rfc3339();
+    SPANS.with(|spans| {
+        let spans = spans.borrow();
+        let fields = spans
+            .iter()
+            .flat_map(|span| span.fields.iter().cloned())
+            .chain(
+                fields
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string())),
+            )
+            .collect::<Vec<_>>();
+        let format = |colored: bool| match logger.config.format {
+            Format::Json => format_json(&time, level, message, &spans, &fields),
+            Format::Text => {
+                colored::control::set_override(colored);
+                let line = format_text(level, message, &spans, &fields);
+                colored::control::unset_override();
+                line
+            }
+        };
+        if let Some(file) = &mut logger.file {
+            let _ = writeln!(file, "{}", format(false));
+        }
+        let line = format(colored::control::SHOULD_COLORIZE.should_colorize());
+        match level {
+            Level::Error | Level::Warn => eprintln!("{}", line),
+            _ => println!("{}", line),
+        }
+    });
+}
+
+pub fn error(message: &str) {
+    log(Level::Error, message, &[]);
+}
+
+pub fn warn(message: &str) {
+    log(Level::Warn, message, &[]);
+}
+
+pub fn info(message: &str) {
+    log(Level::Info, message, &[]);
+}
+
+pub fn debug(message: &str) {
+    log(Level::Debug, message, &[]);
+}
+
+pub fn trace(message: &str) {
+    log(Level::Trace, message, &[]);
+}
+
+/// Acknowledge that something was done, e.g. `🤖 ok, cloned valis`. Logged at the info level.
 pub fn ack(message: &str) {
-    println!("🤖 {}, {}", "ok".green(), message)
+    info(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        colored::control::set_override(false);
+        let spans = vec![SpanData {
+            name: "humble".to_string(),
+            fields: vec![],
+        }];
+        let fields = vec![("page".to_string(), "Index.md".to_string())];
+        assert_eq!(
+            format_text(Level::Warn, "Embed cycle", &spans, &fields),
+            "[humble] ⚠️ Embed cycle page=Index.md"
+        );
+        let line: Value = serde_json::from_str(&format_json(
+            "2023-06-09T10:00:00+00:00",
+            Level::Info,
+            "built",
+            &spans,
+            &fields,
+        ))
+        .unwrap();
+        assert_eq!(line["level"], "info");
+        assert_eq!(line["spans"], json!(["humble"]));
+        assert_eq!(line["fields"]["page"], "Index.md");
+        colored::control::unset_override();
+    }
 }
@@ -1,10 +1,13 @@
 pub mod admin;
+pub mod config;
 pub mod core;
 pub mod db;
 pub mod formats;
 pub mod k8s;
 pub mod log;
 pub mod notes;
+pub mod plan;
+pub mod process;
 pub mod projects;
 pub mod tasks;
 pub mod script;
@@ -0,0 +1,353 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::modules::notes::markdown::{Page, WikilinkType};
+use crate::modules::notes::resolver::{LinkResolver, Resolution};
+
+/// A note of the graph.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Node {
+    /// The vault id of the page (e.g. `dir/Foo`).
+    pub id: String,
+    pub title: String,
+    /// The folder of the page, relative to the vault root (empty at the root).
+    pub folder: String,
+    /// The front-matter tags of the page.
+    pub tags: Vec<String>,
+}
+
+/// The links from a note to another, counted.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Edge {
+    pub source: String,
+    pub target: String,
+    pub count: usize,
+}
+
+/// The graph of the notes of a vault, linked by their wikilinks.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub links: Vec<Edge>,
+}
+
+/// The export formats of a [`Graph`].
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum GraphFormat {
+    Dot,
+    GraphMl,
+    Json,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "dot" | "gv" => Ok(GraphFormat::Dot),
+            "graphml" => Ok(GraphFormat::GraphMl),
+            "json" => Ok(GraphFormat::Json),
+            other => Err(format!("Unknown graph format: {}", other)),
+        }
+    }
+}
+
+/// `GraphFilter` selects a subgraph.
+/// - `tag`: Only the notes with this tag.
+/// - `folder`: Only the notes in this folder, or its subfolders.
+/// - `around`: Only the notes at most `hops` links away from this note, in any direction.
+#[derive(Debug, Default, Clone)]
+pub struct GraphFilter {
+    pub tag: Option<String>,
+    pub folder: Option<String>,
+    pub around: Option<String>,
+    pub hops: usize,
+}
+
+/// Build the graph of `pages`, with an edge for each wikilink resolving to another page.
+pub fn build_graph(resolver: &LinkResolver, pages: &[Page]) -> Graph {
+    let nodes = pages
+        .iter()
+        .map(|page| {
+            let id = resolver.id(page);
+            let folder = match id.rfind('/') {
+                Some(end) => id[..end].to_string(),
+                None => "".to_string(),
+            };
+            Node {
+                id,
+                title: page.title.to_string(),
+                folder,
+                tags: page.tags.clone(),
+            }
+        })
+        .collect();
+
+    let mut counts: BTreeMap<(String, String), usize> = BTreeMap::new();
+    for page in pages {
+        let source = resolver.id(page);
+        for link in &page.wikilinks {
+            if link.link_type != WikilinkType::TEXT || link.link.is_empty() {
+                continue;
+            }
+            if let Resolution::Resolved(target) = resolver.resolve_link(link) {
+                *counts
+                    .entry((source.to_string(), resolver.id(target)))
+                    .or_insert(0) += 1;
+            }
+        }
+    }
+    let links = counts
+        .into_iter()
+        .map(|((source, target), count)| Edge {
+            source,
+            target,
+            count,
+        })
+        .collect();
+
+    Graph { nodes, links }
+}
+
+impl Graph {
+    /// The subgraph of the nodes selected by `filter`, and the edges between them.
+    pub fn filter(&self, filter: &GraphFilter, resolver: &LinkResolver) -> Graph {
+        let mut selected = self
+            .nodes
+            .iter()
+            .filter(|node| match &filter.tag {
+                Some(tag) => node.tags.contains(tag),
+                None => true,
+            })
+            .filter(|node| match &filter.folder {
+                Some(folder) => {
+                    let folder = folder.trim_matches('/');
+                    folder.is_empty()
+                        || node.folder == folder
+                        || node.folder.starts_with(&format!("{}/", folder))
+                }
+                None => true,
+            })
+            .map(|node| node.id.to_string())
+            .collect::<BTreeSet<String>>();
+
+        if let Some(around) = &filter.around {
+            let start = match resolver.resolve(around) {
+                Resolution::Resolved(page) => resolver.id(page),
+                _ => return Graph::default(),
+            };
+            selected = selected
+                .intersection(&self.neighbourhood(&start, filter.hops))
+                .cloned()
+                .collect();
+        }
+
+        Graph {
+            nodes: self
+                .nodes
+                .iter()
+                .filter(|node| selected.contains(&node.id))
+                .cloned()
+                .collect(),
+            links: self
+                .links
+                .iter()
+                .filter(|edge| selected.contains(&edge.source) && selected.contains(&edge.target))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// The ids of the nodes at most `hops` edges away from `start`, in any direction.
+    fn neighbourhood(&self, start: &str, hops: usize) -> BTreeSet<String> {
+        let mut visited = BTreeSet::from([start.to_string()]);
+        let mut queue = VecDeque::from([(start.to_string(), 0)]);
+        while let Some((id, distance)) = queue.pop_front() {
+            if distance == hops {
+                continue;
+            }
+            for edge in &self.links {
+                let next = if edge.source == id {
+                    &edge.target
+                } else if edge.target == id {
+                    &edge.source
+                } else {
+                    continue;
+                };
+                if visited.insert(next.to_string()) {
+                    queue.push_back((next.to_string(), distance + 1));
+                }
+            }
+        }
+        visited
+    }
+
+    /// Render the graph in `format`.
+    pub fn export(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::GraphMl => self.to_graphml(),
+            GraphFormat::Json => self.to_json(),
+        }
+    }
+
+    /// Render the graph in Graphviz DOT.
+    pub fn to_dot(&self) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut dot = "digraph notes {\n".to_string();
+        for node in &self.nodes {
+            dot.push_str(&format!(
+                "  {} [label={}, folder={}, tags={}];\n",
+                quote(&node.id),
+                quote(&node.title),
+                quote(&node.folder),
+                quote(&node.tags.join(","))
+            ));
+        }
+        for edge in &self.links {
+            dot.push_str(&format!(
+                "  {} -> {} [weight={}];\n",
+                quote(&edge.source),
+                quote(&edge.target),
+                edge.count
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Render the graph in GraphML.
+    pub fn to_graphml(&self) -> String {
+        let escape = |s: &str| {
+            s.replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+        };
+        let mut graphml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n  <key id=\"title\" for=\"node\" attr.name=\"title\" attr.type=\"string\"/>\n  <key id=\"folder\" for=\"node\" attr.name=\"folder\" attr.type=\"string\"/>\n  <key id=\"tags\" for=\"node\" attr.name=\"tags\" attr.type=\"string\"/>\n  <key id=\"count\" for=\"edge\" attr.name=\"count\" attr.type=\"int\"/>\n  <graph id=\"notes\" edgedefault=\"directed\">\n".to_string();
+        for node in &self.nodes {
+            graphml.push_str(&format!(
+                "    <node id=\"{}\">\n      <data key=\"title\">{}</data>\n      <data key=\"folder\">{}</data>\n      <data key=\"tags\">{}</data>\n    </node>\n",
+                escape(&node.id),
+                escape(&node.title),
+                escape(&node.folder),
+                escape(&node.tags.join(","))
+            ));
+        }
+        for edge in &self.links {
+            graphml.push_str(&format!(
+                "    <edge source=\"{}\" target=\"{}\">\n      <data key=\"count\">{}</data>\n    </edge>\n",
+                escape(&edge.source),
+                escape(&edge.target),
+                edge.count
+            ));
+        }
+        graphml.push_str("  </graph>\n</graphml>\n");
+        graphml
+    }
+
+    /// Render the graph as a JSON `{nodes, links}` object, as used by d3-force.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("the graph serialises to JSON")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::modules::notes::markdown::{extract_links, tags_from_front_matter, PageLoader};
+
+    fn page(path: &str, contents: &str) -> Page {
+        let path = PathBuf::from(path);
+        Page {
+            title: Page::title_from_path(&path),
+            path,
+            contents: contents.to_string(),
+            wikilinks: extract_links(contents),
+            aliases: vec![],
+            tags: tags_from_front_matter(contents),
+        }
+    }
+
+    fn pages() -> Vec<Page> {
+        vec![
+            page(
+                "/vault/A.md",
+                "---\ntags: [team]\n---\n[[B]] [[B#x]] [[pic.png]]",
+            ),
+            page("/vault/docs/B.md", "---\ntags: [team]\n---\n[[C]]"),
+            page("/vault/docs/C.md", "[[D]]"),
+            page("/vault/D.md", ""),
+        ]
+    }
+
+    #[test]
+    fn test_build_graph() {
+        let pages = pages();
+        let resolver = LinkResolver::new(Path::new("/vault"), &pages);
+        let graph = build_graph(&resolver, &pages);
+        assert_eq!(graph.nodes.len(), 4);
+        assert_eq!(graph.nodes[1].folder, "docs");
+        assert_eq!(
+            graph.links[0],
+            Edge {
+                source: "A".to_string(),
+                target: "docs/B".to_string(),
+                count: 2
+            }
+        );
+        assert_eq!(graph.links.len(), 3);
+        assert!(graph
+            .to_dot()
+            .contains("  \"A\" -> \"docs/B\" [weight=2];\n"));
+        assert!(graph
+            .to_graphml()
+            .contains("<data key=\"tags\">team</data>"));
+        assert!(graph.to_json().contains("\"links\""));
+    }
+
+    #[test]
+    fn test_filter() {
+        let pages = pages();
+        let resolver = LinkResolver::new(Path::new("/vault"), &pages);
+        let graph = build_graph(&resolver, &pages);
+        let ids = |graph: Graph| {
+            graph
+                .nodes
+                .into_iter()
+                .map(|node| node.id)
+                .collect::<Vec<String>>()
+        };
+
+        let tag = GraphFilter {
+            tag: Some("team".to_string()),
+            ..Default::default()
+        };
+        let tagged = graph.filter(&tag, &resolver);
+        assert_eq!(tagged.links.len(), 1);
+        assert_eq!(ids(tagged), vec!["A", "docs/B"]);
+
+        let folder = GraphFilter {
+            folder: Some("docs".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(graph.filter(&folder, &resolver)),
+            vec!["docs/B", "docs/C"]
+        );
+
+        let around = GraphFilter {
+            around: Some("B".to_string()),
+            hops: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(graph.filter(&around, &resolver)),
+            vec!["A", "docs/B", "docs/C"]
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::error::Error;
 use std::fs;
 
 use std::path::{Path, PathBuf};
@@ -6,24 +7,49 @@ use std::path::{Path, PathBuf};
 use indicatif::{ProgressBar, ProgressStyle};
 use regex::Regex;
 
-use crate::modules::notes::markdown;
-use crate::modules::notes::markdown::{Page, PageLoader, WikilinkType};
+use crate::modules::log;
+use crate::modules::notes::markdown::{extract_links, OutputTarget, Page, WikilinkType};
+use crate::modules::notes::publish::{is_published, PublishPolicy, Redaction, RedactionKind};
+use crate::modules::notes::resolver::{LinkProblem, LinkResolver, Resolution};
+use crate::modules::notes::transclusion::transclude_with;
+use crate::modules::notes::{indexes, markdown};
+use crate::modules::plan;
 
 pub fn get_pages(source: PathBuf) -> Vec<Page> {
-    let files = markdown::get_markdown_files(source).ok().unwrap();
-    let pathbufs = files
-        .into_iter()
-        .map(|p| p.ok().unwrap())
-        .collect::<Vec<PathBuf>>();
-    let pages = pathbufs
-        .into_iter()
-        .map(|path| PageLoader::from_path(&path))
-        .collect::<Vec<Page>>();
-    return pages;
+    read_pages(&source).ok().unwrap()
 }
 
-pub fn build_backlinks(pages: &[Page]) -> HashMap<String, Vec<(String, usize)>> {
-    let mut backlinks: HashMap<String, Vec<(String, usize)>> = HashMap::new();
+/// The notes of the vault `source`. The notes which can't be read, e.g. because they were
+/// deleted since being listed or aren't UTF-8, are skipped with a warning.
+pub fn read_pages(source: &Path) -> Result<Vec<Page>, Box<dyn Error>> {
+    let mut pages = Vec::new();
+    for path in markdown::get_markdown_files(source.to_path_buf())? {
+        let path = path.map_err(|e| format!("Could not list the notes: {:?}", e))?;
+        match Page::read(&path) {
+            Ok(page) => pages.push(page),
+            Err(e) => log::warn(&format!("Could not read {}: {}", path.display(), e)),
+        }
+    }
+    Ok(pages)
+}
+
+/// A link from another page, to a location in the target page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backlink {
+    /// The title of the page containing the link.
+    pub title: String,
+    /// The fragment id of the heading or block linked to (empty for the whole page).
+    pub anchor: String,
+    /// The number of links from that page to this location.
+    pub count: usize,
+}
+
+/// Build the backlinks of the vault from the links in `pages`.
+/// Backlinks are keyed by the vault id of the target page (see [`LinkResolver::id`]),
+/// and only links which resolve to a single page are counted. Links to different
+/// headings or blocks of the same page are counted as distinct backlinks.
+pub fn build_backlinks(resolver: &LinkResolver, pages: &[Page]) -> HashMap<String, Vec<Backlink>> {
+    let mut backlinks: HashMap<String, Vec<Backlink>> = HashMap::new();
 
     for page in pages {
         for link in &page.wikilinks {
@@ -32,26 +58,35 @@ pub fn build_backlinks(pages: &[Page]) -> HashMap<String, Vec<(String, usize)>>
                 continue;
             }
 
-            let target_title = &link.link;
+            // Skip empty links (i.e., just anchors), missing and ambiguous targets
+            let target = match resolver.resolve_link(link) {
+                Resolution::Resolved(target) => target,
+                _ => continue,
+            };
 
-            // Skip empty links (i.e., just anchors)
-            if target_title.is_empty() {
-                continue;
-            }
+            // Links to headings or blocks which don't exist point to the page itself
+            let anchor = match link.fragment() {
+                Some(fragment) if target.has_fragment(link) => fragment,
+                _ => "".to_string(),
+            };
 
             let title = page.title.clone();
 
             let entry = backlinks
-                .entry(target_title.clone())
+                .entry(resolver.id(target))
                 .or_insert_with(Vec::new);
             let existing_entry = entry
                 .iter_mut()
-                .find(|(ref other_title, _)| **other_title == title);
+                .find(|backlink| backlink.title == title && backlink.anchor == anchor);
 
-            if let Some((_, ref mut count)) = existing_entry {
-                *count += 1;
+            if let Some(backlink) = existing_entry {
+                backlink.count += 1;
             } else {
-                entry.push((title, 1));
+                entry.push(Backlink {
+                    title,
+                    anchor,
+                    count: 1,
+                });
             }
         }
     }
@@ -70,41 +105,44 @@ fn replace_first(input: &str, from: &str, to: &str) -> String {
 
 fn add_backlinks(
     page: &mut Page,
-    backlinks: &HashMap<String, Vec<(String, usize)>>,
+    backlinks: &HashMap<String, Vec<Backlink>>,
+    resolver: &LinkResolver,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let empty = "backlinks: []\nbacklinks_count: []\n".to_string();
+    let empty = "backlinks: []\nbacklinks_count: []\nbacklinks_anchors: []\n".to_string();
 
     // Check if this page has any backlinks
-    if let Some(links) = backlinks.get(&page.title) {
-        // Convert the Vec<(String, usize)> into separate Vec<String> and Vec<usize>
-        let (backlink_titles, backlink_counts): (Vec<String>, Vec<usize>) = links
+    if let Some(links) = backlinks.get(&resolver.id(page)) {
+        // Convert the titles, counts and anchors into a single String each, separated by commas
+        let backlink_titles_string = links
             .iter()
-            .map(|(title, count)| (title.clone(), *count))
-            .unzip();
-
-        // Convert the Vec<String> and Vec<usize> into a single String each, separated by commas
-        let backlink_titles_string = backlink_titles
-            .into_iter()
-            .map(|title| format!("\"{}\"", title))
+            .map(|backlink| format!("\"{}\"", backlink.title))
             .collect::<Vec<String>>()
             .join(", ");
-        let backlink_counts_string = backlink_counts
+        let backlink_counts_string = links
             .iter()
-            .map(|count| count.to_string())
+            .map(|backlink| backlink.count.to_string())
             .collect::<Vec<_>>()
             .join(", ");
+        let backlink_anchors_string = links
+            .iter()
+            .map(|backlink| format!("\"{}\"", backlink.anchor))
+            .collect::<Vec<String>>()
+            .join(", ");
 
         // Find the end of the first "---\n"
-        let front_matter_end = page.contents.find("---\n").unwrap() + 4;
+        let front_matter_end = page
+            .contents
+            .find("---\n")
+            .ok_or_else(|| format!("No front matter in {}", page.path.display()))?
+            + 4;
 
-        // Insert the backlinks and backlinks_count at the beginning of the page content
+        // Insert the backlinks, backlinks_count and backlinks_anchors at the beginning of the page content
         page.contents.insert_str(
             front_matter_end,
-            &format!("backlinks: [{}]\n", backlink_titles_string),
-        );
-        page.contents.insert_str(
-            front_matter_end + backlink_titles_string.len() + 14,
-            &format!("backlinks_count: [{}]\n", backlink_counts_string),
+            &format!(
+                "backlinks: [{}]\nbacklinks_count: [{}]\nbacklinks_anchors: [{}]\n",
+                backlink_titles_string, backlink_counts_string, backlink_anchors_string
+            ),
         );
     } else {
         let contents = replace_first(&page.contents, "---", &format!("---\n{}", empty));
@@ -114,38 +152,50 @@ fn add_backlinks(
     Ok(())
 }
 
-fn save_pages_to_files(pages: &[Page], dest: &PathBuf) -> std::io::Result<()> {
+/// The path of the file `page` is saved to, in the build `destination`.
+pub fn page_output_path(destination: &Path, page: &Page) -> PathBuf {
+    if page.title == "Index" {
+        destination.join(format!("{}.md", page.title))
+    } else {
+        destination.join("posts").join(format!("{}.md", page.title))
+    }
+}
+
+fn save_pages_to_files(
+    pages: &[Page],
+    dest: &Path,
+    resolver: &LinkResolver,
+    target: OutputTarget,
+) -> std::io::Result<()> {
     // Create the contents subdirectory if it doesn't exist
-    let mut contents_dir = dest.clone();
-    contents_dir.push("posts");
-    fs::create_dir_all(&contents_dir)?;
+    let contents_dir = dest.join("posts");
+    plan::create_dir_all(&contents_dir)?;
 
     for page in pages {
         if page.title == "Index" {
             // Save the Index page directly to dest
             page.title == "_index";
-            page.save_to_file(&dest)?;
+            page.save_to_file(dest, resolver, target)?;
         } else {
             // Save other pages to the contents subdirectory
-            page.save_to_file(&contents_dir)?;
+            page.save_to_file(&contents_dir, resolver, target)?;
         }
     }
 
     Ok(())
 }
 
-fn build_image_map(dir: &PathBuf, map: &mut HashMap<String, PathBuf>) -> std::io::Result<()> {
+fn build_asset_map(dir: &PathBuf, map: &mut HashMap<String, PathBuf>) -> std::io::Result<()> {
     if dir.is_dir() {
         for entry in fs::read_dir(dir)? {
             let entry = entry?;
             let path = entry.path();
             if path.is_dir() {
-                build_image_map(&path, map)?;
-            } else if let Some(extension) = path.extension() {
-                if ["png", "jpg", "gif", "svg"].contains(&extension.to_str().unwrap()) {
-                    if let Some(filename) = path.file_name() {
-                        map.insert(filename.to_str().unwrap().to_string(), path.clone());
-                    }
+                build_asset_map(&path, map)?;
+            } else if let Some(filename) = path.file_name().and_then(|f| f.to_str()) {
+                // Images, PDFs and audio files
+                if markdown::filename_type(filename) != WikilinkType::TEXT {
+                    map.insert(filename.to_string(), path.clone());
                 }
             }
         }
@@ -153,44 +203,121 @@ fn build_image_map(dir: &PathBuf, map: &mut HashMap<String, PathBuf>) -> std::io
     Ok(())
 }
 
-fn create_image_map(source_dir: &str) -> Result<HashMap<String, PathBuf>, std::io::Error> {
-    let mut image_map: HashMap<String, PathBuf> = HashMap::new();
+fn create_asset_map(source_dir: &str) -> Result<HashMap<String, PathBuf>, std::io::Error> {
+    let mut asset_map: HashMap<String, PathBuf> = HashMap::new();
     let source_path = PathBuf::from(source_dir);
-    build_image_map(&source_path, &mut image_map)?;
-    Ok(image_map)
+    build_asset_map(&source_path, &mut asset_map)?;
+    Ok(asset_map)
 }
 
-fn copy_images_from_page(
+/// Whether `destination` is a copy of `source` at least as recent as it.
+fn is_up_to_date(source: &Path, destination: &Path) -> bool {
+    match (fs::metadata(source), fs::metadata(destination)) {
+        (Ok(source), Ok(destination)) => {
+            source.len() == destination.len()
+                && match (source.modified(), destination.modified()) {
+                    (Ok(source), Ok(destination)) => destination >= source,
+                    _ => false,
+                }
+        }
+        _ => false,
+    }
+}
+
+fn copy_assets_from_page(
     page: &Page,
-    image_map: &HashMap<String, PathBuf>,
+    asset_map: &HashMap<String, PathBuf>,
     destination: &str,
 ) -> std::io::Result<()> {
-    let image_link_pattern = Regex::new(r"!\[\[(.*?)\]\]").unwrap();
-    let image_links: Vec<String> = image_link_pattern
+    let asset_link_pattern = Regex::new(r"!\[\[(.*?)\]\]").unwrap();
+    let asset_links: Vec<String> = asset_link_pattern
         .captures_iter(&page.contents)
         .filter_map(|cap| cap.get(1))
-        .map(|m| m.as_str().to_string())
+        .filter_map(|m| markdown::parse_wikilink(m.as_str()).ok())
+        .filter(|wikilink| wikilink.link_type != WikilinkType::TEXT)
+        .map(|wikilink| wikilink.link)
         .collect();
 
-    for link in image_links {
-        if let Some(image_path) = image_map.get(&link) {
+    for link in asset_links {
+        let filename = Path::new(&link)
+            .file_name()
+            .and_then(|f| f.to_str())
+            .unwrap_or(&link);
+        if let Some(asset_path) = asset_map.get(filename) {
             let destination_path = Path::new(destination).join(&link);
+            if is_up_to_date(asset_path, &destination_path) {
+                continue;
+            }
             if let Some(parent_dir) = destination_path.parent() {
-                fs::create_dir_all(parent_dir)?; // create all directories in the path if they don't exist
+                plan::create_dir_all(parent_dir)?; // create all directories in the path if they don't exist
             }
-            fs::copy(image_path, &destination_path)?;
+            plan::copy(asset_path, &destination_path)?;
         }
     }
     Ok(())
 }
 
-/// Build a site using Humble.
+/// The result of a Humble build.
+pub struct Site {
+    /// The published pages, as saved.
+    pub pages: Vec<Page>,
+    /// The backlinks of each page, keyed by vault id.
+    pub backlinks: HashMap<String, Vec<Backlink>>,
+    /// The vault ids of the pages embedded in each page, keyed by vault id.
+    /// A page needs to be rebuilt when any of its dependencies changes.
+    pub dependencies: HashMap<String, Vec<String>>,
+    /// What was removed from the published pages.
+    pub redactions: Vec<Redaction>,
+}
+
+/// `HumbleConfig` holds the options of a Humble build.
+/// - `target`: The format of the published pages. Default is Hugo.
+/// - `title`: The title of the site, used in the feeds.
+/// - `base_url`: The absolute URL of the published site (e.g. `https://example.com`).
+///   The RSS/Atom feeds and the sitemap are only generated when it is set.
+/// - `static_dir`: Where to write `rss.xml`, `atom.xml` and `sitemap.xml`
+///   (e.g. Hugo's `static` directory). Defaults to the build destination.
+/// - `feed_size`: The maximum number of posts in the feeds.
+pub struct HumbleConfig {
+    pub target: OutputTarget,
+    pub title: String,
+    pub base_url: Option<String>,
+    pub static_dir: Option<PathBuf>,
+    pub feed_size: usize,
+}
+
+impl Default for HumbleConfig {
+    fn default() -> Self {
+        HumbleConfig {
+            target: OutputTarget::Hugo,
+            title: "Humble".to_owned(),
+            base_url: None,
+            static_dir: None,
+            feed_size: 20,
+        }
+    }
+}
+
+/// Build a site using Humble, with the default [`HumbleConfig`].
 /// Reads markdown files from `source` and processes them into `destination`.
 pub fn build(
     source: PathBuf,
     destination: PathBuf,
     assets: PathBuf,
-) -> (Vec<Page>, HashMap<String, Vec<(String, usize)>>) {
+) -> Result<Site, Box<dyn Error>> {
+    build_with_config(source, destination, assets, &HumbleConfig::default())
+}
+
+/// Build a site using Humble.
+/// Reads markdown files from `source` and processes them into `destination`,
+/// followed by the tag pages, feeds and sitemap.
+pub fn build_with_config(
+    source: PathBuf,
+    destination: PathBuf,
+    assets: PathBuf,
+    config: &HumbleConfig,
+) -> Result<Site, Box<dyn Error>> {
+    let _span = log::span("humble", &[("source", &source.display().to_string())]);
     let search_markdown_spinner = ProgressBar::new_spinner();
     search_markdown_spinner.set_style(
         ProgressStyle::default_spinner()
@@ -199,7 +326,7 @@ pub fn build(
     );
     search_markdown_spinner.enable_steady_tick(100);
 
-    let files = get_pages(source.clone());
+    let files = read_pages(&source)?;
 
     search_markdown_spinner.finish_with_message("Finished searching for Markdown files.");
 
@@ -211,11 +338,24 @@ pub fn build(
     );
     filter_publishable_spinner.enable_steady_tick(100);
 
+    // Links are resolved against the whole vault, not only the publishable pages
+    let resolver = LinkResolver::new(&source, &files);
+
+    let policy = PublishPolicy::new(&resolver);
+    let mut redactions: Vec<Redaction> = Vec::new();
+
+    // Private content is scrubbed before anything else, so that it can't leak through
+    // backlinks or assets
     let pages = files
-        .into_iter()
-        .filter(|page| {
-            let mut lines = page.contents.split("\n");
-            lines.any(|line| line.starts_with("publish: true"))
+        .iter()
+        .filter(|page| is_published(page))
+        .map(|page| {
+            let (contents, page_redactions) = policy.scrub(page, &page.contents, &resolver);
+            redactions.extend(page_redactions);
+            let mut page = page.clone();
+            page.wikilinks = extract_links(&contents);
+            page.contents = contents;
+            page
         })
         .collect::<Vec<Page>>();
 
@@ -229,44 +369,121 @@ pub fn build(
     );
     backlinks_spinner.enable_steady_tick(100);
 
-    let backlinks = build_backlinks(&pages);
+    let backlinks = build_backlinks(&resolver, &pages);
 
     backlinks_spinner.finish_with_message("Finished searching publishable.");
 
-    let updated_pages = pages
-        .into_iter()
-        .map(|mut page| {
-            add_backlinks(&mut page, &backlinks).ok().unwrap();
-            page
-        })
-        .collect::<Vec<Page>>();
+    for broken in resolver.broken_links(&pages) {
+        let problem = match broken.problem {
+            LinkProblem::Missing => "Broken link".to_string(),
+            LinkProblem::Ambiguous(candidates) => format!(
+                "Ambiguous link ({})",
+                candidates
+                    .iter()
+                    .map(|c| c.display().to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            LinkProblem::MissingHeading(heading) => format!("Missing heading '{}'", heading),
+            LinkProblem::MissingBlock(block) => format!("Missing block '^{}'", block),
+        };
+        log::warn(&format!(
+            "{}: [[{}]] in {}",
+            problem,
+            broken.link.link,
+            broken.source.display()
+        ));
+    }
+
+    let mut dependencies: HashMap<String, Vec<String>> = HashMap::new();
+
+    let mut updated_pages = Vec::new();
+    for mut page in pages {
+        // Inline the embedded published notes, and scrub the private content they bring
+        let transclusion = transclude_with(&page, &resolver, &|target| policy.allows(target));
+        for cycle in &transclusion.cycles {
+            log::warn(&format!(
+                "Embed cycle: {} in {}",
+                cycle,
+                page.path.display()
+            ));
+        }
+        dependencies.insert(resolver.id(&page), transclusion.dependencies);
+        let (contents, page_redactions) = policy.scrub(&page, &transclusion.contents, &resolver);
+        redactions.extend(page_redactions);
+        page.contents = contents;
 
-    save_pages_to_files(&updated_pages, &destination)
-        .ok()
-        .unwrap();
+        add_backlinks(&mut page, &backlinks, &resolver)?;
+        updated_pages.push(page);
+    }
 
-    let copy_images_spinner = ProgressBar::new_spinner();
-    copy_images_spinner.set_style(
+    save_pages_to_files(&updated_pages, &destination, &resolver, config.target)?;
+
+    let copy_assets_spinner = ProgressBar::new_spinner();
+    copy_assets_spinner.set_style(
         ProgressStyle::default_spinner()
             .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏")
-            .template("{spinner} Copying images..."),
+            .template("{spinner} Copying assets..."),
     );
-    copy_images_spinner.enable_steady_tick(100);
+    copy_assets_spinner.enable_steady_tick(100);
 
-    let image_map = create_image_map(source.clone().to_str().unwrap())
-        .ok()
-        .unwrap();
+    let asset_map = create_asset_map(&source.to_string_lossy())?;
 
-    let saved_pages = updated_pages
-        .into_iter()
-        .map(|page| {
-            copy_images_from_page(&page, &image_map, assets.to_str().unwrap())
-                .ok()
-                .unwrap();
-            page
-        })
-        .collect::<Vec<Page>>();
+    for page in &updated_pages {
+        copy_assets_from_page(page, &asset_map, &assets.to_string_lossy())?;
+    }
+    let saved_pages = updated_pages;
+
+    copy_assets_spinner.finish_with_message("Finished copying assets.");
+
+    let indexes_spinner = ProgressBar::new_spinner();
+    indexes_spinner.set_style(
+        ProgressStyle::default_spinner()
+            .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏")
+            .template("{spinner} Generating tag pages, feeds and sitemap..."),
+    );
+    indexes_spinner.enable_steady_tick(100);
+
+    let tag_paths = indexes::save_tag_pages(&saved_pages, &destination, config.target)?;
+
+    if let Some(base_url) = &config.base_url {
+        let static_dir = config.static_dir.clone().unwrap_or(destination.clone());
+        plan::create_dir_all(&static_dir)?;
+        let posts = indexes::recent_posts(&saved_pages, config.feed_size);
+        plan::write(
+            static_dir.join("rss.xml"),
+            indexes::rss_feed(&config.title, base_url, config.target, &posts),
+        )?;
+        plan::write(
+            static_dir.join("atom.xml"),
+            indexes::atom_feed(&config.title, base_url, config.target, &posts),
+        )?;
+        plan::write(
+            static_dir.join("sitemap.xml"),
+            indexes::sitemap(base_url, config.target, &saved_pages, &tag_paths),
+        )?;
+    }
+
+    indexes_spinner.finish_with_message("Finished generating tag pages, feeds and sitemap.");
+
+    for redaction in &redactions {
+        let redacted = match &redaction.kind {
+            RedactionKind::Link(title) => format!("link to unpublished page '{}'", title),
+            RedactionKind::Embed(title) => format!("embed of unpublished page '{}'", title),
+            RedactionKind::Comment => "comment".to_string(),
+            RedactionKind::PrivateCallout => "private callout".to_string(),
+        };
+        log::info(&format!(
+            "Redacted {} in {}",
+            redacted,
+            redaction.source.display()
+        ));
+    }
 
-    copy_images_spinner.finish_with_message("Finished copying images.");
-    (saved_pages, backlinks)
+    Ok(Site {
+        pages: saved_pages,
+        backlinks,
+        dependencies,
+        redactions,
+    })
 }
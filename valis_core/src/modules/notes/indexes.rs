@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde_yaml::Value as YamlValue;
+
+use crate::modules::notes::markdown::{front_matter, slugify, OutputTarget, Page};
+use crate::modules::plan;
+
+/// Read the `date` front-matter entry of a page, as a date (`2023-06-09`),
+/// a date and time (`2023-06-09 10:00:00`) or an RFC 3339 timestamp.
+pub fn page_date(page: &Page) -> Option<DateTime<Utc>> {
+    let date = match front_matter(&page.contents)? {
+        YamlValue::Mapping(map) => map
+            .get(&YamlValue::String("date".to_string()))?
+            .as_str()?
+            .trim()
+            .to_string(),
+        _ => return None,
+    };
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(&date) {
+        return Some(datetime.with_timezone(&Utc));
+    }
+    if let Ok(datetime) = NaiveDateTime::parse_from_str(&date, "%Y-%m-%d %H:%M:%S") {
+        return Some(Utc.from_utc_datetime(&datetime));
+    }
+    NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|datetime| Utc.from_utc_datetime(&datetime))
+}
+
+/// Group the titles of `pages` by tag, sorted by tag and title.
+pub fn pages_by_tag(pages: &[Page]) -> BTreeMap<String, Vec<String>> {
+    let mut tags: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for page in pages {
+        for tag in &page.tags {
+            let titles = tags.entry(tag.to_string()).or_default();
+            if !titles.contains(&page.title) {
+                titles.push(page.title.to_string());
+            }
+        }
+    }
+    tags.values_mut().for_each(|titles| titles.sort());
+    tags
+}
+
+/// Write a listing page for each tag in `pages` to `destination`, and return their paths
+/// relative to the site root. Hugo tag pages are saved as `tags/<tag>/_index.md`,
+/// so that they are used as the taxonomy term pages.
+pub fn save_tag_pages(
+    pages: &[Page],
+    destination: &Path,
+    target: OutputTarget,
+) -> std::io::Result<Vec<String>> {
+    let mut paths = Vec::new();
+    for (tag, titles) in pages_by_tag(pages) {
+        let listing = titles
+            .iter()
+            .map(|title| format!("- {}", target.page_link(title, title, None)))
+            .collect::<Vec<String>>()
+            .join("\n");
+        let (file_path, contents) = match target {
+            OutputTarget::Hugo => (
+                destination
+                    .join("tags")
+                    .join(slugify(&tag))
+                    .join("_index.md"),
+                format!("---\ntitle: \"{}\"\n---\n{}\n", tag, listing),
+            ),
+            OutputTarget::Markdown => (
+                destination.join("tags").join(format!("{}.md", tag)),
+                format!("# {}\n\n{}\n", tag, listing),
+            ),
+        };
+        if let Some(parent_dir) = file_path.parent() {
+            plan::create_dir_all(parent_dir)?;
+        }
+        plan::write(&file_path, contents)?;
+        paths.push(target.tag_path(&tag));
+    }
+    Ok(paths)
+}
+
+/// Escape the XML special characters in `s`.
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// The dated pages, excluding the index, most recent first.
+pub fn recent_posts(pages: &[Page], size: usize) -> Vec<(&Page, DateTime<Utc>)> {
+    let mut posts = pages
+        .iter()
+        .filter(|page| page.title != "Index")
+        .filter_map(|page| page_date(page).map(|date| (page, date)))
+        .collect::<Vec<(&Page, DateTime<Utc>)>>();
+    posts.sort_by(|(a, a_date), (b, b_date)| b_date.cmp(a_date).then(a.title.cmp(&b.title)));
+    posts.truncate(size);
+    posts
+}
+
+/// Render an RSS 2.0 feed of `posts`.
+pub fn rss_feed(
+    title: &str,
+    base_url: &str,
+    target: OutputTarget,
+    posts: &[(&Page, DateTime<Utc>)],
+) -> String {
+    let base_url = base_url.trim_end_matches('/');
+    let items = posts
+        .iter()
+        .map(|(page, date)| {
+            let url = format!("{}{}", base_url, target.page_path(&page.title));
+            format!(
+                "    <item>\n      <title>{}</title>\n      <link>{}</link>\n      <guid>{}</guid>\n      <pubDate>{}</pubDate>\n    </item>\n",
+                xml_escape(&page.title),
+                xml_escape(&url),
+                xml_escape(&url),
+                date.to_rfc2822()
+            )
+        })
+        .collect::<String>();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\">\n  <channel>\n    <title>{}</title>\n    <link>{}/</link>\n    <description>{}</description>\n{}  </channel>\n</rss>\n",
+        xml_escape(title),
+        xml_escape(base_url),
+        xml_escape(title),
+        items
+    )
+}
+
+/// Render an Atom feed of `posts`.
+pub fn atom_feed(
+    title: &str,
+    base_url: &str,
+    target: OutputTarget,
+    posts: &[(&Page, DateTime<Utc>)],
+) -> String {
+    let base_url = base_url.trim_end_matches('/');
+    let updated = posts
+        .iter()
+        .map(|(_, date)| *date)
+        .max()
+        .unwrap_or_else(Utc::now);
+    let entries = posts
+        .iter()
+        .map(|(page, date)| {
+            let url = format!("{}{}", base_url, target.page_path(&page.title));
+            format!(
+                "  <entry>\n    <title>{}</title>\n    <link href=\"{}\"/>\n    <id>{}</id>\n    <updated>{}</updated>\n  </entry>\n",
+                xml_escape(&page.title),
+                xml_escape(&url),
+                xml_escape(&url),
+                date.to_rfc3339()
+            )
+        })
+        .collect::<String>();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n  <title>{}</title>\n  <link href=\"{}/\"/>\n  <id>{}/</id>\n  <updated>{}</updated>\n{}</feed>\n",
+        xml_escape(title),
+        xml_escape(base_url),
+        xml_escape(base_url),
+        updated.to_rfc3339(),
+        entries
+    )
+}
+
+/// Render a `sitemap.xml` for `pages` and the extra `paths` (e.g. tag pages).
+pub fn sitemap(base_url: &str, target: OutputTarget, pages: &[Page], paths: &[String]) -> String {
+    let base_url = base_url.trim_end_matches('/');
+    let mut urls = pages
+        .iter()
+        .map(|page| {
+            let lastmod = page_date(page)
+                .map(|date| format!("    <lastmod>{}</lastmod>\n", date.format("%Y-%m-%d")))
+                .unwrap_or_default();
+            format!(
+                "  <url>\n    <loc>{}</loc>\n{}  </url>\n",
+                xml_escape(&format!("{}{}", base_url, target.page_path(&page.title))),
+                lastmod
+            )
+        })
+        .collect::<Vec<String>>();
+    urls.extend(paths.iter().map(|path| {
+        format!(
+            "  <url>\n    <loc>{}</loc>\n  </url>\n",
+            xml_escape(&format!("{}{}", base_url, path))
+        )
+    }));
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n{}</urlset>\n",
+        urls.concat()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::modules::notes::markdown::tags_from_front_matter;
+
+    fn page(title: &str, contents: &str) -> Page {
+        Page {
+            title: title.to_string(),
+            path: PathBuf::from(format!("/vault/{}.md", title)),
+            contents: contents.to_string(),
+            wikilinks: vec![],
+            aliases: vec![],
+            tags: tags_from_front_matter(contents),
+        }
+    }
+
+    #[test]
+    fn test_recent_posts() {
+        let pages = vec![
+            page("Old", "---\ndate: 2023-01-01\ntags: [a]\n---\n"),
+            page(
+                "New",
+                "---\ndate: 2023-06-09T10:00:00Z\ntags: [a, b]\n---\n",
+            ),
+            page("Undated", "---\ntags: [b]\n---\n"),
+            page("Index", "---\ndate: 2023-07-01\n---\n"),
+        ];
+        let posts = recent_posts(&pages, 10);
+        let titles = posts
+            .iter()
+            .map(|(page, _)| page.title.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(titles, vec!["New", "Old"]);
+        assert_eq!(recent_posts(&pages, 1).len(), 1);
+
+        let tags = pages_by_tag(&pages);
+        assert_eq!(tags["a"], vec!["New", "Old"]);
+        assert_eq!(tags["b"], vec!["New", "Undated"]);
+    }
+
+    #[test]
+    fn test_feeds() {
+        let pages = vec![page("A & B", "---\ndate: 2023-01-01\n---\n")];
+        let posts = recent_posts(&pages, 10);
+        let rss = rss_feed("Garden", "https://example.com/", OutputTarget::Hugo, &posts);
+        assert!(rss.contains("<title>A &amp; B</title>"));
+        assert!(rss.contains("<link>https://example.com/posts/a--b/</link>"));
+        let atom = atom_feed("Garden", "https://example.com", OutputTarget::Hugo, &posts);
+        assert!(atom.contains("<updated>2023-01-01T00:00:00+00:00</updated>"));
+        let sitemap = sitemap(
+            "https://example.com",
+            OutputTarget::Markdown,
+            &pages,
+            &["/tags/a.md".to_string()],
+        );
+        assert!(sitemap.contains("<loc>https://example.com/posts/A%20&amp;%20B.md</loc>"));
+        assert!(sitemap.contains("<lastmod>2023-01-01</lastmod>"));
+        assert!(sitemap.contains("<loc>https://example.com/tags/a.md</loc>"));
+    }
+}
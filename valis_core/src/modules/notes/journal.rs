@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{Datelike, Duration, Local, NaiveDate};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::modules::notes::markdown::{section, slugify};
+use crate::modules::plan;
+use crate::modules::projects::agile::core::sprint_get_active;
+use crate::modules::projects::git::core::get_git_current_branch;
+
+lazy_static! {
+    static ref VARIABLE_REGEX: Regex = Regex::new(r"\{\{\s*(\w+)(?::([^}]*))?\s*\}\}").unwrap();
+}
+
+/// The period covered by a journal note.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Period {
+    Daily,
+    Weekly,
+}
+
+/// `JournalConfig` holds where notes are created.
+/// - `vault`: The root of the notes.
+/// - `daily_pattern`: The path of the daily notes, relative to the vault, formatted with
+///   the date (e.g. `journal/%Y/%Y-%m-%d.md`, see `chrono::format::strftime`).
+/// - `weekly_pattern`: The path of the weekly notes, formatted with the first day of the week.
+/// - `templates`: The templates directory, relative to the vault. The daily and weekly notes
+///   use the `daily.md` and `weekly.md` templates when they exist.
+/// - `sprint_db`: The agile database used to find the active sprint.
+pub struct JournalConfig {
+    pub vault: PathBuf,
+    pub daily_pattern: String,
+    pub weekly_pattern: String,
+    pub templates: PathBuf,
+    pub sprint_db: Option<String>,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        JournalConfig {
+            vault: PathBuf::from("."),
+            daily_pattern: "journal/%Y/%Y-%m-%d.md".to_owned(),
+            weekly_pattern: "journal/%G/%G-W%V.md".to_owned(),
+            templates: PathBuf::from("templates"),
+            sprint_db: None,
+        }
+    }
+}
+
+impl Period {
+    /// The first day of the period containing `date`.
+    pub fn start(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Daily => date,
+            Period::Weekly => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+        }
+    }
+
+    /// The name of the template of the period, e.g. `daily.md`.
+    pub fn template(&self) -> &'static str {
+        match self {
+            Period::Daily => "daily.md",
+            Period::Weekly => "weekly.md",
+        }
+    }
+}
+
+/// `date` formatted with the strftime `format`, or `None` when the format is invalid or
+/// needs a time, which a date doesn't have.
+fn format_date(date: NaiveDate, format: &str) -> Option<String> {
+    let mut formatted = String::new();
+    write!(formatted, "{}", date.format(format)).ok()?;
+    Some(formatted)
+}
+
+/// Replace the `{{variable}}` placeholders of `template` with their value in `variables`.
+/// The date can be formatted with `{{date:FORMAT}}` (e.g. `{{date:%A %d %B}}`).
+/// Unknown variables and invalid date formats are left untouched.
+pub fn render_template(
+    template: &str,
+    date: NaiveDate,
+    variables: &HashMap<String, String>,
+) -> String {
+    VARIABLE_REGEX
+        .replace_all(template, |caps: &regex::Captures| {
+            match (&caps[1], caps.get(2)) {
+                ("date", Some(format)) => {
+                    format_date(date, format.as_str().trim()).unwrap_or_else(|| caps[0].to_string())
+                }
+                (name, None) => match variables.get(name) {
+                    Some(value) => value.to_owned(),
+                    None => caps[0].to_string(),
+                },
+                _ => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// The variables available to the templates: `date`, `title`, `branch` (the git branch of
+/// the current directory) and `sprint` (the name of the active sprint).
+pub fn template_variables(
+    config: &JournalConfig,
+    title: &str,
+    date: NaiveDate,
+) -> HashMap<String, String> {
+    let mut variables = HashMap::new();
+    variables.insert("date".to_owned(), date.format("%Y-%m-%d").to_string());
+    variables.insert("title".to_owned(), title.to_owned());
+    if let Some(branch) = get_git_current_branch(PathBuf::from(".")) {
+        variables.insert("branch".to_owned(), branch);
+    }
+    if let Some(db) = &config.sprint_db {
+        if let Ok(Some(sprint)) = sprint_get_active(db) {
+            variables.insert("sprint".to_owned(), sprint.name);
+        }
+    }
+    variables
+}
+
+/// Create the note `path`, relative to the vault, from the `template` in the templates
+/// directory, or with just a title. Existing notes are left untouched.
+/// Returns the path of the note.
+pub fn new_note(
+    config: &JournalConfig,
+    path: &Path,
+    template: Option<&str>,
+    date: NaiveDate,
+) -> io::Result<PathBuf> {
+    let note_path = config.vault.join(path);
+    if note_path.exists() {
+        return Ok(note_path);
+    }
+    let title = note_path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default()
+        .to_owned();
+    let template = match template {
+        Some(name) => fs::read_to_string(config.vault.join(&config.templates).join(name))?,
+        None => "# {{title}}\n".to_owned(),
+    };
+    let contents = render_template(&template, date, &template_variables(config, &title, date));
+    if let Some(parent_dir) = note_path.parent() {
+        plan::create_dir_all(parent_dir)?;
+    }
+    plan::write(&note_path, contents)?;
+    Ok(note_path)
+}
+
+/// The path of the journal note of `period` containing `date`, relative to the vault.
+pub fn journal_path(
+    config: &JournalConfig,
+    period: Period,
+    date: NaiveDate,
+) -> io::Result<PathBuf> {
+    let pattern = match period {
+        Period::Daily => &config.daily_pattern,
+        Period::Weekly => &config.weekly_pattern,
+    };
+    format_date(period.start(date), pattern)
+        .map(PathBuf::from)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid journal pattern {}", pattern),
+            )
+        })
+}
+
+/// Create the journal note of `period` containing `date`, if it doesn't exist yet.
+/// Returns the path of the note.
+pub fn new_journal_note(
+    config: &JournalConfig,
+    period: Period,
+    date: NaiveDate,
+) -> io::Result<PathBuf> {
+    let template = config
+        .vault
+        .join(&config.templates)
+        .join(period.template())
+        .exists()
+        .then(|| period.template());
+    new_note(
+        config,
+        &journal_path(config, period, date)?,
+        template,
+        period.start(date),
+    )
+}
+
+/// Create today's journal note of `period`. Returns the path of the note.
+pub fn today(config: &JournalConfig, period: Period) -> io::Result<PathBuf> {
+    new_journal_note(config, period, Local::now().date_naive())
+}
+
+/// Append `text` at the end of the section of `contents` under `heading`.
+/// The section is added at the end of `contents` when it doesn't exist.
+pub fn append_to_section(contents: &str, heading: &str, text: &str) -> String {
+    let text = text.trim_end();
+    match section(contents, &slugify(heading)) {
+        Some(existing) => {
+            let start = existing.as_ptr() as usize - contents.as_ptr() as usize;
+            let end = start + existing.trim_end().len();
+            format!("{}\n{}{}", &contents[..end], text, &contents[end..])
+        }
+        None => {
+            let contents = contents.trim_end();
+            let separator = if contents.is_empty() { "" } else { "\n\n" };
+            format!("{}{}## {}\n\n{}\n", contents, separator, heading, text)
+        }
+    }
+}
+
+/// Append `text` to the section under `heading` of the note at `path`, relative to `vault`
+/// as with [`new_note`].
+pub fn append_to_note(vault: &Path, path: &Path, heading: &str, text: &str) -> io::Result<()> {
+    let note_path = vault.join(path);
+    let contents = fs::read_to_string(&note_path)?;
+    plan::write(&note_path, append_to_section(&contents, heading, text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_path() {
+        let config = JournalConfig::default();
+        let date = NaiveDate::from_ymd_opt(2026, 10, 22).unwrap();
+        assert_eq!(
+            journal_path(&config, Period::Daily, date).unwrap(),
+            PathBuf::from("journal/2026/2026-10-22.md")
+        );
+        assert_eq!(
+            journal_path(&config, Period::Weekly, date).unwrap(),
+            PathBuf::from("journal/2026/2026-W43.md")
+        );
+        let config = JournalConfig {
+            daily_pattern: "journal/%Q.md".to_owned(),
+            ..Default::default()
+        };
+        assert!(journal_path(&config, Period::Daily, date).is_err());
+        assert_eq!(
+            Period::Weekly.start(date),
+            NaiveDate::from_ymd_opt(2026, 10, 19).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_render_template() {
+        let date = NaiveDate::from_ymd_opt(2026, 10, 22).unwrap();
+        let mut variables = HashMap::new();
+        variables.insert("title".to_owned(), "Standup".to_owned());
+        variables.insert("date".to_owned(), "2026-10-22".to_owned());
+        assert_eq!(
+            render_template(
+                "# {{title}} {{ date }}\n{{date:%A}} {{sprint}}",
+                date,
+                &variables
+            ),
+            "# Standup 2026-10-22\nThursday {{sprint}}"
+        );
+        assert_eq!(
+            render_template("{{date:%Q}} {{date:%H:%M}}", date, &variables),
+            "{{date:%Q}} {{date:%H:%M}}"
+        );
+    }
+
+    #[test]
+    fn test_append_to_section() {
+        let contents = "# Today\n\n## Tasks\n- one\n\n## Notes\nnothing\n";
+        assert_eq!(
+            append_to_section(contents, "Tasks", "- two"),
+            "# Today\n\n## Tasks\n- one\n- two\n\n## Notes\nnothing\n"
+        );
+        assert_eq!(
+            append_to_section(contents, "Done", "- three\n"),
+            "# Today\n\n## Tasks\n- one\n\n## Notes\nnothing\n\n## Done\n\n- three\n"
+        );
+        assert_eq!(append_to_section("", "Done", "x"), "## Done\n\nx\n");
+    }
+
+    #[test]
+    fn test_append_to_note() {
+        let vault = tempfile::tempdir().unwrap();
+        fs::create_dir(vault.path().join("journal")).unwrap();
+        fs::write(vault.path().join("journal/today.md"), "# Today\n").unwrap();
+        append_to_note(
+            vault.path(),
+            Path::new("journal/today.md"),
+            "Tasks",
+            "- one",
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(vault.path().join("journal/today.md")).unwrap(),
+            "# Today\n\n## Tasks\n\n- one\n"
+        );
+        assert!(append_to_note(vault.path(), Path::new("missing.md"), "Tasks", "x").is_err());
+    }
+}
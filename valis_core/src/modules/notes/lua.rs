@@ -0,0 +1,143 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chrono::Local;
+use rlua::{Context, Error, Table};
+
+use crate::modules::notes::graph::{build_graph, GraphFilter, GraphFormat};
+use crate::modules::notes::humble::get_pages;
+use crate::modules::notes::journal;
+use crate::modules::notes::journal::{JournalConfig, Period};
+use crate::modules::notes::rename;
+use crate::modules::notes::resolver::LinkResolver;
+
+/// `notes_new(vault, path, [template], [sprint_db])` creates the note `path` in `vault`,
+/// from a template of the `templates` directory, and returns its path.
+pub fn notes_new(ctx: &Context) {
+    let f = ctx
+        .create_function(
+            |_,
+             (vault, path, template, sprint_db): (
+                String,
+                String,
+                Option<String>,
+                Option<String>,
+            )| {
+                let config = JournalConfig {
+                    vault: PathBuf::from(vault),
+                    sprint_db,
+                    ..Default::default()
+                };
+                match journal::new_note(
+                    &config,
+                    &PathBuf::from(path),
+                    template.as_deref(),
+                    Local::now().date_naive(),
+                ) {
+                    Ok(note_path) => Ok(note_path.to_str().unwrap().to_owned()),
+                    Err(e) => Err(Error::external(e)),
+                }
+            },
+        )
+        .unwrap();
+    ctx.globals().set("notes_new", f).unwrap();
+}
+
+/// `notes_journal(vault, ["daily"|"weekly"], [sprint_db])` creates today's journal note
+/// in `vault`, and returns its path.
+pub fn notes_journal(ctx: &Context) {
+    let f = ctx
+        .create_function(
+            |_, (vault, period, sprint_db): (String, Option<String>, Option<String>)| {
+                let period = match period.as_deref() {
+                    None | Some("daily") => Period::Daily,
+                    Some("weekly") => Period::Weekly,
+                    Some(other) => {
+                        return Err(Error::external(format!(
+                            "Unknown journal period: {}",
+                            other
+                        )))
+                    }
+                };
+                let config = JournalConfig {
+                    vault: PathBuf::from(vault),
+                    sprint_db,
+                    ..Default::default()
+                };
+                match journal::today(&config, period) {
+                    Ok(note_path) => Ok(note_path.to_str().unwrap().to_owned()),
+                    Err(e) => Err(Error::external(e)),
+                }
+            },
+        )
+        .unwrap();
+    ctx.globals().set("notes_journal", f).unwrap();
+}
+
+/// `notes_append(vault, path, heading, text)` appends `text` to the section under `heading`
+/// of the note `path` in `vault`.
+pub fn notes_append(ctx: &Context) {
+    let f = ctx
+        .create_function(
+            |_, (vault, path, heading, text): (String, String, String, String)| {
+                journal::append_to_note(
+                    &PathBuf::from(vault),
+                    &PathBuf::from(path),
+                    &heading,
+                    &text,
+                )
+                .map_err(Error::external)
+            },
+        )
+        .unwrap();
+    ctx.globals().set("notes_append", f).unwrap();
+}
+
+/// `notes_mv(vault, from, to, [dry_run])` renames the note `from` to `to` in `vault`,
+/// rewriting the links to it, and returns the diff of the changes.
+pub fn notes_mv(ctx: &Context) {
+    let f = ctx
+        .create_function(
+            |_, (vault, from, to, dry_run): (String, String, String, Option<bool>)| {
+                match rename::rename_note(
+                    &PathBuf::from(vault),
+                    &PathBuf::from(from),
+                    &PathBuf::from(to),
+                    dry_run.unwrap_or(false),
+                ) {
+                    Ok(rename) => Ok(rename.diff()),
+                    Err(e) => Err(Error::external(e.to_string())),
+                }
+            },
+        )
+        .unwrap();
+    ctx.globals().set("notes_mv", f).unwrap();
+}
+
+/// `notes_graph(vault, format, [filter])` exports the graph of the notes of `vault` as
+/// `dot`, `graphml` or `json`. The optional `filter` table selects a subgraph with
+/// its `tag`, `folder`, `around` and `hops` fields.
+pub fn notes_graph(ctx: &Context) {
+    let f = ctx
+        .create_function(
+            |_, (vault, format, filter): (String, String, Option<Table>)| {
+                let format = GraphFormat::from_str(&format).map_err(Error::external)?;
+                let filter = match filter {
+                    Some(table) => GraphFilter {
+                        tag: table.get("tag")?,
+                        folder: table.get("folder")?,
+                        around: table.get("around")?,
+                        hops: table.get::<_, Option<usize>>("hops")?.unwrap_or(1),
+                    },
+                    None => GraphFilter::default(),
+                };
+                let vault = PathBuf::from(vault);
+                let pages = get_pages(vault.clone());
+                let resolver = LinkResolver::new(&vault, &pages);
+                let graph = build_graph(&resolver, &pages).filter(&filter, &resolver);
+                Ok(graph.export(format))
+            },
+        )
+        .unwrap();
+    ctx.globals().set("notes_graph", f).unwrap();
+}
@@ -1,20 +1,32 @@
 use std::error::Error;
 use std::fs;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+use chrono::NaiveDate;
+
 use globmatch::Matcher;
 use lazy_static::lazy_static;
+use pulldown_cmark::{Event, Options, Parser, Tag};
 use regex::Regex;
+use serde_yaml::Value as YamlValue;
 
 use crate::modules::core::get_files;
 use crate::modules::notes::markdown::WikilinkType::TEXT;
+use crate::modules::notes::resolver::{LinkResolver, Resolution};
+use crate::modules::plan;
 
 lazy_static! {
-    static ref WIKILINK_REGEX: Regex = Regex::new(r"(!)?\[\[(.*?)\]\]").unwrap();
+    /// A wikilink: `!` for embeds, then the contents between the brackets.
+    pub(crate) static ref WIKILINK_REGEX: Regex = Regex::new(r"(!?)\[\[(.*?)\]\]").unwrap();
     static ref MEDIA_REGEX: Regex = Regex::new(r"!\[(.*)?\]\((.*)\)").unwrap();
+    static ref BLOCK_ID_REGEX: Regex = Regex::new(r"(^|\s)\^([A-Za-z0-9-]+)[ \t]*$").unwrap();
+    static ref LIST_ITEM_REGEX: Regex = Regex::new(r"^\s*([-*+]|\d+[.)])\s").unwrap();
+    static ref TASK_CHECKBOX_REGEX: Regex =
+        Regex::new(r"^(\s*(?:[-*+]|\d+[.)])\s+\[)[ xX](\])").unwrap();
+    static ref TASK_DUE_REGEX: Regex = Regex::new(r"📅\s*(\d{4}-\d{2}-\d{2})").unwrap();
+    static ref TASK_ID_REGEX: Regex = Regex::new(r"🆔\s*([\w-]+)").unwrap();
+    static ref TASK_TAG_REGEX: Regex = Regex::new(r"(^|\s)#([\w/-]+)").unwrap();
     static ref IMAGE_EXTENSIONS: Vec<String> = vec![
         "jpg".to_string(),
         "png".to_string(),
@@ -30,6 +42,8 @@ pub struct Page {
     pub path: PathBuf,
     pub contents: String,
     pub wikilinks: Vec<WikiLink>,
+    pub aliases: Vec<String>,
+    pub tags: Vec<String>,
 }
 
 impl Clone for Page {
@@ -39,57 +53,556 @@ impl Clone for Page {
             path: self.path.clone(),
             contents: self.contents.clone(),
             wikilinks: self.wikilinks.clone(),
+            aliases: self.aliases.clone(),
+            tags: self.tags.clone(),
+        }
+    }
+}
+
+/// Parse the YAML front-matter at the start of `contents`, if any.
+pub fn front_matter(contents: &str) -> Option<YamlValue> {
+    let rest = contents.strip_prefix("---")?;
+    let rest = rest
+        .strip_prefix("\r\n")
+        .or_else(|| rest.strip_prefix('\n'))?;
+    if rest.starts_with("---") {
+        return Some(YamlValue::Null);
+    }
+    let end = rest.find("\n---")?;
+    serde_yaml::from_str(&rest[..end]).ok()
+}
+
+/// Return `contents` without its YAML front-matter.
+pub fn strip_front_matter(contents: &str) -> &str {
+    if let Some(rest) = contents.strip_prefix("---") {
+        if let Some(rest) = rest
+            .strip_prefix("\r\n")
+            .or_else(|| rest.strip_prefix('\n'))
+        {
+            if let Some(body) = rest.strip_prefix("---") {
+                return body.trim_start_matches(['\r', '\n']);
+            }
+            if let Some(end) = rest.find("\n---") {
+                let body = &rest[end + 4..];
+                return body.trim_start_matches(['\r', '\n']);
+            }
         }
     }
+    contents
+}
+
+/// Read the first of the `keys` front-matter entries found, either as a list or a single string.
+fn strings_from_front_matter(contents: &str, keys: &[&str]) -> Vec<String> {
+    let front_matter = match front_matter(contents) {
+        Some(YamlValue::Mapping(map)) => map,
+        _ => return vec![],
+    };
+    let value = keys
+        .iter()
+        .find_map(|key| front_matter.get(&YamlValue::String(key.to_string())));
+    match value {
+        Some(YamlValue::String(value)) => vec![value.to_string()],
+        Some(YamlValue::Sequence(values)) => values
+            .iter()
+            .filter_map(|value| value.as_str().map(|v| v.to_string()))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Read the `aliases` (or `alias`) front-matter entry, either as a list or a single string.
+pub fn aliases_from_front_matter(contents: &str) -> Vec<String> {
+    strings_from_front_matter(contents, &["aliases", "alias"])
+}
+
+/// Read the `tags` (or `tag`) front-matter entry, either as a list or a single string.
+/// Leading `#` are removed.
+pub fn tags_from_front_matter(contents: &str) -> Vec<String> {
+    strings_from_front_matter(contents, &["tags", "tag"])
+        .into_iter()
+        .map(|tag| tag.trim_start_matches('#').to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+/// The format Humble writes the published pages in.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum OutputTarget {
+    /// Hugo content, using the `ref` and `figure` shortcodes.
+    #[default]
+    Hugo,
+    /// Plain Markdown, using root-relative links to the `.md` files.
+    Markdown,
 }
 
-fn convert_wikilinks_to_hugo(contents: &str) -> String {
-    // Regular expression to match wikilinks
-    let re = Regex::new(r"(!?)\[\[(.*?)\]\]").unwrap();
+impl OutputTarget {
+    /// The URL path of a published page, relative to the site root.
+    pub fn page_path(&self, title: &str) -> String {
+        match (self, title) {
+            (OutputTarget::Hugo, "Index") => "/".to_string(),
+            (OutputTarget::Hugo, _) => format!("/posts/{}/", slugify(title)),
+            (OutputTarget::Markdown, "Index") => "/Index.md".to_string(),
+            (OutputTarget::Markdown, _) => format!("/posts/{}.md", title.replace(' ', "%20")),
+        }
+    }
+
+    /// The URL path of a tag listing page, relative to the site root.
+    pub fn tag_path(&self, tag: &str) -> String {
+        match self {
+            OutputTarget::Hugo => format!("/tags/{}/", slugify(tag)),
+            OutputTarget::Markdown => format!("/tags/{}.md", tag.replace(' ', "%20")),
+        }
+    }
+
+    /// Render a link to the page saved as `title`, optionally to a fragment of it.
+    pub fn page_link(&self, name: &str, title: &str, fragment: Option<&str>) -> String {
+        let fragment = fragment.map(|f| format!("#{}", f)).unwrap_or_default();
+        match self {
+            OutputTarget::Hugo => {
+                format!("[{}]({{{{< ref \"{}{}\" >}}}})", name, title, fragment)
+            }
+            OutputTarget::Markdown => format!("[{}]({}{})", name, self.page_path(title), fragment),
+        }
+    }
+
+    /// Render an embedded image, copied to the assets.
+    pub fn image(&self, link: &str) -> String {
+        match self {
+            OutputTarget::Hugo => format!(
+                "{{{{< figure src=\"/assets/{}\" alt=\"{}\" >}}}}",
+                link, link
+            ),
+            OutputTarget::Markdown => format!("![{}](/assets/{})", link, link.replace(' ', "%20")),
+        }
+    }
+}
 
-    // Function to convert a matched wikilink to Hugo format
+fn convert_wikilinks(contents: &str, resolver: &LinkResolver, target: OutputTarget) -> String {
+    // Function to convert a matched wikilink to the target format
     let replace_func = |caps: &regex::Captures| {
-        let is_image = &caps[1] == "!";
+        let is_embed = &caps[1] == "!";
         let link_and_name = &caps[2];
 
-        // Check if the matched link is an image
-        if is_image {
-            format!(
-                "{{{{< figure src=\"/assets/{}\" alt=\"{}\" >}}}}",
-                link_and_name, link_and_name
-            )
+        let wikilink = match parse_wikilink(link_and_name) {
+            Ok(wikilink) => wikilink,
+            Err(_) => return link_and_name.to_string(),
+        };
+
+        // Check if the matched link is an embedded asset
+        if is_embed {
+            match wikilink.link_type {
+                WikilinkType::IMAGE => return target.image(&wikilink.link),
+                WikilinkType::PDF => {
+                    return format!("[{}](/assets/{})", wikilink.name, wikilink.link)
+                }
+                WikilinkType::AUDIO => {
+                    return format!("<audio controls src=\"/assets/{}\"></audio>", wikilink.link)
+                }
+                // Notes which couldn't be transcluded are rendered as links
+                WikilinkType::TEXT => {}
+            }
         }
-        // Check if the matched link contains a '|'
-        else if let Some(pipe_index) = link_and_name.find('|') {
-            // If it does, split the string on this character to get the link and the alternate name
-            let (link, name) = link_and_name.split_at(pipe_index);
-            let name = &name[1..]; // Remove the leading '|'
-            format!("[{}]({{{{< ref \"{}\" >}}}})", name, link)
+
+        // Otherwise resolve the link against the vault
+        let name = if wikilink.name.is_empty() {
+            link_and_name
+                .trim_start_matches('#')
+                .trim_start_matches('^')
+                .to_string()
         } else {
-            // If there's no '|', use the entire match as both the link and the name
-            format!("[{}]({{{{< ref \"{}\" >}}}})", link_and_name, link_and_name)
+            wikilink.name.to_string()
+        };
+        if wikilink.link.is_empty() {
+            // A link to an anchor in the same page
+            return match wikilink.fragment() {
+                Some(fragment) => format!("[{}](#{})", name, fragment),
+                None => name,
+            };
+        }
+        match resolver.resolve_link(&wikilink) {
+            // Link to the page with the title it is saved under, and to the
+            // heading or block if it exists in the target page
+            Resolution::Resolved(page) => match wikilink.fragment() {
+                Some(fragment) if page.has_fragment(&wikilink) => {
+                    target.page_link(&name, &page.title, Some(&fragment))
+                }
+                _ => target.page_link(&name, &page.title, None),
+            },
+            // Render missing or ambiguous links as plain text, so that Hugo doesn't fail
+            _ => name,
         }
     };
 
     // Replace all matches in the contents
-    re.replace_all(contents, replace_func).into_owned()
+    WIKILINK_REGEX
+        .replace_all(contents, replace_func)
+        .into_owned()
 }
 
 impl Page {
-    pub fn save_to_file(&self, directory: &PathBuf) -> std::io::Result<()> {
+    /// Read the note at `path`.
+    pub fn read(path: &Path) -> std::io::Result<Page> {
+        let contents = fs::read_to_string(path)?;
+        Ok(Page {
+            path: path.to_path_buf(),
+            title: Self::title_from_path(&path.to_path_buf()),
+            wikilinks: extract_links(&contents),
+            aliases: aliases_from_front_matter(&contents),
+            tags: tags_from_front_matter(&contents),
+            contents,
+        })
+    }
+
+    pub fn save_to_file(
+        &self,
+        directory: &Path,
+        resolver: &LinkResolver,
+        target: OutputTarget,
+    ) -> std::io::Result<()> {
         // Construct the full file path
-        let mut file_path = directory.clone();
-        file_path.push(format!("{}.md", &self.title));
+        let file_path = directory.join(format!("{}.md", &self.title));
+
+        // Convert wikilinks to the target format
+        let contents = convert_wikilinks(&self.contents, resolver, target);
+        let contents = render_block_anchors(&contents);
+
+        // Leave unchanged files untouched, so that rebuilds only rewrite what changed
+        if fs::read_to_string(&file_path).ok().as_deref() == Some(contents.as_str()) {
+            return Ok(());
+        }
+
+        plan::write(&file_path, contents)
+    }
+
+    /// The text of all the headings in the page.
+    pub fn headings(&self) -> Vec<String> {
+        headings(&self.contents)
+    }
+
+    /// The ids of all the blocks (`^blockid`) in the page.
+    pub fn block_ids(&self) -> Vec<String> {
+        block_ids(&self.contents)
+    }
 
-        // Open a file in write-only mode
-        let mut file = File::create(&file_path)?;
+    /// The checklist items (`- [ ] task`) of the page.
+    pub fn tasks(&self) -> Vec<MarkdownTask> {
+        extract_tasks(&self.path, &self.contents)
+    }
+
+    /// Whether the heading or block that `link` points to exists in this page.
+    /// Links without an anchor always match.
+    pub fn has_fragment(&self, link: &WikiLink) -> bool {
+        if !link.block.is_empty() {
+            self.block_ids().contains(&link.block)
+        } else if !link.anchor.is_empty() {
+            let fragment = link.fragment();
+            self.headings()
+                .iter()
+                .any(|heading| Some(slugify(heading)) == fragment)
+        } else {
+            true
+        }
+    }
+}
 
-        // Convert wikilinks to Hugo format
-        let contents = convert_wikilinks_to_hugo(&self.contents);
+/// Convert a heading into its fragment id, as generated by Hugo
+/// (lowercase, whitespace replaced by `-` and punctuation removed).
+pub fn slugify(heading: &str) -> String {
+    heading
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                Some(c)
+            } else if c.is_whitespace() {
+                Some('-')
+            } else {
+                None
+            }
+        })
+        .collect()
+}
 
-        // Write the contents to file
-        file.write_all(contents.as_bytes())
+/// The level, start offset and text of all the headings in `contents`.
+fn heading_spans(contents: &str) -> Vec<(u32, usize, String)> {
+    let mut spans = Vec::new();
+    let mut current: Option<(u32, usize, String)> = None;
+    for (event, range) in Parser::new(contents).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Heading(level)) => {
+                current = Some((level, range.start, String::new()))
+            }
+            Event::End(Tag::Heading(_)) => {
+                if let Some(span) = current.take() {
+                    spans.push(span);
+                }
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, _, heading)) = current.as_mut() {
+                    heading.push_str(&text);
+                }
+            }
+            _ => {}
+        }
     }
+    spans
+}
+
+/// Extract the text of all the headings in `contents`.
+pub fn headings(contents: &str) -> Vec<String> {
+    heading_spans(contents)
+        .into_iter()
+        .map(|(_, _, heading)| heading)
+        .collect()
+}
+
+/// Extract the section of `contents` starting at the heading with the given
+/// fragment id (see [`slugify`]), up to the next heading of the same or higher level.
+pub fn section<'a>(contents: &'a str, fragment: &str) -> Option<&'a str> {
+    let spans = heading_spans(contents);
+    let index = spans
+        .iter()
+        .position(|(_, _, heading)| slugify(heading) == fragment)?;
+    let (level, start, _) = &spans[index];
+    let end = spans[index + 1..]
+        .iter()
+        .find(|(other_level, _, _)| other_level <= level)
+        .map(|(_, other_start, _)| *other_start)
+        .unwrap_or(contents.len());
+    Some(contents[*start..end].trim_end())
+}
+
+/// Extract the block with the given id from `contents`, without its `^blockid` marker.
+/// A block is the paragraph ending with the marker, the list item holding it or,
+/// for markers on their own line, the paragraph just before it.
+pub fn block(contents: &str, id: &str) -> Option<String> {
+    let lines = contents.lines().collect::<Vec<&str>>();
+    let mut in_code = false;
+    let mut marker = None;
+    for (index, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code = !in_code;
+        } else if !in_code {
+            if let Some(captures) = BLOCK_ID_REGEX.captures(line) {
+                if &captures[2] == id {
+                    marker = Some(index);
+                    break;
+                }
+            }
+        }
+    }
+    let marker = marker?;
+    let stripped = BLOCK_ID_REGEX
+        .replace(lines[marker], "")
+        .trim_end()
+        .to_string();
+
+    let is_list_item = LIST_ITEM_REGEX.is_match(&stripped);
+    if is_list_item {
+        return Some(stripped);
+    }
+    let mut end = marker + 1;
+    if stripped.trim().is_empty() {
+        // Skip the marker line and the blank lines before it
+        end = marker;
+        while end > 0 && lines[end - 1].trim().is_empty() {
+            end -= 1;
+        }
+    }
+    let mut start = end;
+    while start > 0 && !lines[start - 1].trim().is_empty() {
+        start -= 1;
+    }
+    let mut block = lines[start..end]
+        .iter()
+        .map(|line| line.to_string())
+        .collect::<Vec<String>>();
+    if let Some(last) = block.last_mut() {
+        if !stripped.trim().is_empty() {
+            *last = stripped;
+        }
+    }
+    Some(block.join("\n"))
+}
+
+/// Apply `f` to every line of `contents` outside fenced code blocks.
+pub(crate) fn map_lines_outside_code<F: FnMut(&str) -> String>(contents: &str, mut f: F) -> String {
+    let mut in_code = false;
+    let lines = contents
+        .split('\n')
+        .map(|line| {
+            let trimmed = line.trim_start();
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                in_code = !in_code;
+                line.to_string()
+            } else if in_code {
+                line.to_string()
+            } else {
+                f(line)
+            }
+        })
+        .collect::<Vec<String>>();
+    lines.join("\n")
+}
+
+/// Extract the ids of all the blocks in `contents`, i.e. lines ending with `^blockid`.
+pub fn block_ids(contents: &str) -> Vec<String> {
+    let mut ids = Vec::new();
+    map_lines_outside_code(contents, |line| {
+        if let Some(captures) = BLOCK_ID_REGEX.captures(line) {
+            ids.push(captures[2].to_string());
+        }
+        line.to_string()
+    });
+    ids
+}
+
+/// Replace the `^blockid` markers with HTML anchors, so that block references
+/// can link to them. Requires raw HTML to be enabled in the Hugo Markdown renderer.
+pub fn render_block_anchors(contents: &str) -> String {
+    map_lines_outside_code(contents, |line| {
+        BLOCK_ID_REGEX
+            .replace(line, "$1<a id=\"block-$2\"></a>")
+            .to_string()
+    })
+}
+
+/// A checklist item (`- [ ] task` or `- [x] task`) of a page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarkdownTask {
+    /// The page containing the task.
+    pub source: PathBuf,
+    /// The line of the checkbox in the page, starting at 1.
+    pub line: usize,
+    /// The text of the task, without its due date, id and tags.
+    pub text: String,
+    pub completed: bool,
+    /// The due date, from the `📅 2026-10-20` syntax.
+    pub due: Option<NaiveDate>,
+    /// The id of the task in another task system, from the `🆔 id` syntax.
+    pub id: Option<String>,
+    /// The `#tags` of the task, without the `#`.
+    pub tags: Vec<String>,
+}
+
+impl MarkdownTask {
+    fn parse(source: &Path, line: usize, completed: bool, item: &str) -> Self {
+        let due = TASK_DUE_REGEX
+            .captures(item)
+            .and_then(|caps| NaiveDate::parse_from_str(&caps[1], "%Y-%m-%d").ok());
+        let id = TASK_ID_REGEX.captures(item).map(|caps| caps[1].to_string());
+        let tags = TASK_TAG_REGEX
+            .captures_iter(item)
+            .map(|caps| caps[2].to_string())
+            .collect();
+        let text = TASK_DUE_REGEX.replace_all(item, "");
+        let text = TASK_ID_REGEX.replace_all(&text, "");
+        let text = TASK_TAG_REGEX.replace_all(&text, "$1");
+        MarkdownTask {
+            source: source.to_path_buf(),
+            line,
+            text: text.split_whitespace().collect::<Vec<&str>>().join(" "),
+            completed,
+            due,
+            id,
+            tags,
+        }
+    }
+}
+
+/// Extract the checklist items of `contents`, the contents of the page at `source`.
+/// The text of a task stops at its nested list, if any.
+pub fn extract_tasks(source: &Path, contents: &str) -> Vec<MarkdownTask> {
+    let mut tasks = Vec::new();
+    // The start offset of each open list item, with its checkbox state and text if it's a task
+    let mut items: Vec<(usize, Option<(bool, String)>)> = Vec::new();
+    let line_of = |offset: usize| contents[..offset].matches('\n').count() + 1;
+    let finish = |item: &mut (usize, Option<(bool, String)>), tasks: &mut Vec<MarkdownTask>| {
+        if let Some((completed, text)) = item.1.take() {
+            tasks.push(MarkdownTask::parse(
+                source,
+                line_of(item.0),
+                completed,
+                &text,
+            ));
+        }
+    };
+
+    for (event, range) in Parser::new_ext(contents, Options::ENABLE_TASKLISTS).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Item) => items.push((range.start, None)),
+            Event::End(Tag::Item) => {
+                if let Some(mut item) = items.pop() {
+                    finish(&mut item, &mut tasks);
+                }
+            }
+            // A nested list ends the text of the current item
+            Event::Start(Tag::List(_)) => {
+                if let Some(item) = items.last_mut() {
+                    finish(item, &mut tasks);
+                }
+            }
+            Event::TaskListMarker(completed) => {
+                if let Some(item) = items.last_mut() {
+                    item.1 = Some((completed, String::new()));
+                }
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, Some((_, task_text)))) = items.last_mut() {
+                    task_text.push_str(&text);
+                }
+            }
+            Event::SoftBreak | Event::HardBreak => {
+                if let Some((_, Some((_, task_text)))) = items.last_mut() {
+                    task_text.push(' ');
+                }
+            }
+            _ => {}
+        }
+    }
+    tasks
+}
+
+/// Set the checkbox of the task at `line` (starting at 1) of `contents`.
+/// Returns `None` when there is no task at `line`.
+pub fn set_task_completed(contents: &str, line: usize, completed: bool) -> Option<String> {
+    let mut lines = contents.split('\n').collect::<Vec<&str>>();
+    let current = lines.get(line.checked_sub(1)?)?;
+    if !TASK_CHECKBOX_REGEX.is_match(current) {
+        return None;
+    }
+    let mark = if completed { "${1}x$2" } else { "${1} $2" };
+    let updated = TASK_CHECKBOX_REGEX.replace(current, mark).into_owned();
+    lines[line - 1] = &updated;
+    Some(lines.join("\n"))
+}
+
+/// Update the checkboxes of the tasks of the page at `path` whose completion state is
+/// given by `state`, e.g. because they were closed in another task system.
+/// Returns the number of tasks updated.
+pub fn sync_task_completion<F: Fn(&MarkdownTask) -> Option<bool>>(
+    path: &Path,
+    state: F,
+) -> std::io::Result<usize> {
+    let mut contents = fs::read_to_string(path)?;
+    let mut updated = 0;
+    for task in extract_tasks(path, &contents) {
+        match state(&task) {
+            Some(completed) if completed != task.completed => {
+                if let Some(new_contents) = set_task_completed(&contents, task.line, completed) {
+                    contents = new_contents;
+                    updated += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    if updated > 0 {
+        plan::write(path, contents)?;
+    }
+    Ok(updated)
 }
 
 pub trait PageLoader {
@@ -99,14 +612,7 @@ pub trait PageLoader {
 
 impl PageLoader for Page {
     fn from_path(path: &PathBuf) -> Self {
-        // Read the contents, extract wikilinks, or perform other initializations here...
-        let contents = fs::read_to_string(path).ok().unwrap();
-        Self {
-            path: path.clone(),
-            title: Self::title_from_path(path),
-            contents: contents.clone(),
-            wikilinks: extract_links(&contents),
-        }
+        Page::read(path).ok().unwrap()
     }
 
     fn title_from_path(path: &PathBuf) -> String {
@@ -121,6 +627,8 @@ impl PageLoader for Page {
 pub enum WikilinkType {
     IMAGE,
     TEXT,
+    PDF,
+    AUDIO,
 }
 
 #[derive(Debug)]
@@ -128,17 +636,37 @@ pub struct WikiLink {
     pub name: String,
     pub link: String,
     pub anchor: String,
+    pub block: String,
     pub link_type: WikilinkType,
+    pub embed: bool,
     pub original: String,
 }
 
+impl WikiLink {
+    /// The fragment id of the heading or block this link points to, if any.
+    /// Headings are slugified and blocks are prefixed with `block-`.
+    pub fn fragment(&self) -> Option<String> {
+        if !self.block.is_empty() {
+            Some(format!("block-{}", self.block))
+        } else if !self.anchor.is_empty() {
+            // Obsidian allows nested headings (`[[Page#Heading#Subheading]]`)
+            let heading = self.anchor.rsplit('#').next().unwrap_or("");
+            Some(slugify(heading))
+        } else {
+            None
+        }
+    }
+}
+
 impl Clone for WikiLink {
     fn clone(&self) -> Self {
         WikiLink {
             name: self.name.to_string(),
             link: self.link.to_string(),
             anchor: self.anchor.to_string(),
+            block: self.block.to_string(),
             link_type: self.link_type.clone(),
+            embed: self.embed,
             original: self.original.to_string(),
         }
     }
@@ -157,7 +685,7 @@ pub fn remove_code_blocks(s: &str) -> String {
 pub fn extract_links(contents: &str) -> Vec<WikiLink> {
     return WIKILINK_REGEX
         .captures_iter(&remove_code_blocks(contents))
-        .map(|captures| parse_wikilink(captures.get(2).unwrap().as_str()))
+        .map(|captures| parse_wikilink(captures.get(0).unwrap().as_str()))
         .filter(|wikilink| wikilink.as_ref().ok().is_some())
         .map(|wikilink| wikilink.unwrap())
         .collect::<Vec<WikiLink>>();
@@ -167,6 +695,12 @@ impl FromStr for WikiLink {
     type Err = Box<dyn Error>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let original = s;
+        // Embeds (transclusions) are prefixed with `!`
+        let (embed, s) = match s.strip_prefix('!') {
+            Some(rest) if rest.starts_with("[[") => (true, rest),
+            _ => (false, s),
+        };
         let link_contents = s.trim_start_matches("[[").trim_end_matches("]]");
 
         let (link, name) = if let Some(pipe_pos) = link_contents.find('|') {
@@ -190,10 +724,15 @@ impl FromStr for WikiLink {
             link_without_anchor
         };
         let final_anchor = if is_just_anchor {
-            link_contents.trim_start_matches('#')
+            link.trim_start_matches('#')
         } else {
             anchor
         };
+        // Block references are anchors starting with `^`
+        let (final_anchor, block) = match final_anchor.strip_prefix('^') {
+            Some(block) => ("", block),
+            None => (final_anchor, ""),
+        };
         let final_name = if name == link {
             final_link.to_string()
         } else {
@@ -206,8 +745,10 @@ impl FromStr for WikiLink {
             name: final_name,
             link: String::from(final_link),
             anchor: String::from(final_anchor),
+            block: String::from(block),
             link_type,
-            original: "".to_string(),
+            embed,
+            original: original.to_string(),
         })
     }
 }
@@ -219,7 +760,9 @@ pub fn parse_wikilink(s: &str) -> Result<WikiLink, Box<dyn Error>> {
 pub fn filename_type(filename: &str) -> WikilinkType {
     let lower_ext = filename.split('.').last().unwrap_or("").to_lowercase();
     match lower_ext.as_str() {
-        "jpg" | "jpeg" | "png" | "gif" => WikilinkType::IMAGE,
+        "jpg" | "jpeg" | "png" | "gif" | "svg" => WikilinkType::IMAGE,
+        "pdf" => WikilinkType::PDF,
+        "mp3" | "wav" | "ogg" | "m4a" | "flac" => WikilinkType::AUDIO,
         _ => WikilinkType::TEXT,
     }
 }
@@ -238,6 +781,7 @@ mod tests {
         assert_eq!(wikilink.link, "Test");
         assert_eq!(wikilink.anchor, "");
         assert_eq!(wikilink.link_type, TEXT);
+        assert_eq!(wikilink.original, "[[Test]]");
     }
 
     #[test]
@@ -318,4 +862,139 @@ mod tests {
         let filename2 = "FOO.GIF";
         assert_eq!(filename_type(filename2), IMAGE);
     }
+
+    #[test]
+    fn test_block_reference() {
+        let wikilink = parse_wikilink("[[Test#^abc-123|Another]]").unwrap();
+        assert_eq!(wikilink.name, "Another");
+        assert_eq!(wikilink.link, "Test");
+        assert_eq!(wikilink.anchor, "");
+        assert_eq!(wikilink.block, "abc-123");
+        assert_eq!(wikilink.fragment(), Some("block-abc-123".to_string()));
+
+        let wikilink = parse_wikilink("[[#^abc]]").unwrap();
+        assert_eq!(wikilink.link, "");
+        assert_eq!(wikilink.block, "abc");
+    }
+
+    #[test]
+    fn test_fragment() {
+        let wikilink = parse_wikilink("[[Test#Some Heading!]]").unwrap();
+        assert_eq!(wikilink.fragment(), Some("some-heading".to_string()));
+        let wikilink = parse_wikilink("[[Test#Top#Nested]]").unwrap();
+        assert_eq!(wikilink.fragment(), Some("nested".to_string()));
+        assert_eq!(parse_wikilink("[[Test]]").unwrap().fragment(), None);
+    }
+
+    #[test]
+    fn test_headings_and_blocks() {
+        let contents = "# Title\n\nSome text ^intro\n\n## Sub `code`\n\n```\nx ^notablock\n```\n";
+        assert_eq!(headings(contents), vec!["Title", "Sub code"]);
+        assert_eq!(block_ids(contents), vec!["intro"]);
+        assert_eq!(
+            render_block_anchors(contents),
+            "# Title\n\nSome text <a id=\"block-intro\"></a>\n\n## Sub `code`\n\n```\nx ^notablock\n```\n"
+        );
+    }
+
+    #[test]
+    fn test_embed() {
+        let wikilink = parse_wikilink("![[Note#Section]]").unwrap();
+        assert!(wikilink.embed);
+        assert_eq!(wikilink.link, "Note");
+        assert_eq!(wikilink.anchor, "Section");
+        assert_eq!(wikilink.link_type, TEXT);
+        assert!(!parse_wikilink("[[Note]]").unwrap().embed);
+    }
+
+    #[test]
+    fn test_extension_assets() {
+        assert_eq!(filename_type("doc.PDF"), WikilinkType::PDF);
+        assert_eq!(filename_type("voice.m4a"), WikilinkType::AUDIO);
+        assert_eq!(filename_type("drawing.svg"), IMAGE);
+    }
+
+    #[test]
+    fn test_section_and_block() {
+        let contents = "# A\na\n## B\nb\n### C\nc\n## D\nd\n";
+        assert_eq!(section(contents, "b"), Some("## B\nb\n### C\nc"));
+        assert_eq!(section(contents, "d"), Some("## D\nd"));
+        assert_eq!(section(contents, "e"), None);
+
+        let contents = "One\ntwo ^p\n\n- x\n- y ^li\n\nThree\n\n^own\n";
+        assert_eq!(block(contents, "p"), Some("One\ntwo".to_string()));
+        assert_eq!(block(contents, "li"), Some("- y".to_string()));
+        assert_eq!(block(contents, "own"), Some("Three".to_string()));
+    }
+
+    #[test]
+    fn test_strip_front_matter() {
+        assert_eq!(strip_front_matter("---\na: 1\n---\nBody\n"), "Body\n");
+        assert_eq!(strip_front_matter("Body\n"), "Body\n");
+    }
+
+    #[test]
+    fn test_tags() {
+        let contents = "---\ntags: [\"#rust\", notes]\n---\n";
+        assert_eq!(tags_from_front_matter(contents), vec!["rust", "notes"]);
+    }
+
+    #[test]
+    fn test_output_target_links() {
+        assert_eq!(
+            OutputTarget::Hugo.page_link("Name", "My Page", Some("intro")),
+            "[Name]({{< ref \"My Page#intro\" >}})"
+        );
+        assert_eq!(
+            OutputTarget::Markdown.page_link("Name", "My Page", None),
+            "[Name](/posts/My%20Page.md)"
+        );
+        assert_eq!(OutputTarget::Hugo.page_path("My Page"), "/posts/my-page/");
+    }
+
+    #[test]
+    fn test_aliases_list() {
+        let contents = "---\ntitle: Foo\naliases: [Bar, Baz]\n---\n# Foo\n";
+        assert_eq!(aliases_from_front_matter(contents), vec!["Bar", "Baz"]);
+    }
+
+    #[test]
+    fn test_aliases_single() {
+        let contents = "---\nalias: Bar\n---\n";
+        assert_eq!(aliases_from_front_matter(contents), vec!["Bar"]);
+        assert!(aliases_from_front_matter("# No front-matter\n").is_empty());
+    }
+
+    #[test]
+    fn test_extract_tasks() {
+        let contents = "# Meeting\n\n- [ ] Send the `report` 📅 2026-10-20 #work\n  - [x] Draft it 🆔 abc-1\n- not a task\n1. [X] Book room\n";
+        let tasks = extract_tasks(Path::new("/vault/Meeting.md"), contents);
+        assert_eq!(tasks.len(), 3);
+        assert_eq!(tasks[0].line, 3);
+        assert_eq!(tasks[0].text, "Send the report");
+        assert!(!tasks[0].completed);
+        assert_eq!(tasks[0].due, NaiveDate::from_ymd_opt(2026, 10, 20));
+        assert_eq!(tasks[0].tags, vec!["work"]);
+        assert_eq!(tasks[1].line, 4);
+        assert_eq!(tasks[1].text, "Draft it");
+        assert_eq!(tasks[1].id, Some("abc-1".to_string()));
+        assert!(tasks[1].completed);
+        assert_eq!(tasks[2].line, 6);
+        assert_eq!(tasks[2].text, "Book room");
+    }
+
+    #[test]
+    fn test_set_task_completed() {
+        let contents = "- [ ] one\n  * [x] two\n- three\n";
+        assert_eq!(
+            set_task_completed(contents, 1, true),
+            Some("- [x] one\n  * [x] two\n- three\n".to_string())
+        );
+        assert_eq!(
+            set_task_completed(contents, 2, false),
+            Some("- [ ] one\n  * [ ] two\n- three\n".to_string())
+        );
+        assert_eq!(set_task_completed(contents, 3, true), None);
+        assert_eq!(set_task_completed(contents, 0, true), None);
+    }
 }
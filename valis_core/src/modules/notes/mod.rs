@@ -1,2 +1,11 @@
+pub mod graph;
 pub mod humble;
-pub mod markdown;
\ No newline at end of file
+pub mod indexes;
+pub mod journal;
+pub mod lua;
+pub mod markdown;
+pub mod publish;
+pub mod rename;
+pub mod resolver;
+pub mod transclusion;
+pub mod watch;
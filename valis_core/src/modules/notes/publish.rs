@@ -0,0 +1,182 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::modules::notes::markdown::{parse_wikilink, Page, WikilinkType, WIKILINK_REGEX};
+use crate::modules::notes::resolver::{LinkResolver, Resolution};
+
+lazy_static! {
+    static ref COMMENT_REGEX: Regex = Regex::new(r"(?s)%%.*?%%").unwrap();
+    static ref PRIVATE_CALLOUT_REGEX: Regex = Regex::new(r"(?i)^\s*>\s*\[!private\][-+]?").unwrap();
+}
+
+/// What was removed from a published page.
+#[derive(Debug, PartialEq, Clone)]
+pub enum RedactionKind {
+    /// A link to an unpublished page, rendered as plain text.
+    Link(String),
+    /// An embed of an unpublished page, removed.
+    Embed(String),
+    /// A `%%comment%%` block, removed.
+    Comment,
+    /// A `> [!private]` callout, removed.
+    PrivateCallout,
+}
+
+/// A redaction made to a published page.
+#[derive(Debug, Clone)]
+pub struct Redaction {
+    pub source: PathBuf,
+    pub kind: RedactionKind,
+}
+
+/// Whether a page is marked with `publish: true`.
+pub fn is_published(page: &Page) -> bool {
+    let mut lines = page.contents.split('\n');
+    lines.any(|line| line.starts_with("publish: true"))
+}
+
+/// Decides what can be published from a vault: pages marked with `publish: true`,
+/// without their private comments, callouts and links to unpublished pages.
+pub struct PublishPolicy {
+    published: HashSet<PathBuf>,
+}
+
+impl PublishPolicy {
+    /// Create a policy for the pages known to `resolver`.
+    pub fn new(resolver: &LinkResolver) -> Self {
+        PublishPolicy {
+            published: resolver
+                .pages()
+                .iter()
+                .filter(|page| is_published(page))
+                .map(|page| page.path.clone())
+                .collect(),
+        }
+    }
+
+    /// Whether `page` is published.
+    pub fn allows(&self, page: &Page) -> bool {
+        self.published.contains(&page.path)
+    }
+
+    /// Remove the private parts of the contents of `page`: `%%comments%%`,
+    /// `> [!private]` callouts and embeds of unpublished pages. Links to unpublished
+    /// pages are replaced by their text.
+    pub fn scrub(
+        &self,
+        page: &Page,
+        contents: &str,
+        resolver: &LinkResolver,
+    ) -> (String, Vec<Redaction>) {
+        let mut redactions = Vec::new();
+        let mut redact = |kind: RedactionKind| {
+            redactions.push(Redaction {
+                source: page.path.clone(),
+                kind,
+            })
+        };
+
+        let contents = COMMENT_REGEX
+            .replace_all(contents, |_: &regex::Captures| {
+                redact(RedactionKind::Comment);
+                ""
+            })
+            .into_owned();
+
+        let mut in_callout = false;
+        let contents = contents
+            .split('\n')
+            .filter(|line| {
+                if PRIVATE_CALLOUT_REGEX.is_match(line) {
+                    redact(RedactionKind::PrivateCallout);
+                    in_callout = true;
+                } else if in_callout && !line.trim_start().starts_with('>') {
+                    in_callout = false;
+                }
+                !in_callout
+            })
+            .collect::<Vec<&str>>()
+            .join("\n");
+
+        let contents = WIKILINK_REGEX
+            .replace_all(&contents, |caps: &regex::Captures| {
+                let original = caps[0].to_string();
+                let wikilink = match parse_wikilink(&caps[2]) {
+                    Ok(wikilink) if wikilink.link_type == WikilinkType::TEXT => wikilink,
+                    _ => return original,
+                };
+                let target = match resolver.resolve_link(&wikilink) {
+                    Resolution::Resolved(target) if !self.allows(target) => target,
+                    _ => return original,
+                };
+                if &caps[1] == "!" {
+                    redact(RedactionKind::Embed(target.title.to_string()));
+                    "".to_string()
+                } else {
+                    redact(RedactionKind::Link(target.title.to_string()));
+                    wikilink.name
+                }
+            })
+            .into_owned();
+
+        (contents, redactions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::modules::notes::markdown::{extract_links, PageLoader};
+
+    fn page(path: &str, contents: &str) -> Page {
+        let path = PathBuf::from(path);
+        Page {
+            title: Page::title_from_path(&path),
+            path,
+            contents: contents.to_string(),
+            wikilinks: extract_links(contents),
+            aliases: vec![],
+            tags: vec![],
+        }
+    }
+
+    #[test]
+    fn test_scrub() {
+        let pages = vec![
+            page(
+                "/vault/Public.md",
+                "---\npublish: true\n---\nSee [[Private|my diary]] and [[Other]].\n![[Private]]\nA %%secret%% note.\n> [!private] Todo\n> hidden\nEnd\n",
+            ),
+            page("/vault/Private.md", "Dear diary"),
+            page("/vault/Other.md", "---\npublish: true\n---\n"),
+        ];
+        let resolver = LinkResolver::new(Path::new("/vault"), &pages);
+        let policy = PublishPolicy::new(&resolver);
+        assert!(policy.allows(&pages[0]));
+        assert!(!policy.allows(&pages[1]));
+
+        let (contents, redactions) = policy.scrub(&pages[0], &pages[0].contents, &resolver);
+        assert_eq!(
+            contents,
+            "---\npublish: true\n---\nSee my diary and [[Other]].\n\nA  note.\nEnd\n"
+        );
+        let kinds = redactions
+            .into_iter()
+            .map(|redaction| redaction.kind)
+            .collect::<Vec<RedactionKind>>();
+        assert_eq!(
+            kinds,
+            vec![
+                RedactionKind::Comment,
+                RedactionKind::PrivateCallout,
+                RedactionKind::Link("Private".to_string()),
+                RedactionKind::Embed("Private".to_string()),
+            ]
+        );
+    }
+}
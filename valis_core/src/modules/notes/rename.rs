@@ -0,0 +1,285 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::modules::notes::humble::get_pages;
+use crate::modules::notes::markdown::{
+    map_lines_outside_code, parse_wikilink, Page, PageLoader, WikilinkType, WIKILINK_REGEX,
+};
+use crate::modules::notes::resolver::{LinkResolver, Resolution};
+use crate::modules::plan;
+
+/// A line changed by a rename.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineEdit {
+    /// The line number, starting at 1.
+    pub line: usize,
+    pub before: String,
+    pub after: String,
+}
+
+/// The new contents of a page linking to a renamed note.
+#[derive(Debug, Clone)]
+pub struct FileEdit {
+    /// The path of the page, after the rename.
+    pub path: PathBuf,
+    pub contents: String,
+    pub lines: Vec<LineEdit>,
+}
+
+/// The changes needed to rename a note: moving the file and rewriting the links to it.
+#[derive(Debug, Clone)]
+pub struct Rename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub edits: Vec<FileEdit>,
+}
+
+impl Rename {
+    /// The number of links rewritten.
+    pub fn links(&self) -> usize {
+        self.edits.iter().map(|edit| edit.lines.len()).sum()
+    }
+
+    /// A unified diff of the rename, for dry runs.
+    pub fn diff(&self) -> String {
+        let mut diff = format!(
+            "rename from {}\nrename to {}\n",
+            self.from.display(),
+            self.to.display()
+        );
+        for edit in &self.edits {
+            diff.push_str(&format!(
+                "--- {}\n+++ {}\n",
+                edit.path.display(),
+                edit.path.display()
+            ));
+            for line in &edit.lines {
+                diff.push_str(&format!(
+                    "@@ -{} +{} @@\n-{}\n+{}\n",
+                    line.line, line.line, line.before, line.after
+                ));
+            }
+        }
+        diff
+    }
+
+    /// Move the note and write the rewritten pages.
+    pub fn apply(&self) -> std::io::Result<()> {
+        if let Some(parent_dir) = self.to.parent() {
+            plan::create_dir_all(parent_dir)?;
+        }
+        plan::rename(&self.from, &self.to)?;
+        for edit in &self.edits {
+            plan::write(&edit.path, &edit.contents)?;
+        }
+        Ok(())
+    }
+}
+
+/// The link target to use for `new_id` (e.g. `dir/New`), written in the same form as
+/// `target`: a path when `target` is a path, or the title unless it is ambiguous.
+/// Ambiguous paths to notes at the root of the vault start with `/`, e.g. `/New`.
+fn new_target(target: &str, new_id: &str, ambiguous: bool) -> String {
+    let extension = if target.trim().ends_with(".md") {
+        ".md"
+    } else {
+        ""
+    };
+    let title = new_id.rsplit('/').next().unwrap_or(new_id);
+    if ambiguous && !new_id.contains('/') {
+        format!("/{}{}", new_id, extension)
+    } else if target.contains('/') || ambiguous {
+        format!("{}{}", new_id, extension)
+    } else {
+        format!("{}{}", title, extension)
+    }
+}
+
+/// Rewrite the wikilinks of `contents` resolving to `renamed`, keeping their anchor,
+/// alias and embed formatting. Links using an alias of `renamed` are left untouched.
+fn rewrite_links(
+    contents: &str,
+    renamed: &Page,
+    new_id: &str,
+    ambiguous: bool,
+    resolver: &LinkResolver,
+) -> String {
+    map_lines_outside_code(contents, |line| {
+        WIKILINK_REGEX
+            .replace_all(line, |caps: &regex::Captures| {
+                let original = caps[0].to_string();
+                let inner = &caps[2];
+                let wikilink = match parse_wikilink(&original) {
+                    Ok(wikilink) if wikilink.link_type == WikilinkType::TEXT => wikilink,
+                    _ => return original,
+                };
+                match resolver.resolve_link(&wikilink) {
+                    Resolution::Resolved(target) if target.path == renamed.path => {}
+                    _ => return original,
+                }
+                let end = inner.find(['#', '|']).unwrap_or(inner.len());
+                let target = &inner[..end];
+                let is_alias = !target.contains('/')
+                    && target.trim().trim_end_matches(".md").to_lowercase()
+                        != Page::title_from_path(&renamed.path).to_lowercase();
+                if is_alias {
+                    return original;
+                }
+                let leading = &target[..target.len() - target.trim_start().len()];
+                let trailing = &target[target.trim_end().len()..];
+                let prefix = if original.starts_with('!') { "!" } else { "" };
+                format!(
+                    "{}[[{}{}{}{}]]",
+                    prefix,
+                    leading,
+                    new_target(target, new_id, ambiguous),
+                    trailing,
+                    &inner[end..]
+                )
+            })
+            .into_owned()
+    })
+}
+
+/// Plan the rename of the note at `from` to `to`, both relative to `vault`, rewriting
+/// the links to it across the vault. Nothing is written until [`Rename::apply`].
+pub fn plan_rename(vault: &Path, from: &Path, to: &Path) -> Result<Rename, Box<dyn Error>> {
+    let from = vault.join(from);
+    let mut to = vault.join(to);
+    if to.is_dir() {
+        to.push(from.file_name().ok_or("Invalid note path")?);
+    }
+    if to.extension().is_none() {
+        to.set_extension("md");
+    }
+    if !from.is_file() {
+        return Err(format!("Note {} not found", from.display()).into());
+    }
+    if to.exists() {
+        return Err(format!("{} already exists", to.display()).into());
+    }
+
+    let pages = get_pages(vault.to_path_buf());
+    let resolver = LinkResolver::new(vault, &pages);
+    let renamed = pages
+        .iter()
+        .find(|page| page.path == from)
+        .ok_or_else(|| format!("Note {} is not in the vault", from.display()))?;
+    let new_id = to
+        .strip_prefix(vault)
+        .unwrap_or(&to)
+        .with_extension("")
+        .to_string_lossy()
+        .replace('\\', "/");
+    let new_title = Page::title_from_path(&to).to_lowercase();
+    let ambiguous = pages.iter().any(|page| {
+        page.path != renamed.path
+            && (page.title.to_lowercase() == new_title
+                || page
+                    .aliases
+                    .iter()
+                    .any(|alias| alias.to_lowercase() == new_title))
+    });
+
+    let mut edits = Vec::new();
+    for page in &pages {
+        let contents = rewrite_links(&page.contents, renamed, &new_id, ambiguous, &resolver);
+        if contents == page.contents {
+            continue;
+        }
+        let lines = page
+            .contents
+            .split('\n')
+            .zip(contents.split('\n'))
+            .enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(index, (before, after))| LineEdit {
+                line: index + 1,
+                before: before.to_string(),
+                after: after.to_string(),
+            })
+            .collect();
+        let path = if page.path == renamed.path {
+            to.clone()
+        } else {
+            page.path.clone()
+        };
+        edits.push(FileEdit {
+            path,
+            contents,
+            lines,
+        });
+    }
+
+    Ok(Rename { from, to, edits })
+}
+
+/// Rename the note at `from` to `to`, both relative to `vault`, and rewrite the links to it.
+/// With `dry_run`, nothing is written. Returns the rename, e.g. to show its diff.
+pub fn rename_note(
+    vault: &Path,
+    from: &Path,
+    to: &Path,
+    dry_run: bool,
+) -> Result<Rename, Box<dyn Error>> {
+    let rename = plan_rename(vault, from, to)?;
+    if !dry_run {
+        rename.apply()?;
+    }
+    Ok(rename)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_rename_note() {
+        let vault = tempfile::tempdir().unwrap();
+        let root = vault.path();
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::write(
+            root.join("dir/Old.md"),
+            "---\naliases: [Legacy]\n---\nSelf [[#Heading]] and [[Old#Heading]]\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("Other.md"),
+            "[[Old]], [[old|text]], ![[dir/Old.md#^block]], [[Legacy]]\n```\n[[Old]]\n```\n[[Unrelated]]\n",
+        )
+        .unwrap();
+
+        let rename = rename_note(root, Path::new("dir/Old.md"), Path::new("New"), true).unwrap();
+        assert_eq!(rename.to, root.join("New.md"));
+        assert_eq!(rename.links(), 2);
+        assert!(root.join("dir/Old.md").exists());
+
+        rename_note(root, Path::new("dir/Old.md"), Path::new("New"), false).unwrap();
+        assert!(!root.join("dir/Old.md").exists());
+        assert_eq!(
+            fs::read_to_string(root.join("New.md")).unwrap(),
+            "---\naliases: [Legacy]\n---\nSelf [[#Heading]] and [[New#Heading]]\n"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("Other.md")).unwrap(),
+            "[[New]], [[New|text]], ![[New.md#^block]], [[Legacy]]\n```\n[[Old]]\n```\n[[Unrelated]]\n"
+        );
+
+        // A note of the same name elsewhere makes the links paths
+        fs::create_dir_all(root.join("archive")).unwrap();
+        fs::write(root.join("archive/Latest.md"), "").unwrap();
+        rename_note(root, Path::new("New.md"), Path::new("Latest"), false).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("Other.md")).unwrap(),
+            "[[/Latest]], [[/Latest|text]], ![[/Latest.md#^block]], [[Legacy]]\n```\n[[Old]]\n```\n[[Unrelated]]\n"
+        );
+        let pages = get_pages(root.to_path_buf());
+        let resolver = LinkResolver::new(root, &pages);
+        match resolver.resolve("/Latest") {
+            Resolution::Resolved(page) => assert_eq!(page.path, root.join("Latest.md")),
+            _ => panic!("/Latest should resolve to the renamed note"),
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::modules::notes::markdown::{Page, PageLoader, WikiLink, WikilinkType};
+
+/// The outcome of resolving a wikilink against a vault.
+#[derive(Debug)]
+pub enum Resolution<'a> {
+    /// The link points to exactly one page.
+    Resolved(&'a Page),
+    /// The link matches several pages and cannot be disambiguated.
+    Ambiguous(Vec<&'a Page>),
+    /// No page matches the link.
+    Missing,
+}
+
+/// Why a wikilink is broken.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkProblem {
+    /// No page matches the link.
+    Missing,
+    /// Several pages match the link, given as their paths.
+    Ambiguous(Vec<PathBuf>),
+    /// The target page has no heading matching the link anchor.
+    MissingHeading(String),
+    /// The target page has no block with the link block id.
+    MissingBlock(String),
+}
+
+/// A wikilink which does not resolve to a single page, or to an existing
+/// heading or block in that page.
+#[derive(Debug, Clone)]
+pub struct BrokenLink {
+    pub source: PathBuf,
+    pub link: WikiLink,
+    pub problem: LinkProblem,
+}
+
+/// Resolves Obsidian-style wikilinks over a vault of pages.
+///
+/// Links are matched case-insensitively against the page file name, any `aliases`
+/// declared in the page front-matter and, for links containing a `/`, against the
+/// path of the page relative to the vault root.
+pub struct LinkResolver<'a> {
+    root: PathBuf,
+    pages: &'a [Page],
+    names: HashMap<String, Vec<usize>>,
+}
+
+/// Normalise a link target or page name for case-insensitive matching.
+fn normalise(name: &str) -> String {
+    let name = name.trim().replace('\\', "/").to_lowercase();
+    let name = name.trim_start_matches("./").trim_start_matches('/');
+    name.strip_suffix(".md").unwrap_or(name).to_string()
+}
+
+impl<'a> LinkResolver<'a> {
+    /// Create a resolver for `pages` located under the vault `root`.
+    pub fn new(root: &Path, pages: &'a [Page]) -> Self {
+        let mut names: HashMap<String, Vec<usize>> = HashMap::new();
+        for (index, page) in pages.iter().enumerate() {
+            let stem = Page::title_from_path(&page.path);
+            for name in std::iter::once(&stem).chain(page.aliases.iter()) {
+                let entry = names.entry(normalise(name)).or_default();
+                if !entry.contains(&index) {
+                    entry.push(index);
+                }
+            }
+        }
+        LinkResolver {
+            root: root.to_path_buf(),
+            pages,
+            names,
+        }
+    }
+
+    /// All the pages known to this resolver.
+    pub fn pages(&self) -> &'a [Page] {
+        self.pages
+    }
+
+    /// The vault identifier of a page: its path relative to the vault root,
+    /// without extension and using `/` as separator (e.g. `dir/Foo`).
+    pub fn id(&self, page: &Page) -> String {
+        page.path
+            .strip_prefix(&self.root)
+            .unwrap_or(&page.path)
+            .with_extension("")
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+
+    /// Whether `page` matches `target` with the exact same case.
+    fn matches_exactly(&self, page: &Page, target: &str) -> bool {
+        let target = target.trim().trim_end_matches(".md");
+        let id = self.id(page);
+        id == target
+            || id.ends_with(&format!("/{}", target))
+            || page.title == target
+            || page.aliases.iter().any(|alias| alias == target)
+    }
+
+    /// Resolve a link target, such as `Foo`, `dir/Foo`, `/Foo` (at the root of the vault)
+    /// or an alias, to a page.
+    pub fn resolve(&self, target: &str) -> Resolution<'a> {
+        let normalised = normalise(target);
+        if normalised.is_empty() {
+            return Resolution::Missing;
+        }
+
+        // Paths starting with `/` are from the root of the vault, others can be partial
+        let rooted = target.trim_start().starts_with('/');
+        let candidates: Vec<usize> = if rooted || normalised.contains('/') {
+            let suffix = format!("/{}", normalised);
+            self.pages
+                .iter()
+                .enumerate()
+                .filter(|(_, page)| {
+                    let id = normalise(&self.id(page));
+                    id == normalised || !rooted && id.ends_with(&suffix)
+                })
+                .map(|(index, _)| index)
+                .collect()
+        } else {
+            self.names.get(&normalised).cloned().unwrap_or_default()
+        };
+
+        match candidates.len() {
+            0 => Resolution::Missing,
+            1 => Resolution::Resolved(&self.pages[candidates[0]]),
+            _ => {
+                // Prefer a case-sensitive match when it is unique
+                let exact = candidates
+                    .iter()
+                    .filter(|index| self.matches_exactly(&self.pages[**index], target))
+                    .collect::<Vec<&usize>>();
+                if exact.len() == 1 {
+                    Resolution::Resolved(&self.pages[*exact[0]])
+                } else {
+                    Resolution::Ambiguous(
+                        candidates
+                            .into_iter()
+                            .map(|index| &self.pages[index])
+                            .collect(),
+                    )
+                }
+            }
+        }
+    }
+
+    /// Resolve a parsed wikilink. Links to an anchor only (e.g. `[[#Section]]`) are `Missing`.
+    pub fn resolve_link(&self, link: &WikiLink) -> Resolution<'a> {
+        self.resolve(&link.link)
+    }
+
+    /// List the text wikilinks in `pages` that are missing, ambiguous or point
+    /// to a heading or block which doesn't exist in the target page.
+    pub fn broken_links(&self, pages: &[Page]) -> Vec<BrokenLink> {
+        let mut broken = Vec::new();
+        for page in pages {
+            for link in &page.wikilinks {
+                if link.link_type != WikilinkType::TEXT {
+                    continue;
+                }
+                // Links to an anchor only point to the page itself
+                let target = if link.link.is_empty() {
+                    page
+                } else {
+                    match self.resolve_link(link) {
+                        Resolution::Resolved(target) => target,
+                        Resolution::Ambiguous(pages) => {
+                            broken.push(BrokenLink {
+                                source: page.path.clone(),
+                                link: link.clone(),
+                                problem: LinkProblem::Ambiguous(
+                                    pages.into_iter().map(|p| p.path.clone()).collect(),
+                                ),
+                            });
+                            continue;
+                        }
+                        Resolution::Missing => {
+                            broken.push(BrokenLink {
+                                source: page.path.clone(),
+                                link: link.clone(),
+                                problem: LinkProblem::Missing,
+                            });
+                            continue;
+                        }
+                    }
+                };
+                if !target.has_fragment(link) {
+                    let problem = if link.block.is_empty() {
+                        LinkProblem::MissingHeading(link.anchor.clone())
+                    } else {
+                        LinkProblem::MissingBlock(link.block.clone())
+                    };
+                    broken.push(BrokenLink {
+                        source: page.path.clone(),
+                        link: link.clone(),
+                        problem,
+                    });
+                }
+            }
+        }
+        broken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::notes::markdown::extract_links;
+
+    fn page(path: &str, aliases: Vec<&str>) -> Page {
+        page_with_contents(path, aliases, "")
+    }
+
+    fn page_with_contents(path: &str, aliases: Vec<&str>, contents: &str) -> Page {
+        let path = PathBuf::from(path);
+        Page {
+            title: Page::title_from_path(&path),
+            path,
+            contents: contents.to_string(),
+            wikilinks: extract_links(contents),
+            aliases: aliases.into_iter().map(|a| a.to_string()).collect(),
+            tags: vec![],
+        }
+    }
+
+    fn resolved_path(resolution: Resolution) -> Option<PathBuf> {
+        match resolution {
+            Resolution::Resolved(page) => Some(page.path.clone()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_resolve_case_insensitive() {
+        let pages = vec![page("/vault/Foo.md", vec![])];
+        let resolver = LinkResolver::new(Path::new("/vault"), &pages);
+        assert_eq!(
+            resolved_path(resolver.resolve("foo")),
+            Some(PathBuf::from("/vault/Foo.md"))
+        );
+        assert_eq!(
+            resolved_path(resolver.resolve("Foo.md")),
+            Some(PathBuf::from("/vault/Foo.md"))
+        );
+    }
+
+    #[test]
+    fn test_resolve_alias() {
+        let pages = vec![page("/vault/Foo.md", vec!["Bar Baz"])];
+        let resolver = LinkResolver::new(Path::new("/vault"), &pages);
+        assert_eq!(
+            resolved_path(resolver.resolve("bar baz")),
+            Some(PathBuf::from("/vault/Foo.md"))
+        );
+        assert!(matches!(resolver.resolve("Qux"), Resolution::Missing));
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let pages = vec![
+            page("/vault/a/Foo.md", vec![]),
+            page("/vault/b/Foo.md", vec![]),
+        ];
+        let resolver = LinkResolver::new(Path::new("/vault"), &pages);
+        assert!(matches!(resolver.resolve("Foo"), Resolution::Ambiguous(ref p) if p.len() == 2));
+        assert_eq!(
+            resolved_path(resolver.resolve("b/foo")),
+            Some(PathBuf::from("/vault/b/Foo.md"))
+        );
+        assert_eq!(resolver.id(&pages[0]), "a/Foo");
+
+        let pages = vec![
+            page("/vault/Foo.md", vec![]),
+            page("/vault/a/Foo.md", vec![]),
+        ];
+        let resolver = LinkResolver::new(Path::new("/vault"), &pages);
+        assert_eq!(
+            resolved_path(resolver.resolve("/foo")),
+            Some(PathBuf::from("/vault/Foo.md"))
+        );
+        assert!(matches!(resolver.resolve("/Bar"), Resolution::Missing));
+    }
+
+    #[test]
+    fn test_resolve_prefers_exact_case() {
+        let pages = vec![
+            page("/vault/Foo.md", vec![]),
+            page("/vault/a/foo.md", vec![]),
+        ];
+        let resolver = LinkResolver::new(Path::new("/vault"), &pages);
+        assert_eq!(
+            resolved_path(resolver.resolve("foo")),
+            Some(PathBuf::from("/vault/a/foo.md"))
+        );
+        assert!(matches!(resolver.resolve("FOO"), Resolution::Ambiguous(_)));
+    }
+
+    #[test]
+    fn test_broken_anchors() {
+        let pages = vec![
+            page_with_contents("/vault/Foo.md", vec![], "# Intro\n\nText ^b1\n"),
+            page_with_contents(
+                "/vault/Bar.md",
+                vec![],
+                "[[Foo#Intro]] [[Foo#^b1]] [[Foo#Outro]] [[Foo#^b2]] [[#Bar]] [[Qux]]\n",
+            ),
+        ];
+        let resolver = LinkResolver::new(Path::new("/vault"), &pages);
+        let problems = resolver
+            .broken_links(&pages)
+            .into_iter()
+            .map(|broken| broken.problem)
+            .collect::<Vec<LinkProblem>>();
+        assert_eq!(
+            problems,
+            vec![
+                LinkProblem::MissingHeading("Outro".to_string()),
+                LinkProblem::MissingBlock("b2".to_string()),
+                LinkProblem::MissingHeading("Bar".to_string()),
+                LinkProblem::Missing,
+            ]
+        );
+    }
+}
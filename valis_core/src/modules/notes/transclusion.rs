@@ -0,0 +1,209 @@
+use crate::modules::notes::markdown::{
+    block, map_lines_outside_code, parse_wikilink, section, strip_front_matter, Page, WikilinkType,
+    WIKILINK_REGEX,
+};
+use crate::modules::notes::resolver::{LinkResolver, Resolution};
+
+/// The contents of a page with all its note embeds inlined.
+#[derive(Debug, Clone)]
+pub struct Transclusion {
+    pub contents: String,
+    /// The vault ids of all the pages embedded, directly or not, in the page.
+    pub dependencies: Vec<String>,
+    /// The embeds which were left as links, because they would embed themselves.
+    pub cycles: Vec<String>,
+}
+
+/// Inline the notes, sections (`![[Note#Section]]`) and blocks (`![[Note#^blockid]]`)
+/// embedded in `page`, recursively. Embeds of images, PDFs and audio are left untouched,
+/// as are embeds which can't be resolved or would create a cycle.
+pub fn transclude(page: &Page, resolver: &LinkResolver) -> Transclusion {
+    transclude_with(page, resolver, &|_| true)
+}
+
+/// Like [`transclude`], but only inline the pages for which `allow` is true.
+/// The embeds of other pages are left untouched.
+pub fn transclude_with(
+    page: &Page,
+    resolver: &LinkResolver,
+    allow: &dyn Fn(&Page) -> bool,
+) -> Transclusion {
+    let mut transclusion = Transclusion {
+        contents: "".to_string(),
+        dependencies: vec![],
+        cycles: vec![],
+    };
+    let mut stack = vec![(resolver.id(page), None)];
+    transclusion.contents = expand(
+        &page.contents,
+        page,
+        resolver,
+        allow,
+        &mut stack,
+        &mut transclusion,
+    );
+    transclusion
+}
+
+fn expand(
+    contents: &str,
+    current: &Page,
+    resolver: &LinkResolver,
+    allow: &dyn Fn(&Page) -> bool,
+    stack: &mut Vec<(String, Option<String>)>,
+    transclusion: &mut Transclusion,
+) -> String {
+    // Embeds in code blocks are left as they are
+    map_lines_outside_code(contents, |line| {
+        WIKILINK_REGEX
+            .replace_all(line, |caps: &regex::Captures| {
+                let original = caps[0].to_string();
+                let wikilink = match parse_wikilink(&caps[2]) {
+                    Ok(wikilink) if &caps[1] == "!" && wikilink.link_type == WikilinkType::TEXT => {
+                        wikilink
+                    }
+                    _ => return original,
+                };
+                // Embeds without a link refer to the current page
+                let target = if wikilink.link.is_empty() {
+                    current
+                } else {
+                    match resolver.resolve_link(&wikilink) {
+                        Resolution::Resolved(target) if allow(target) => target,
+                        _ => return original,
+                    }
+                };
+
+                let key = (resolver.id(target), wikilink.fragment());
+                if stack.contains(&key) {
+                    transclusion.cycles.push(original.clone());
+                    // Render the embed as a plain link
+                    return original[1..].to_string();
+                }
+
+                let embedded = if !wikilink.block.is_empty() {
+                    block(&target.contents, &wikilink.block)
+                } else if let Some(fragment) = &key.1 {
+                    section(&target.contents, fragment).map(|s| s.to_string())
+                } else {
+                    Some(strip_front_matter(&target.contents).trim_end().to_string())
+                };
+                let embedded = match embedded {
+                    Some(embedded) => embedded,
+                    None => return original,
+                };
+
+                if key.0 != stack[0].0 && !transclusion.dependencies.contains(&key.0) {
+                    transclusion.dependencies.push(key.0.clone());
+                }
+                stack.push(key);
+                let expanded = expand(&embedded, target, resolver, allow, stack, transclusion);
+                stack.pop();
+                expanded
+            })
+            .into_owned()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::modules::notes::markdown::{extract_links, PageLoader};
+
+    fn page(path: &str, contents: &str) -> Page {
+        let path = PathBuf::from(path);
+        Page {
+            title: Page::title_from_path(&path),
+            path,
+            contents: contents.to_string(),
+            wikilinks: extract_links(contents),
+            aliases: vec![],
+            tags: vec![],
+        }
+    }
+
+    #[test]
+    fn test_transclude_note_and_section() {
+        let pages = vec![
+            page("/vault/A.md", "Before\n![[B]]\n![[C#Two]]\n![[pic.png]]"),
+            page("/vault/B.md", "---\npublish: true\n---\nB body\n"),
+            page("/vault/C.md", "# One\n1\n## Two\n2\n# Three\n3\n"),
+        ];
+        let resolver = LinkResolver::new(Path::new("/vault"), &pages);
+        let transclusion = transclude(&pages[0], &resolver);
+        assert_eq!(
+            transclusion.contents,
+            "Before\nB body\n## Two\n2\n![[pic.png]]"
+        );
+        assert_eq!(transclusion.dependencies, vec!["B", "C"]);
+        assert!(transclusion.cycles.is_empty());
+    }
+
+    #[test]
+    fn test_transclude_outside_code() {
+        let pages = vec![
+            page(
+                "/vault/A.md",
+                "![[B]]
+```
+![[B]]
+```
+~~~md
+![[B]]
+~~~",
+            ),
+            page("/vault/B.md", "B"),
+        ];
+        let resolver = LinkResolver::new(Path::new("/vault"), &pages);
+        assert_eq!(
+            transclude(&pages[0], &resolver).contents,
+            "B
+```
+![[B]]
+```
+~~~md
+![[B]]
+~~~"
+        );
+    }
+
+    #[test]
+    fn test_transclude_block() {
+        let pages = vec![
+            page("/vault/A.md", "![[B#^one]]"),
+            page("/vault/B.md", "First\nparagraph ^one\n\n- item ^two\n"),
+        ];
+        let resolver = LinkResolver::new(Path::new("/vault"), &pages);
+        assert_eq!(
+            transclude(&pages[0], &resolver).contents,
+            "First\nparagraph"
+        );
+    }
+
+    #[test]
+    fn test_transclude_cycle() {
+        let pages = vec![
+            page("/vault/A.md", "A ![[B]]"),
+            page("/vault/B.md", "B ![[A]]"),
+        ];
+        let resolver = LinkResolver::new(Path::new("/vault"), &pages);
+        let transclusion = transclude(&pages[0], &resolver);
+        assert_eq!(transclusion.contents, "A B [[A]]");
+        assert_eq!(transclusion.cycles, vec!["![[A]]"]);
+    }
+
+    #[test]
+    fn test_transclude_with() {
+        let pages = vec![
+            page("/vault/A.md", "![[B]] ![[C]]"),
+            page("/vault/B.md", "B"),
+            page("/vault/C.md", "C"),
+        ];
+        let resolver = LinkResolver::new(Path::new("/vault"), &pages);
+        let transclusion = transclude_with(&pages[0], &resolver, &|page| page.title != "C");
+        assert_eq!(transclusion.contents, "B ![[C]]");
+        assert_eq!(transclusion.dependencies, vec!["B"]);
+    }
+}
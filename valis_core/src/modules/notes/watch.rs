@@ -0,0 +1,300 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use notify::{Event, RecursiveMode, Watcher};
+use tiny_http::{Header, Response, Server};
+
+use crate::modules::log::{self, ack};
+use crate::modules::notes::humble::{build_with_config, page_output_path, HumbleConfig, Site};
+use crate::modules::plan;
+
+/// The path polled by the live reload script.
+const LIVE_RELOAD_PATH: &str = "/__livereload";
+
+/// Reloads the page when the build version served at [`LIVE_RELOAD_PATH`] changes.
+const LIVE_RELOAD_SCRIPT: &str = "<script>(function(){var v=null;setInterval(function(){fetch('/__livereload').then(function(r){return r.text()}).then(function(t){if(v!==null&&t!==v){location.reload()}v=t}).catch(function(){})},1000)})();</script>";
+
+/// `WatchConfig` holds the options of the watch mode.
+/// - `debounce`: How long to wait for the vault to be quiet before rebuilding, so that
+///   a burst of editor saves triggers a single build. Default is 300ms.
+/// - `serve`: The local port on which to serve the build destination, with live reload.
+pub struct WatchConfig {
+    pub debounce: Duration,
+    pub serve: Option<u16>,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        WatchConfig {
+            debounce: Duration::from_millis(300),
+            serve: None,
+        }
+    }
+}
+
+/// Whether a change to `path` should trigger a build. Changes to the build outputs
+/// and to hidden directories (`.git`, `.obsidian`, ...) are ignored.
+pub fn is_relevant(path: &Path, source: &Path, outputs: &[&Path]) -> bool {
+    if outputs.iter().any(|output| path.starts_with(output)) {
+        return false;
+    }
+    let relative = path.strip_prefix(source).unwrap_or(path);
+    !relative.components().any(|component| match component {
+        Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+        _ => false,
+    })
+}
+
+/// Remove the outputs of the pages of `previous` which are not in `current` anymore,
+/// e.g. because they were renamed or unpublished.
+fn remove_stale_outputs(previous: &Site, current: &Site, destination: &Path) {
+    let current_outputs = current
+        .pages
+        .iter()
+        .map(|page| page_output_path(destination, page))
+        .collect::<HashSet<PathBuf>>();
+    for page in &previous.pages {
+        let output = page_output_path(destination, page);
+        if !current_outputs.contains(&output)
+            && output.exists()
+            && plan::remove_file(&output).is_ok()
+        {
+            ack(&format!("removed {}", output.display()));
+        }
+    }
+}
+
+/// Decode the `%XX` escapes of a URL path segment, read as UTF-8.
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// The file to serve for the request `url`, in `root`. Directories are served with
+/// their `index.html`, and paths escaping `root` are rejected.
+pub fn request_path(root: &Path, url: &str) -> Option<PathBuf> {
+    let path = url.split(['?', '#']).next().unwrap_or("");
+    let mut file = root.to_path_buf();
+    for segment in path.split('/') {
+        let segment = percent_decode(segment);
+        match segment.as_str() {
+            "" | "." => {}
+            ".." => return None,
+            // An escaped separator would climb out of the segment
+            segment if segment.contains(['/', '\\']) => return None,
+            segment => file.push(segment),
+        }
+    }
+    if file.is_dir() {
+        file.push("index.html");
+    }
+    Some(file)
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).unwrap_or("") {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css",
+        "js" => "application/javascript",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "md" | "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "pdf" => "application/pdf",
+        "mp3" => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Serve `root` on `port` in the background. HTML pages are served with a script which
+/// reloads them when `version` changes.
+fn serve(root: PathBuf, port: u16, version: Arc<AtomicUsize>) -> Result<(), String> {
+    let server = Server::http(("127.0.0.1", port)).map_err(|e| e.to_string())?;
+    ack(&format!(
+        "serving {} on http://127.0.0.1:{}/",
+        root.display(),
+        port
+    ));
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            if request.url() == LIVE_RELOAD_PATH {
+                let _ = request.respond(Response::from_string(
+                    version.load(Ordering::SeqCst).to_string(),
+                ));
+                continue;
+            }
+            let file = match request_path(&root, request.url()) {
+                Some(file) if file.is_file() => file,
+                _ => {
+                    let _ =
+                        request.respond(Response::from_string("Not found").with_status_code(404));
+                    continue;
+                }
+            };
+            let mut body = match fs::read(&file) {
+                Ok(body) => body,
+                Err(_) => {
+                    let _ = request.respond(Response::from_string("Error").with_status_code(500));
+                    continue;
+                }
+            };
+            let content_type = content_type(&file);
+            if content_type.starts_with("text/html") {
+                let html = String::from_utf8_lossy(&body);
+                body = match html.rfind("</body>") {
+                    Some(end) => format!("{}{}{}", &html[..end], LIVE_RELOAD_SCRIPT, &html[end..]),
+                    None => format!("{}{}", html, LIVE_RELOAD_SCRIPT),
+                }
+                .into_bytes();
+            }
+            let header = Header::from_bytes("Content-Type", content_type).unwrap();
+            let _ = request.respond(Response::from_data(body).with_header(header));
+        }
+    });
+    Ok(())
+}
+
+/// Build the site with Humble, then watch `source` and rebuild the whole site whenever it
+/// changes. Outputs whose contents didn't change are left untouched. Runs until interrupted.
+pub fn watch(
+    source: PathBuf,
+    destination: PathBuf,
+    assets: PathBuf,
+    config: &HumbleConfig,
+    watch_config: &WatchConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let version = Arc::new(AtomicUsize::new(0));
+    let mut site = build_with_config(source.clone(), destination.clone(), assets.clone(), config)?;
+
+    if let Some(port) = watch_config.serve {
+        serve(destination.clone(), port, version.clone())?;
+    }
+
+    let (sender, receiver) = channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        if let Ok(event) = event {
+            let _ = sender.send(event);
+        }
+    })?;
+    watcher.watch(&source, RecursiveMode::Recursive)?;
+    ack(&format!("watching {}", source.display()));
+
+    let outputs = [destination.as_path(), assets.as_path()];
+    loop {
+        // Wait for a change, then for the vault to be quiet
+        let mut changed: HashSet<PathBuf> = HashSet::new();
+        let event = receiver.recv()?;
+        changed.extend(event.paths);
+        loop {
+            match receiver.recv_timeout(watch_config.debounce) {
+                Ok(event) => changed.extend(event.paths),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+        changed.retain(|path| is_relevant(path, &source, &outputs));
+        if changed.is_empty() {
+            continue;
+        }
+        let changed_pages = changed
+            .iter()
+            .filter(|path| path.extension().map(|e| e == "md").unwrap_or(false))
+            .map(|path| {
+                path.strip_prefix(&source)
+                    .unwrap_or(path)
+                    .with_extension("")
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect::<HashSet<String>>();
+        // The whole site is rebuilt, as backlinks, tag pages and feeds depend on every note
+        ack(&format!(
+            "{} notes changed, rebuilding the site",
+            changed_pages.len()
+        ));
+        // A note which breaks the build is reported, and the next change retried
+        match build_with_config(source.clone(), destination.clone(), assets.clone(), config) {
+            Ok(rebuilt) => {
+                remove_stale_outputs(&site, &rebuilt, &destination);
+                site = rebuilt;
+            }
+            Err(e) => {
+                log::error(&format!("Failed to rebuild the site: {}", e));
+                continue;
+            }
+        }
+        version.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_relevant() {
+        let source = Path::new("/vault");
+        let outputs = [Path::new("/vault/public")];
+        assert!(is_relevant(
+            Path::new("/vault/notes/A.md"),
+            source,
+            &outputs
+        ));
+        assert!(!is_relevant(
+            Path::new("/vault/.obsidian/app.json"),
+            source,
+            &outputs
+        ));
+        assert!(!is_relevant(
+            Path::new("/vault/public/posts/A.md"),
+            source,
+            &outputs
+        ));
+    }
+
+    #[test]
+    fn test_request_path() {
+        let root = Path::new("/site");
+        assert_eq!(
+            request_path(root, "/posts/My%20Note.md?x=1"),
+            Some(PathBuf::from("/site/posts/My Note.md"))
+        );
+        assert_eq!(
+            request_path(root, "/posts/Caf%C3%A9%20%E2%80%94%20notes.md"),
+            Some(PathBuf::from("/site/posts/Café — notes.md"))
+        );
+        assert_eq!(
+            request_path(root, "/posts/100%.md"),
+            Some(PathBuf::from("/site/posts/100%.md"))
+        );
+        assert_eq!(request_path(root, "/../etc/passwd"), None);
+        assert_eq!(request_path(root, "/posts/..%2F..%2Fetc/passwd"), None);
+    }
+}
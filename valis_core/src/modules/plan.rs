@@ -0,0 +1,241 @@
+//! Dry-run mode: instead of running commands, writing files and changing databases, the
+//! side-effecting operations of valis record what they would do into a plan, which can be
+//! reviewed before running an automation for real.
+//!
+//! ```
+//! use valis_core::modules::{core, plan};
+//!
+//! plan::set_dry_run(true);
+//! core::run("kind create cluster").unwrap();
+//! for action in plan::take() {
+//!     println!("{}", action);
+//! }
+//! ```
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use colored::*;
+use rusqlite::types::{ToSqlOutput, Value};
+use rusqlite::{Connection, ToSql};
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+static PLAN: Mutex<Vec<Action>> = Mutex::new(Vec::new());
+
+/// An operation recorded in dry-run mode.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// A command line, quoted as for a shell.
+    Command(String),
+    /// A file written with `bytes` bytes.
+    Write { path: PathBuf, bytes: usize },
+    /// A file copied from `from` to `to`.
+    Copy { from: PathBuf, to: PathBuf },
+    /// A file moved from `from` to `to`.
+    Rename { from: PathBuf, to: PathBuf },
+    /// A file, or a directory and its contents, removed.
+    Remove(PathBuf),
+    /// The tables of the database `db` created.
+    Schema { db: String },
+    /// An SQL statement changing the database `db`, with its parameters.
+    Sql {
+        db: String,
+        statement: String,
+        params: Vec<String>,
+    },
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Command(command) => write!(f, "run {}", command),
+            Action::Write { path, bytes } => {
+                write!(f, "write {} ({} bytes)", path.display(), bytes)
+            }
+            Action::Copy { from, to } => write!(f, "copy {} to {}", from.display(), to.display()),
+            Action::Rename { from, to } => {
+                write!(f, "move {} to {}", from.display(), to.display())
+            }
+            Action::Remove(path) => write!(f, "remove {}", path.display()),
+            Action::Schema { db } => write!(f, "create the tables of {}", db),
+            Action::Sql {
+                db,
+                statement,
+                params,
+            } => {
+                let statement = statement.split_whitespace().collect::<Vec<_>>().join(" ");
+                write!(f, "sql {}: {}", db, statement)?;
+                if !params.is_empty() {
+                    write!(f, " [{}]", params.join(", "))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Enable or disable dry-run mode, for the whole process.
+pub fn set_dry_run(enabled: bool) {
+    DRY_RUN.store(enabled, Ordering::SeqCst);
+}
+
+/// Whether dry-run mode is enabled.
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::SeqCst)
+}
+
+/// Add `action` to the plan.
+pub fn record(action: Action) {
+    PLAN.lock().unwrap_or_else(|e| e.into_inner()).push(action);
+}
+
+/// The actions recorded so far.
+pub fn actions() -> Vec<Action> {
+    PLAN.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Remove and return the actions recorded so far.
+pub fn take() -> Vec<Action> {
+    std::mem::take(&mut *PLAN.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// Print the actions recorded so far, numbered, and clear the plan.
+pub fn print_plan() {
+    let actions = take();
+    if actions.is_empty() {
+        println!("📋 {}: nothing to do", "plan".cyan());
+        return;
+    }
+    let noun = if actions.len() == 1 {
+        "action"
+    } else {
+        "actions"
+    };
+    println!("📋 {}: {} {}", "plan".cyan(), actions.len(), noun);
+    for (index, action) in actions.iter().enumerate() {
+        println!("{:>4}. {}", index + 1, action);
+    }
+}
+
+/// Write `contents` to `path`, or record it in dry-run mode.
+pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    if is_dry_run() {
+        record(Action::Write {
+            path: path.as_ref().to_path_buf(),
+            bytes: contents.as_ref().len(),
+        });
+        return Ok(());
+    }
+    fs::write(path, contents)
+}
+
+/// Copy the file `from` to `to`, or record it in dry-run mode.
+pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    if is_dry_run() {
+        record(Action::Copy {
+            from: from.as_ref().to_path_buf(),
+            to: to.as_ref().to_path_buf(),
+        });
+        return Ok(());
+    }
+    fs::copy(from, to).map(|_| ())
+}
+
+/// Move the file `from` to `to`, or record it in dry-run mode.
+pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    if is_dry_run() {
+        record(Action::Rename {
+            from: from.as_ref().to_path_buf(),
+            to: to.as_ref().to_path_buf(),
+        });
+        return Ok(());
+    }
+    fs::rename(from, to)
+}
+
+/// Remove the file `path`, or record it in dry-run mode.
+pub fn remove_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    if is_dry_run() {
+        record(Action::Remove(path.as_ref().to_path_buf()));
+        return Ok(());
+    }
+    fs::remove_file(path)
+}
+
+/// Remove the directory `path` and its contents, or record it in dry-run mode.
+pub fn remove_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    if is_dry_run() {
+        record(Action::Remove(path.as_ref().to_path_buf()));
+        return Ok(());
+    }
+    fs::remove_dir_all(path)
+}
+
+/// Create a directory and its parents. Nothing is created in dry-run mode, where the
+/// writes into the directory are recorded instead.
+pub fn create_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    if is_dry_run() {
+        return Ok(());
+    }
+    fs::create_dir_all(path)
+}
+
+fn param_to_string(param: &dyn ToSql) -> String {
+    let value = match param.to_sql() {
+        Ok(ToSqlOutput::Borrowed(value)) => value.into(),
+        Ok(ToSqlOutput::Owned(value)) => value,
+        _ => return "?".to_string(),
+    };
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Real(r) => r.to_string(),
+        Value::Text(text) => format!("{:?}", text),
+        Value::Blob(blob) => format!("<{} bytes>", blob.len()),
+    }
+}
+
+/// Execute an SQL statement changing the database, or record it in dry-run mode.
+/// # Returns
+/// The number of rows changed, 0 in dry-run mode.
+pub fn execute(
+    conn: &Connection,
+    statement: &str,
+    params: &[&dyn ToSql],
+) -> rusqlite::Result<usize> {
+    if is_dry_run() {
+        record(Action::Sql {
+            db: conn.path().unwrap_or(":memory:").to_string(),
+            statement: statement.to_string(),
+            params: params.iter().map(|param| param_to_string(*param)).collect(),
+        });
+        return Ok(0);
+    }
+    conn.execute(statement, params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_action_display() {
+        let action = Action::Sql {
+            db: "valis.db".to_string(),
+            statement: "DELETE FROM sprint\n    WHERE id = ?1".to_string(),
+            params: vec![param_to_string(&"42"), param_to_string(&7)],
+        };
+        assert_eq!(
+            action.to_string(),
+            "sql valis.db: DELETE FROM sprint WHERE id = ?1 [\"42\", 7]"
+        );
+        let action = Action::Write {
+            path: PathBuf::from("/tmp/site/index.md"),
+            bytes: 12,
+        };
+        assert_eq!(action.to_string(), "write /tmp/site/index.md (12 bytes)");
+    }
+}
@@ -0,0 +1,412 @@
+//! Asynchronous process execution, streaming the output of commands line by line as they
+//! run.
+//!
+//! Commands run in their own process group: while they run, Ctrl-C is caught by valis and
+//! forwarded to them as `SIGINT`, and they are killed if they don't exit within
+//! [`INTERRUPT_GRACE`]. Interrupted commands are reported in [`CommandOutput::interrupted`]
+//! for the caller to stop. Once they have exited, Ctrl-C stops valis again.
+use std::error::Error;
+use std::io;
+use std::process::Stdio;
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::process::{Child, Command as ProcessCommand};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use crate::modules::core::{self, Command, CommandOutput};
+
+/// How long a command may take to exit after Ctrl-C before it is killed.
+pub const INTERRUPT_GRACE: Duration = Duration::from_secs(5);
+
+/// An output stream of a command.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+enum Event {
+    Line(usize, Stream, String),
+    Exit(usize, io::Result<CommandOutput>),
+}
+
+/// Send the lines read from `pipe` as events. Invalid UTF-8 is replaced rather than ending
+/// the stream.
+fn read_lines<R>(
+    index: usize,
+    stream: Stream,
+    pipe: R,
+    events: mpsc::UnboundedSender<Event>,
+) -> JoinHandle<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(pipe);
+        let mut buffer = Vec::new();
+        loop {
+            buffer.clear();
+            match reader.read_until(b'\n', &mut buffer).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    let line = String::from_utf8_lossy(&buffer);
+                    let line = line.trim_end_matches('\n').trim_end_matches('\r');
+                    let _ = events.send(Event::Line(index, stream, line.to_string()));
+                }
+            }
+        }
+    })
+}
+
+/// Send `signal` to the process group of `child`, so that the processes it started get it
+/// too, as with Ctrl-C in a terminal.
+#[cfg(unix)]
+fn signal_group(child: &Child, signal: libc::c_int) {
+    if let Some(pid) = child.id() {
+        core::signal_group(pid, signal);
+    }
+}
+
+#[cfg(unix)]
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Catches Ctrl-C until dropped, when the previous `SIGINT` handler is restored. Tokio's
+/// handler isn't used, as it stays installed for the rest of the process.
+#[cfg(unix)]
+struct InterruptGuard {
+    previous: libc::sigaction,
+}
+
+#[cfg(unix)]
+impl InterruptGuard {
+    fn install() -> InterruptGuard {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            libc::sigaction(libc::SIGINT, &action, &mut previous);
+            InterruptGuard { previous }
+        }
+    }
+
+    /// Wait for Ctrl-C.
+    async fn interrupted(&self) {
+        while !INTERRUPTED.load(Ordering::SeqCst) {
+            time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        unsafe {
+            libc::sigaction(libc::SIGINT, &self.previous, std::ptr::null_mut());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct InterruptGuard;
+
+#[cfg(not(unix))]
+impl InterruptGuard {
+    fn install() -> InterruptGuard {
+        InterruptGuard
+    }
+
+    /// Wait for Ctrl-C.
+    async fn interrupted(&self) {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Kill `child` and the processes it started.
+async fn kill(child: &mut Child) -> io::Result<()> {
+    if let Some(pid) = child.id() {
+        core::kill_group(pid);
+    }
+    child.kill().await
+}
+
+/// Wait for `child`, killing it after `timeout`, or interrupting it when `cancel` is set.
+async fn wait(
+    mut child: Child,
+    timeout: Option<Duration>,
+    mut cancel: watch::Receiver<bool>,
+) -> io::Result<CommandOutput> {
+    let deadline = async {
+        match timeout {
+            Some(timeout) => time::sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    let cancelled = async {
+        if cancel.wait_for(|cancelled| *cancelled).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+    let interrupted = tokio::select! {
+        status = child.wait() => {
+            return Ok(CommandOutput {
+                code: status?.code(),
+                ..Default::default()
+            });
+        }
+        _ = deadline => false,
+        _ = cancelled => true,
+    };
+    if !interrupted {
+        kill(&mut child).await?;
+        return Ok(CommandOutput {
+            timed_out: true,
+            ..Default::default()
+        });
+    }
+    #[cfg(unix)]
+    signal_group(&child, libc::SIGINT);
+    let code = match time::timeout(INTERRUPT_GRACE, child.wait()).await {
+        Ok(status) => status?.code(),
+        Err(_) => {
+            kill(&mut child).await?;
+            None
+        }
+    };
+    Ok(CommandOutput {
+        code,
+        interrupted: true,
+        ..Default::default()
+    })
+}
+
+/// Run `commands` concurrently, calling `on_line` with the index of the command, the
+/// stream and the line (without its line ending) for every line they output.
+/// # Returns
+/// The outputs of the commands, in the order of `commands`. `stdout` and `stderr` hold all
+/// the lines which were streamed.
+pub async fn stream_all(
+    commands: &[Command],
+    mut on_line: impl FnMut(usize, Stream, &str),
+) -> Result<Vec<CommandOutput>, Box<dyn Error>> {
+    let planned = commands
+        .iter()
+        .map(|command| command.plan())
+        .collect::<Option<Vec<_>>>();
+    if let Some(outputs) = planned {
+        return Ok(outputs);
+    }
+
+    // Ctrl-C is forwarded to the commands while they run
+    let guard = InterruptGuard::install();
+
+    // Spawn all the commands first, so that they are all killed if one can't be run
+    let mut children = Vec::new();
+    for command in commands {
+        let mut process = ProcessCommand::from(command.to_process_group());
+        if command.input().is_none() {
+            process.stdin(Stdio::null());
+        }
+        process
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        let mut child = process
+            .spawn()
+            .map_err(|e| format!("Could not run `{}`: {}", command, e))?;
+        if let (Some(input), Some(mut stdin)) = (command.input().cloned(), child.stdin.take()) {
+            tokio::spawn(async move {
+                let _ = stdin.write_all(input.expose().as_bytes()).await;
+            });
+        }
+        children.push(child);
+    }
+
+    let (events, mut receiver) = mpsc::unbounded_channel();
+    let (cancel, _) = watch::channel(false);
+    for (index, mut child) in children.into_iter().enumerate() {
+        let readers = [
+            read_lines(
+                index,
+                Stream::Stdout,
+                child.stdout.take().unwrap(),
+                events.clone(),
+            ),
+            read_lines(
+                index,
+                Stream::Stderr,
+                child.stderr.take().unwrap(),
+                events.clone(),
+            ),
+        ];
+        let events = events.clone();
+        let exit = wait(child, commands[index].timeout, cancel.subscribe());
+        tokio::spawn(async move {
+            let exit = exit.await;
+            // Send the last lines before the exit
+            for reader in readers {
+                let _ = reader.await;
+            }
+            let _ = events.send(Event::Exit(index, exit));
+        });
+    }
+    drop(events);
+
+    let mut outputs = vec![CommandOutput::default(); commands.len()];
+    let mut running = commands.len();
+    let mut interrupted = false;
+    let mut error = None;
+    while running > 0 {
+        let event = tokio::select! {
+            event = receiver.recv() => event,
+            _ = guard.interrupted(), if !interrupted => {
+                interrupted = true;
+                let _ = cancel.send(true);
+                continue;
+            }
+        };
+        match event {
+            Some(Event::Line(index, stream, line)) => {
+                on_line(index, stream, &line);
+                let output = &mut outputs[index];
+                let buffer = match stream {
+                    Stream::Stdout => &mut output.stdout,
+                    Stream::Stderr => &mut output.stderr,
+                };
+                buffer.push_str(&line);
+                buffer.push('\n');
+            }
+            Some(Event::Exit(index, exit)) => {
+                running -= 1;
+                match exit {
+                    Ok(exit) => {
+                        let output = &mut outputs[index];
+                        output.code = exit.code;
+                        output.timed_out = exit.timed_out;
+                        output.interrupted = exit.interrupted;
+                    }
+                    Err(e) => {
+                        error.get_or_insert_with(|| {
+                            format!("Could not wait for `{}`: {}", commands[index], e)
+                        });
+                    }
+                }
+            }
+            None => break,
+        }
+    }
+    match error {
+        Some(error) => Err(error.into()),
+        None => Ok(outputs),
+    }
+}
+
+/// Run `command`, calling `on_line` for every line of its stdout and stderr.
+pub async fn stream(
+    command: &Command,
+    mut on_line: impl FnMut(Stream, &str),
+) -> Result<CommandOutput, Box<dyn Error>> {
+    let mut outputs = stream_all(std::slice::from_ref(command), |_, stream, line| {
+        on_line(stream, line)
+    })
+    .await?;
+    Ok(outputs.remove(0))
+}
+
+/// Run `commands` concurrently, printing their lines prefixed with their names, e.g.
+/// `[api] listening on :8080`. Lines from stderr are printed to stderr.
+pub async fn run_all(commands: &[(String, Command)]) -> Result<Vec<CommandOutput>, Box<dyn Error>> {
+    let width = commands
+        .iter()
+        .map(|(name, _)| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let prefixes = commands
+        .iter()
+        .map(|(name, _)| format!("[{:width$}]", name, width = width))
+        .collect::<Vec<_>>();
+    let commands = commands
+        .iter()
+        .map(|(_, command)| command.clone())
+        .collect::<Vec<_>>();
+    stream_all(&commands, |index, stream, line| match stream {
+        Stream::Stdout => println!("{} {}", prefixes[index], line),
+        Stream::Stderr => eprintln!("{} {}", prefixes[index], line),
+    })
+    .await
+}
+
+/// Run `command`, calling `on_line` for every line of its output, blocking until it exits.
+pub fn run_streaming(
+    command: &Command,
+    on_line: impl FnMut(Stream, &str),
+) -> Result<CommandOutput, Box<dyn Error>> {
+    tokio::runtime::Runtime::new()?.block_on(stream(command, on_line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Command {
+        Command::new("sh").arg("-c").arg(script)
+    }
+
+    #[test]
+    fn test_stream() {
+        let mut lines = Vec::new();
+        let output = run_streaming(&sh("echo one; echo two >&2; echo three; exit 3"), |s, l| {
+            lines.push((s, l.to_string()))
+        })
+        .unwrap();
+        assert_eq!(output.code, Some(3));
+        assert_eq!(output.stdout, "one\nthree\n");
+        assert_eq!(output.stderr, "two\n");
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|(stream, _)| *stream == Stream::Stdout)
+                .map(|(_, line)| line.as_str())
+                .collect::<Vec<_>>(),
+            vec!["one", "three"]
+        );
+
+        let output = run_streaming(
+            &sh("echo start; sleep 5").timeout(Duration::from_millis(200)),
+            |_, _| {},
+        )
+        .unwrap();
+        assert!(output.timed_out);
+        assert_eq!(output.stdout, "start\n");
+    }
+
+    #[test]
+    fn test_stream_all() {
+        let commands = vec![sh("sleep 0.2; echo slow"), sh("echo fast")];
+        let mut order = Vec::new();
+        let outputs = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(stream_all(&commands, |index, _, line| {
+                order.push((index, line.to_string()))
+            }))
+            .unwrap();
+        assert_eq!(
+            order,
+            vec![(1, "fast".to_string()), (0, "slow".to_string())]
+        );
+        assert!(outputs.iter().all(|output| output.success()));
+    }
+}
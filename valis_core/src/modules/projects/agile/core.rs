@@ -11,6 +11,7 @@ use db::DatabaseOperations;
 use crate::modules::db;
 use crate::modules::db::get_connection;
 use crate::modules::db::serializers::SerializableDateTime;
+use crate::modules::plan;
 use crate::modules::tasks::todoist::core::Task as TodoisTask;
 
 #[derive(Serialize, Deserialize)]
@@ -29,7 +30,8 @@ impl DatabaseOperations<String> for Project {
         let id = Uuid::new_v4();
         let now = Utc::now().to_string();
 
-        conn.execute(
+        plan::execute(
+            &conn,
             "INSERT INTO project (id, name, description, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
             params![id.to_string(), self.name, self.description, now, now],
         ).ok().unwrap();
@@ -109,7 +111,8 @@ impl DatabaseOperations<String> for Sprint {
 
         let now = Utc::now().to_rfc3339().to_string();
 
-        conn.execute(
+        plan::execute(
+            &conn,
             "INSERT INTO sprint (id, project_id, name, start_date, end_date, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
             params![self.id.to_string(), &self.project_id.to_string(), &self.name, &self.start_date.with_timezone(&Utc).to_string(), &self.end_date.with_timezone(&Utc).to_string(), now, now],
         ).ok().unwrap();
@@ -174,6 +177,13 @@ pub fn sprint_get_all(db: &str) -> Result<Vec<Sprint>, rusqlite::Error> {
     Sprint::get_all(db)
 }
 
+/// The first active sprint, if any.
+pub fn sprint_get_active(db: &str) -> Result<Option<Sprint>, rusqlite::Error> {
+    Ok(Sprint::get_all(db)?
+        .into_iter()
+        .find(|sprint| sprint.is_active()))
+}
+
 impl Default for Sprint {
     fn default() -> Self {
         let now = SerializableDateTime::now();
@@ -198,14 +208,18 @@ impl Sprint {
 
 // Delete a project, given the project id
 pub fn delete_project_by_id(conn: &Connection, id: Uuid) -> Result<()> {
-    conn.execute("DELETE FROM project WHERE id = ?1", params![id.to_string()])?;
+    plan::execute(
+        conn,
+        "DELETE FROM project WHERE id = ?1",
+        params![id.to_string()],
+    )?;
 
     Ok(())
 }
 
 // Delete a project, given the project name
 pub fn delete_project_by_name(conn: &Connection, name: &str) -> Result<()> {
-    conn.execute("DELETE FROM project WHERE name = ?1", params![name])?;
+    plan::execute(conn, "DELETE FROM project WHERE name = ?1", params![name])?;
 
     Ok(())
 }
@@ -221,7 +235,8 @@ pub fn create_sprint(
     let id = Uuid::new_v4();
     let now = Utc::now().to_string();
 
-    conn.execute(
+    plan::execute(
+        conn,
         "INSERT INTO sprint (id, project_id, name, start_date, end_date, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
         params![id.to_string(), project_id.to_string(), name, start_date.to_string(), end_date.to_string(), now, now],
     )?;
@@ -231,7 +246,11 @@ pub fn create_sprint(
 
 // Delete a Sprint
 pub fn delete_sprint(conn: &Connection, id: Uuid) -> Result<()> {
-    conn.execute("DELETE FROM sprint WHERE id = ?1", params![id.to_string()])?;
+    plan::execute(
+        conn,
+        "DELETE FROM sprint WHERE id = ?1",
+        params![id.to_string()],
+    )?;
 
     Ok(())
 }
@@ -1,7 +1,8 @@
-use rlua::Context;
+use rlua::{Context, Error};
 
 use uuid::Uuid;
 
+use crate::modules::config;
 use crate::modules::db;
 use crate::modules::db::serializers::SerializableDateTime;
 use crate::modules::db::DatabaseOperations;
@@ -10,35 +11,38 @@ use crate::modules::projects::agile::core::{print_sprint_info, Project};
 
 pub fn agile_create_project(ctx: &Context) {
     let f = ctx
-        .create_function(|ctx, (name, description, path): (String, String, String)| {
-            db::init_db(&path).ok().unwrap();
-            let project = Project {
-                name,
-                description,
-                ..Default::default()
-            };
-            project.save(&path).ok().unwrap();
-            let project_table = ctx.create_table().ok().unwrap();
-            project_table
-                .set("id", project.id.to_string())
-                .ok()
-                .unwrap();
-            project_table.set("name", project.name).ok().unwrap();
-            project_table
-                .set("description", project.description)
-                .ok()
-                .unwrap();
-            project_table
-                .set("created_at", project.created_at.to_string())
-                .ok()
-                .unwrap();
-            project_table
-                .set("update_at", project.updated_at.to_string())
-                .ok()
-                .unwrap();
+        .create_function(
+            |ctx, (name, description, path): (String, String, Option<String>)| {
+                let path = path.unwrap_or_else(|| config::get().db_path());
+                db::init_db(&path).map_err(|e| Error::external(e.to_string()))?;
+                let project = Project {
+                    name,
+                    description,
+                    ..Default::default()
+                };
+                project.save(&path).ok().unwrap();
+                let project_table = ctx.create_table().ok().unwrap();
+                project_table
+                    .set("id", project.id.to_string())
+                    .ok()
+                    .unwrap();
+                project_table.set("name", project.name).ok().unwrap();
+                project_table
+                    .set("description", project.description)
+                    .ok()
+                    .unwrap();
+                project_table
+                    .set("created_at", project.created_at.to_string())
+                    .ok()
+                    .unwrap();
+                project_table
+                    .set("update_at", project.updated_at.to_string())
+                    .ok()
+                    .unwrap();
 
-            Ok(project_table)
-        })
+                Ok(project_table)
+            },
+        )
         .unwrap();
     ctx.globals().set("agile_create_project", f).unwrap();
 }
@@ -46,8 +50,9 @@ pub fn agile_create_project(ctx: &Context) {
 pub fn agile_create_sprint(ctx: &Context) {
     let f = ctx
         .create_function(
-            |ctx, (project_id, name, start_date, path): (String, String, String, String)| {
-                db::init_db(&path).ok().unwrap();
+            |ctx, (project_id, name, start_date, path): (String, String, String, Option<String>)| {
+                let path = path.unwrap_or_else(|| config::get().db_path());
+                db::init_db(&path).map_err(|e| Error::external(e.to_string()))?;
                 let id = Uuid::new_v4();
                 let sprint = agile::core::Sprint {
                     id,
@@ -92,8 +97,9 @@ pub fn agile_create_sprint(ctx: &Context) {
 
 pub fn agile_show_sprint(ctx: &Context) {
     let f = ctx
-        .create_function(|_ctx, (id, path): (String, String)| {
-            db::init_db(&path).ok().unwrap();
+        .create_function(|_ctx, (id, path): (String, Option<String>)| {
+            let path = path.unwrap_or_else(|| config::get().db_path());
+            db::init_db(&path).map_err(|e| Error::external(e.to_string()))?;
             let id = Uuid::parse_str(&id).ok().unwrap();
             let _ = print_sprint_info(&path, id);
             Ok(())
@@ -50,6 +50,18 @@ pub fn get_git_project_root_path(path: PathBuf) -> Option<PathBuf> {
     Some(workdir.to_path_buf())
 }
 
+/// Get the name of the branch checked out in the git project containing `path`.
+/// Returns `None` outside of a git project, or when HEAD is detached.
+pub fn get_git_current_branch(path: PathBuf) -> Option<String> {
+    let empty_string_vec: Vec<String> = Vec::new();
+    let repo = Repository::open_ext(path, RepositoryOpenFlags::empty(), empty_string_vec).ok()?;
+    let head = repo.head().ok()?;
+    if !head.is_branch() {
+        return None;
+    }
+    head.shorthand().map(|name| name.to_owned())
+}
+
 /// Get the full path from a partial path, relative to the git project root.
 /// # Arguments
 /// * `partial_path` - A string slice that holds the partial path of the file.
@@ -2,6 +2,9 @@ use std::path::PathBuf;
 
 use git2::Repository;
 
+use crate::modules::core::Command;
+use crate::modules::{config, log, plan};
+
 #[derive(Debug)]
 pub struct VirtualEnv {
     pub name: String,
@@ -11,7 +14,8 @@ pub struct VirtualEnv {
 }
 
 /// Returns a `VirtualEnv` struct for the specified `PathBuf` path.
-/// Assumes that the virtualenvs are located in `~/.virtualenvs` and that
+/// Assumes that the virtualenvs are located in the `virtualenvs` directory of the
+/// configuration (`~/.virtualenvs` by default) and that
 /// the virtualenv name is the same as the project name.
 ///
 /// # Arguments
@@ -23,8 +27,7 @@ pub fn get_venv_info(path: PathBuf) -> VirtualEnv {
     let name = root.file_name().unwrap();
     let mut requirements = root.clone();
     requirements.push("requirements.txt");
-    let mut virtualvenv = home::home_dir().unwrap();
-    virtualvenv.push(".virtualenvs");
+    let mut virtualvenv = config::get().virtualenvs.clone();
     virtualvenv.push(name);
     let virtualenv = VirtualEnv {
         name: name.to_str().unwrap().to_string(),
@@ -36,53 +39,39 @@ pub fn get_venv_info(path: PathBuf) -> VirtualEnv {
 }
 
 /// Rebuilds the virtualenv for the current project at `path`.
-/// Assumes that the virtualenvs are located in `~/.virtualenvs` and that the virtualenv name is the same as the project name.
+/// Assumes that the virtualenvs are located in the configured `virtualenvs` directory and that the virtualenv name is the same as the project name.
 /// # Arguments
 /// * `path` - A `PathBuf` object that holds the path to the project root.
 pub fn rebuild(venv: VirtualEnv) {
     // Check if virtualenv exists
     if venv.location.exists() {
         // Delete the original environment
-        let _ = std::fs::remove_dir_all(&venv.location);
+        if let Err(e) = plan::remove_dir_all(&venv.location) {
+            log::error(&format!("Failed to delete the virtualenv: {}", e));
+            return;
+        }
     }
 
     // Recreate it
-    let output = std::process::Command::new("python3")
-        .arg("-m")
-        .arg("venv")
-        .arg(&venv.location)
-        .output()
-        .expect("Failed to create virtualenv");
-
-    if !output.status.success() {
-        eprintln!(
-            "Failed to create virtualenv: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
+    let location = venv.location.to_string_lossy();
+    let command = Command::new("python3").args(&["-m", "venv", &location]);
+    if let Err(e) = command.output().and_then(|output| output.check(&command)) {
+        log::error(&format!("Failed to create virtualenv: {}", e));
         return;
     }
 
     // Install requirements.txt if it exists
     if venv.requirements.exists() {
-        let output = std::process::Command::new("pip")
-            .arg("install")
-            .arg("-r")
-            .arg(&venv.requirements)
-            .output()
-            .expect("Failed to install requirements");
-
-        if !output.status.success() {
-            eprintln!(
-                "Failed to install requirements: {}",
-                String::from_utf8_lossy(&output.stderr)
-            );
-            return;
+        let requirements = venv.requirements.to_string_lossy();
+        let command = Command::new("pip").args(&["install", "-r", &requirements]);
+        if let Err(e) = command.output().and_then(|output| output.check(&command)) {
+            log::error(&format!("Failed to install requirements: {}", e));
         }
     }
 }
 
 /// Prints the status of the virtualenv for the current project at `path`.
-/// Assumes that the virtualenvs are located in `~/.virtualenvs` and that
+/// Assumes that the virtualenvs are located in the configured `virtualenvs` directory and that
 /// the virtualenv name is the same as the project name.
 ///
 /// # Arguments
@@ -1,17 +1,22 @@
 use std::path::PathBuf;
+use std::time::Duration;
 
 use rlua::Error as LuaError;
 
 use rlua::Table;
-use rlua::{Context, Lua, Result, Value};
+use rlua::{Context, Function, Lua, MultiValue, Result, Value};
 use termion::color;
 
+use crate::modules::admin::{self, credentials};
+use crate::modules::config;
 use crate::modules::core;
-use crate::modules::formats::text;
-use crate::modules::formats::yaml::{get_yaml_value, update_yaml_value};
-use crate::modules::log::ack;
+use crate::modules::formats;
+use crate::modules::log::{self, ack, Level};
+use crate::modules::notes;
 use crate::modules::notes::markdown;
 use crate::modules::notes::markdown::Page;
+use crate::modules::plan;
+use crate::modules::process;
 use crate::modules::projects::git::core::{GitOperations, SimpleRepo};
 use crate::modules::projects::{agile, git};
 use crate::modules::tasks::todoist;
@@ -51,6 +56,63 @@ fn pretty_print_table(table: &Table, indent: usize) -> Result<()> {
     Ok(())
 }
 
+/// A command from a Lua command line or argv table, with the options `cwd`, `env` (a
+/// table) and `timeout` (in seconds).
+pub(crate) fn command_from_lua(command: Value, options: Option<Table>) -> Result<core::Command> {
+    let mut command = match command {
+        Value::String(line) => core::Command::parse(line.to_str()?)
+            .map_err(|e| LuaError::RuntimeError(e.to_string()))?,
+        Value::Table(argv) => {
+            let argv = argv
+                .sequence_values::<String>()
+                .collect::<Result<Vec<_>>>()?;
+            let (program, args) = argv
+                .split_first()
+                .ok_or_else(|| LuaError::RuntimeError("Empty command".to_string()))?;
+            core::Command::new(program).args(args)
+        }
+        _ => {
+            return Err(LuaError::RuntimeError(
+                "The command must be a string or a table".to_string(),
+            ))
+        }
+    };
+    if let Some(options) = options {
+        if let Some(cwd) = options.get::<_, Option<String>>("cwd")? {
+            command = command.cwd(cwd);
+        }
+        if let Some(env) = options.get::<_, Option<Table>>("env")? {
+            for pair in env.pairs::<String, String>() {
+                let (key, value) = pair?;
+                command = command.env(&key, &value);
+            }
+        }
+        if let Some(timeout) = options.get::<_, Option<f64>>("timeout")? {
+            if !timeout.is_finite() || timeout < 0.0 {
+                return Err(LuaError::RuntimeError(format!(
+                    "Invalid timeout: {}",
+                    timeout
+                )));
+            }
+            command = command.timeout(Duration::from_secs_f64(timeout));
+        }
+    }
+    Ok(command)
+}
+
+/// The result of a command as a Lua table `{code, stdout, stderr, timed_out}`.
+pub(crate) fn output_to_lua<'lua>(
+    ctx: Context<'lua>,
+    output: &core::CommandOutput,
+) -> Result<Table<'lua>> {
+    let result = ctx.create_table()?;
+    result.set("code", output.code)?;
+    result.set("stdout", output.stdout.as_str())?;
+    result.set("stderr", output.stderr.as_str())?;
+    result.set("timed_out", output.timed_out)?;
+    Ok(result)
+}
+
 /// Add built-in functions to the Lua `context`.
 /// All functions are available in the global scope.
 /// # Arguments
@@ -74,43 +136,125 @@ pub fn prepare_context(ctx: &Context) {
         })
         .unwrap();
     globals.set("git_clone", git_clone).unwrap();
-    let run = ctx
-        .create_function(|_, command: String| {
-            return Ok(core::run_buffered(&command));
+    // `log.error(message, [fields])`, `log.warn`, `log.info`, `log.debug` and `log.trace` log
+    // a message with an optional table of fields; `log.span(name, f)` calls `f` in a span
+    let log_table = ctx.create_table().unwrap();
+    for level in [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ] {
+        let log_function = ctx
+            .create_function(move |_, (message, fields): (String, Option<Table>)| {
+                let fields = match fields {
+                    Some(fields) => fields
+                        .pairs::<String, String>()
+                        .collect::<Result<Vec<_>>>()?,
+                    None => Vec::new(),
+                };
+                let fields = fields
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str()))
+                    .collect::<Vec<_>>();
+                log::log(level, &message, &fields);
+                Ok(())
+            })
+            .unwrap();
+        log_table.set(level.to_string(), log_function).unwrap();
+    }
+    let span = ctx
+        .create_function(|_, (name, f): (String, Function)| {
+            let _span = log::span(&name, &[]);
+            f.call::<_, MultiValue>(())
         })
         .unwrap();
-    globals.set("run", run).unwrap();
-    let get_yaml_value = ctx
-        .create_function(|_, (file_path, yaml_key_path): (String, String)| {
-            return Ok(get_yaml_value(&file_path, &yaml_key_path).ok().unwrap());
+    log_table.set("span", span).unwrap();
+    globals.set("log", log_table).unwrap();
+    // `run(command, [options])` runs a command line or an argv table, with the options
+    // `cwd`, `env` (a table) and `timeout` (in seconds), and returns `{code, stdout, stderr,
+    // timed_out}`
+    let run = ctx
+        .create_function(|ctx, (command, options): (Value, Option<Table>)| {
+            let output = command_from_lua(command, options)?
+                .output()
+                .map_err(|e| LuaError::RuntimeError(e.to_string()))?;
+            output_to_lua(ctx, &output)
         })
         .unwrap();
-    globals.set("yaml_get_value", get_yaml_value).unwrap();
-    let update_yaml_value = ctx
-        .create_function(
-            |_, (file_path, yaml_key_path, new_value): (String, String, String)| {
-                // TODO: Return the error properly
-                let v = update_yaml_value(&file_path, &yaml_key_path, &new_value)
-                    .ok()
-                    .unwrap();
-                return Ok(());
-            },
-        )
-        .unwrap();
-    globals.set("yaml_set_value", update_yaml_value).unwrap();
-    let replace_matching_line = ctx
+    // `run_stream(command, [options], [on_line])` runs a command like `run`, calling
+    // `on_line(stream, line)` with `"stdout"` or `"stderr"` for every line it outputs, or
+    // printing the lines without `on_line`. Fails if the command is interrupted by Ctrl-C.
+    let run_stream = ctx
         .create_function(
-            |_, (file_path, regex, new_value): (String, String, String)| {
-                match text::replace_matching_line(&file_path, &regex, &new_value) {
-                    Ok(()) => Ok(()),
-                    Err(e) => Err(LuaError::RuntimeError(
-                        "Could not replace matching line".to_string(),
-                    )),
+            |ctx, (command, options, on_line): (Value, Option<Table>, Option<Function>)| {
+                let command = command_from_lua(command, options)?;
+                let mut error = None;
+                let output = process::run_streaming(&command, |stream, line| {
+                    let on_line = match (&on_line, &error) {
+                        (Some(on_line), None) => on_line,
+                        (None, _) => {
+                            match stream {
+                                process::Stream::Stdout => println!("{}", line),
+                                process::Stream::Stderr => eprintln!("{}", line),
+                            }
+                            return;
+                        }
+                        _ => return,
+                    };
+                    let stream = match stream {
+                        process::Stream::Stdout => "stdout",
+                        process::Stream::Stderr => "stderr",
+                    };
+                    if let Err(e) = on_line.call::<_, ()>((stream, line)) {
+                        error = Some(e);
+                    }
+                })
+                .map_err(|e| LuaError::RuntimeError(e.to_string()))?;
+                if let Some(error) = error {
+                    return Err(error);
+                }
+                if output.interrupted {
+                    return Err(LuaError::RuntimeError(format!(
+                        "`{}` was interrupted",
+                        command
+                    )));
                 }
+                output_to_lua(ctx, &output)
             },
         )
         .unwrap();
-    globals.set("replace_line", replace_matching_line).unwrap();
+    globals.set("run_stream", run_stream).unwrap();
+    // `run_parallel(commands, [options])` runs a table of commands by name concurrently,
+    // printing their lines prefixed with their names, and returns a table of their results
+    // by name. Fails if the commands are interrupted by Ctrl-C.
+    let run_parallel = ctx
+        .create_function(|ctx, (commands, options): (Table, Option<Table>)| {
+            let mut named = Vec::new();
+            for pair in commands.pairs::<String, Value>() {
+                let (name, command) = pair?;
+                named.push((name, command_from_lua(command, options.clone())?));
+            }
+            named.sort_by(|(a, _), (b, _)| a.cmp(b));
+            let outputs = tokio::runtime::Runtime::new()
+                .map_err(LuaError::external)?
+                .block_on(process::run_all(&named))
+                .map_err(|e| LuaError::RuntimeError(e.to_string()))?;
+            if outputs.iter().any(|output| output.interrupted) {
+                return Err(LuaError::RuntimeError(
+                    "The commands were interrupted".to_string(),
+                ));
+            }
+            let results = ctx.create_table()?;
+            for ((name, _), output) in named.iter().zip(outputs.iter()) {
+                results.set(name.as_str(), output_to_lua(ctx, output)?)?;
+            }
+            Ok(results)
+        })
+        .unwrap();
+    globals.set("run_parallel", run_parallel).unwrap();
+    globals.set("run", run).unwrap();
     let set_dir = ctx
         .create_function(|_, dir: String| match core::set_dir(&dir) {
             Ok(()) => Ok(()),
@@ -153,6 +297,8 @@ pub fn prepare_context(ctx: &Context) {
             table.set("title", page.title.to_string())?;
             table.set("path", page.path.to_str().unwrap_or(""))?;
             table.set("contents", page.contents.to_string())?;
+            table.set("aliases", page.aliases.clone())?;
+            let tasks = page.tasks();
             let wikilinks = page
                 .wikilinks
                 .into_iter()
@@ -161,6 +307,8 @@ pub fn prepare_context(ctx: &Context) {
                     table.set("name", wikilink.name).ok().unwrap();
                     table.set("link", wikilink.link).ok().unwrap();
                     table.set("anchor", wikilink.anchor).ok().unwrap();
+                    table.set("block", wikilink.block).ok().unwrap();
+                    table.set("embed", wikilink.embed).ok().unwrap();
                     table
                         .set("link_type", wikilink.link_type as u8)
                         .ok()
@@ -169,17 +317,119 @@ pub fn prepare_context(ctx: &Context) {
                 })
                 .collect::<Vec<Table>>();
             table.set("wikilinks", wikilinks).ok().unwrap();
+            let tasks = tasks
+                .into_iter()
+                .map(|task| {
+                    let table = ctx.create_table().ok().unwrap();
+                    table.set("line", task.line).ok().unwrap();
+                    table.set("text", task.text).ok().unwrap();
+                    table.set("completed", task.completed).ok().unwrap();
+                    table
+                        .set("due", task.due.map(|due| due.to_string()))
+                        .ok()
+                        .unwrap();
+                    table.set("id", task.id).ok().unwrap();
+                    table.set("tags", task.tags).ok().unwrap();
+                    table
+                })
+                .collect::<Vec<Table>>();
+            table.set("tasks", tasks).ok().unwrap();
 
             Ok(table)
         })
         .unwrap();
     globals.set("md_load", md_load).unwrap();
+    let md_set_task = ctx
+        .create_function(|_, (path, line, completed): (String, usize, bool)| {
+            let contents = std::fs::read_to_string(&path).map_err(LuaError::external)?;
+            match markdown::set_task_completed(&contents, line, completed) {
+                Some(contents) => plan::write(&path, contents).map_err(LuaError::external),
+                None => Err(LuaError::RuntimeError(format!(
+                    "No task at line {} of {}",
+                    line, path
+                ))),
+            }
+        })
+        .unwrap();
+    globals.set("md_set_task", md_set_task).unwrap();
     let pprint = ctx
         .create_function(|_, table: Table| pretty_print_table(&table, 2))
         .unwrap();
     globals.set("pprint", pprint).unwrap();
+    // `dry_run(enabled)` records commands, file writes and database changes into a plan
+    // instead of running them; `plan()` returns the recorded actions and `print_plan()`
+    // prints and clears them
+    let dry_run = ctx
+        .create_function(|_, enabled: bool| {
+            plan::set_dry_run(enabled);
+            Ok(())
+        })
+        .unwrap();
+    globals.set("dry_run", dry_run).unwrap();
+    let get_plan = ctx
+        .create_function(|_, ()| {
+            Ok(plan::actions()
+                .iter()
+                .map(|action| action.to_string())
+                .collect::<Vec<_>>())
+        })
+        .unwrap();
+    globals.set("plan", get_plan).unwrap();
+    let print_plan = ctx
+        .create_function(|_, ()| {
+            plan::print_plan();
+            Ok(())
+        })
+        .unwrap();
+    globals.set("print_plan", print_plan).unwrap();
+    // `config()` returns the configuration as a table, `config("kind.version")` one setting
+    let get_config = ctx
+        .create_function(|ctx, path: Option<String>| {
+            let doc = serde_yaml::to_value(config::get())
+                .map_err(|e| LuaError::RuntimeError(e.to_string()))?;
+            let value = match path {
+                Some(path) => formats::yaml::get_values(&doc, &path)
+                    .map_err(|e| LuaError::RuntimeError(e.to_string()))?
+                    .into_iter()
+                    .next()
+                    .unwrap_or(serde_yaml::Value::Null),
+                None => doc,
+            };
+            formats::lua::yaml_to_lua(ctx, &value)
+        })
+        .unwrap();
+    globals.set("config", get_config).unwrap();
+    // `credential(name)` returns the `login` and `secret` of a credential, e.g. `github`
+    let credential = ctx
+        .create_function(|ctx, name: String| {
+            let credential =
+                credentials::get(&name).map_err(|e| LuaError::RuntimeError(e.to_string()))?;
+            let table = ctx.create_table()?;
+            table.set("login", credential.login)?;
+            table.set("secret", credential.secret.expose())?;
+            Ok(table)
+        })
+        .unwrap();
+    globals.set("credential", credential).unwrap();
+    formats::lua::yaml_get_value(ctx);
+    formats::lua::yaml_get_values(ctx);
+    formats::lua::yaml_set_value(ctx);
+    formats::lua::yaml_append_value(ctx);
+    formats::lua::yaml_delete_value(ctx);
+    formats::lua::yaml_documents(ctx);
+    formats::lua::toml_functions(ctx);
+    formats::lua::json_functions(ctx);
+    formats::lua::file_functions(ctx);
+    formats::lua::text_functions(ctx);
+    notes::lua::notes_new(ctx);
+    notes::lua::notes_journal(ctx);
+    notes::lua::notes_append(ctx);
+    notes::lua::notes_mv(ctx);
+    notes::lua::notes_graph(ctx);
+    admin::lua::kdbx_functions(ctx);
     todoist::lua::todoist_sync(ctx);
     todoist::lua::todoist_add_task_to_sprint(ctx);
+    todoist::lua::todoist_close_markdown_tasks(ctx);
     agile::lua::agile_create_project(ctx);
     agile::lua::agile_create_sprint(ctx);
     agile::lua::agile_show_sprint(ctx);
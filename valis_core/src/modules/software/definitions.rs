@@ -1,4 +1,5 @@
 use super::super::core;
+use crate::modules::log;
 
 pub struct Component {
     pub name: String,
@@ -17,27 +18,29 @@ impl Installation for Component {
     fn install(&self) {
         let os = core::get_os();
         if os == "macos" {
-            println!("🍏 Installing for {}", core::get_os());
+            log::info(&format!("Installing for {}", core::get_os()));
             if self.dependencies.is_some() {
-                println!("🧰 Installing dependencies");
+                log::info("Installing dependencies");
                 let dependencies = self.dependencies.as_ref().unwrap();
                 for dependency in dependencies {
                     // install(dependency);
                     dependency.install();
                 }
             }
-            println!("🧰 Installing {}", &self.name);
+            log::info(&format!("Installing {}", &self.name));
             for command in &self.install_darwin {
-                println!("\t⚙️ {}", command);
-                core::run(&command);
+                log::debug(command);
+                if let Err(e) = core::run(command) {
+                    log::warn(&e.to_string());
+                }
             }
         }
     }
     fn check_install(&self) {
         if core::in_path(self.executable.as_str()) {
-            println!("✅ {} is installed", self.name);
+            log::info(&format!("{} is installed", self.name));
         } else {
-            println!("❌ {} is not installed", self.name);
+            log::warn(&format!("{} is not installed", self.name));
         }
     }
 }
@@ -1,13 +1,21 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::PathBuf;
 use std::result::Result;
 
+use chrono::Utc;
 use rusqlite::{params, Connection, Row};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use db::DatabaseOperations;
 
+use crate::modules::config;
 use crate::modules::db;
 use crate::modules::db::get_connection;
+use crate::modules::log;
+use crate::modules::notes::markdown;
+use crate::modules::plan;
 use crate::modules::projects::agile::core::Sprint;
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -176,7 +184,7 @@ pub fn get_all_tasks(db: &str) -> Result<Vec<Task>, rusqlite::Error> {
 async fn get_todoist_tasks(todoist_token: &str) -> Result<Vec<Task>, reqwest::Error> {
     let client = reqwest::Client::new();
     let response = client
-        .get("https://api.todoist.com/rest/v2/tasks")
+        .get(format!("{}/tasks", config::get().todoist.url))
         .header("Authorization", format!("Bearer {}", todoist_token))
         .send()
         .await?
@@ -196,17 +204,28 @@ fn sync_to_db(tasks: &Vec<Task>, db: &str) -> rusqlite::Result<()> {
             |row| row.get(0),
         );
 
+        // A task closed by a previous sync was reopened
+        if is_closed(&conn, &task.id)? {
+            plan::execute(
+                &conn,
+                "DELETE FROM todoist_closed_tasks WHERE id = ?1",
+                params![task.id],
+            )?;
+        }
+
         match result {
             Ok(_) => {
                 // task exists in the database, update it
-                conn.execute(
+                plan::execute(
+                    &conn,
                     "UPDATE todoist_tasks SET content = ?1 WHERE id = ?2",
                     params![task.content, task.id],
                 )?;
             }
             Err(_) => {
                 // task doesn't exist in the database, insert it
-                conn.execute(
+                plan::execute(
+                    &conn,
                     "INSERT INTO todoist_tasks (id, content) VALUES (?1, ?2)",
                     params![task.id, task.content],
                 )?;
@@ -214,27 +233,33 @@ fn sync_to_db(tasks: &Vec<Task>, db: &str) -> rusqlite::Result<()> {
         }
         for label in &task.labels {
             // Insert tag if it doesn't exist
-            conn.execute(
+            plan::execute(
+                &conn,
                 "INSERT OR IGNORE INTO todoist_labels (label) VALUES (?)",
-                [label],
+                params![label],
             )?;
 
             // Get tag id
-            let label_id: i32 = conn.query_row(
+            // In dry-run mode, new labels are not inserted and have no id yet
+            let label_id: i32 = match conn.query_row(
                 "SELECT id FROM todoist_labels WHERE label = ?",
                 [label],
                 |row| row.get(0),
-            )?;
+            ) {
+                Err(rusqlite::Error::QueryReturnedNoRows) if plan::is_dry_run() => 0,
+                label_id => label_id?,
+            };
 
             // Link task and tag
-            conn.execute(
+            plan::execute(
+                &conn,
                 "INSERT OR IGNORE INTO todoist_task_labels (todoist_task_id, todoist_label_id) VALUES (?, ?)",
-                [&task.id, &(label_id.to_string())],
+                params![&task.id, &(label_id.to_string())],
             )?;
         }
     }
 
-    // delete tasks from database that are not in Todoist
+    // delete tasks from database that are not in Todoist, and remember them as closed
     let db_tasks: Vec<String> = conn
         .prepare("SELECT id FROM todoist_tasks")?
         .query_map([], |row| row.get(0))?
@@ -242,30 +267,110 @@ fn sync_to_db(tasks: &Vec<Task>, db: &str) -> rusqlite::Result<()> {
 
     for db_task in db_tasks {
         if !tasks.iter().any(|task| task.id == db_task) {
-            conn.execute("DELETE FROM todoist_tasks WHERE id = ?1", params![db_task])?;
+            plan::execute(
+                &conn,
+                "DELETE FROM todoist_tasks WHERE id = ?1",
+                params![db_task],
+            )?;
+            plan::execute(
+                &conn,
+                "INSERT OR REPLACE INTO todoist_closed_tasks (id, closed_at) VALUES (?1, ?2)",
+                params![db_task, Utc::now().to_rfc3339()],
+            )?;
         }
     }
 
     Ok(())
 }
 
+fn is_closed(conn: &Connection, id: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM todoist_closed_tasks WHERE id = ?1",
+        params![id],
+        |row| row.get::<_, i64>(0),
+    )
+    .map(|count| count > 0)
+}
+
+/// The ids of the tasks which were open in a sync and gone from Todoist in a later one.
+pub fn get_closed_task_ids(db: &str) -> Result<HashSet<String>, rusqlite::Error> {
+    let conn = get_connection(db);
+    let ids = conn
+        .prepare("SELECT id FROM todoist_closed_tasks")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(ids)
+}
+
 pub fn add_task_to_sprint(
     db: &str,
     sprint_id: &Uuid,
     task_id: String,
 ) -> Result<(), rusqlite::Error> {
     let conn = get_connection(db);
-    conn.execute(
+    plan::execute(
+        &conn,
         "INSERT INTO sprint_todoist_task (sprint_id, todoist_task_id) VALUES (?1, ?2)",
-        [&sprint_id.to_string(), &task_id],
+        params![&sprint_id.to_string(), &task_id],
     )?;
 
     Ok(())
 }
 
+/// Close the tasks of the Markdown files in `vault` which were closed in Todoist.
+/// Markdown tasks are linked to Todoist with the id syntax (`- [ ] task 🆔 <todoist id>`),
+/// and are closed when a sync saw their Todoist task disappear (see
+/// [`get_closed_task_ids`]). Ids which were never synced are left alone.
+/// Returns the number of tasks closed.
+pub fn close_markdown_tasks(db: &str, vault: PathBuf) -> Result<usize, Box<dyn Error>> {
+    let closed_ids = get_closed_task_ids(db)?;
+    let files = markdown::get_markdown_files(vault)?;
+    let mut closed = 0;
+    for path in files.into_iter().filter_map(|path| path.ok()) {
+        closed += markdown::sync_task_completion(&path, |task| match &task.id {
+            Some(id) if closed_ids.contains(id) => Some(true),
+            _ => None,
+        })?;
+    }
+    Ok(closed)
+}
+
 pub async fn sync(token: &str, db: &str) -> Result<(), std::fmt::Error> {
+    let _span = log::span("todoist sync", &[("db", db)]);
     let tasks = get_todoist_tasks(token).await.ok().unwrap();
     sync_to_db(&tasks, db).ok().unwrap();
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(id: &str) -> Task {
+        Task {
+            id: id.to_string(),
+            content: Some(format!("task {}", id)),
+            labels: vec![],
+        }
+    }
+
+    #[test]
+    fn test_closed_tasks() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("valis.db").to_string_lossy().to_string();
+        db::init_db(&db).unwrap();
+        assert!(get_closed_task_ids(&db).unwrap().is_empty());
+
+        sync_to_db(&vec![task("1"), task("2")], &db).unwrap();
+        sync_to_db(&vec![task("1")], &db).unwrap();
+        assert_eq!(
+            get_closed_task_ids(&db).unwrap(),
+            HashSet::from(["2".to_string()])
+        );
+
+        // Reopened
+        sync_to_db(&vec![task("1"), task("2")], &db).unwrap();
+        assert!(get_closed_task_ids(&db).unwrap().is_empty());
+    }
+}
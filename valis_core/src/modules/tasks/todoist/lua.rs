@@ -1,26 +1,30 @@
-use std::env;
+use std::path::PathBuf;
 
 use rlua::{Context, Error};
 use tokio::runtime::Runtime;
 use uuid::Uuid;
 
+use crate::modules::admin::credentials;
+use crate::modules::config;
 use crate::modules::db;
+use crate::modules::log;
 use crate::modules::tasks::todoist;
 use crate::modules::tasks::todoist::core::add_task_to_sprint;
 
 pub fn todoist_sync(ctx: &Context) {
     let f = ctx
-        .create_function(|_, db: String| {
-            match env::var("TODOIST_TOKEN") {
+        .create_function(|_, db: Option<String>| {
+            let db = db.unwrap_or_else(|| config::get().db_path());
+            match credentials::get("todoist") {
                 Ok(token) => {
-                    db::init_db(&db);
+                    db::init_db(&db).map_err(|e| Error::external(e.to_string()))?;
                     Runtime::new()
                         .unwrap()
-                        .block_on(todoist::core::sync(&token, &db))
+                        .block_on(todoist::core::sync(token.secret.expose(), &db))
                         .unwrap();
                 }
                 Err(e) => {
-                    println!("Failed to read TODOIST_TOKEN: {}", e);
+                    log::error(&format!("Failed to read the Todoist token: {}", e));
                 }
             }
             Ok(())
@@ -29,24 +33,44 @@ pub fn todoist_sync(ctx: &Context) {
     ctx.globals().set("todoist_sync", f).unwrap();
 }
 
+pub fn todoist_close_markdown_tasks(ctx: &Context) {
+    let f = ctx
+        .create_function(|_, (db, vault): (Option<String>, String)| {
+            let db = db.unwrap_or_else(|| config::get().db_path());
+            match todoist::core::close_markdown_tasks(&db, PathBuf::from(vault)) {
+                Ok(closed) => Ok(closed),
+                Err(e) => Err(Error::external(e.to_string())),
+            }
+        })
+        .unwrap();
+    ctx.globals()
+        .set("todoist_close_markdown_tasks", f)
+        .unwrap();
+}
+
 pub fn todoist_add_task_to_sprint(ctx: &Context) {
     let f = ctx
-        .create_function(|_, (sprint_id, task_id, db): (String, String, String)| {
-            match Uuid::parse_str(&sprint_id) {
-                Ok(sprint_uuid) => {
-                    println!("Sprint id: {}", sprint_id);
-                    match add_task_to_sprint(&db, &sprint_uuid, task_id) {
-                        Ok(()) => Ok(()),
-                        Err(e) => Err(Error::RuntimeError(
-                            "Could not save task to Sprint".to_string(),
-                        )),
+        .create_function(
+            |_, (sprint_id, task_id, db): (String, String, Option<String>)| {
+                let db = db.unwrap_or_else(|| config::get().db_path());
+                match Uuid::parse_str(&sprint_id) {
+                    Ok(sprint_uuid) => {
+                        log::debug(&format!("Sprint id: {}", sprint_id));
+                        match add_task_to_sprint(&db, &sprint_uuid, task_id) {
+                            Ok(()) => Ok(()),
+                            Err(e) => Err(Error::RuntimeError(format!(
+                                "Could not save task to Sprint: {}",
+                                e
+                            ))),
+                        }
                     }
+                    Err(e) => Err(Error::RuntimeError(format!(
+                        "Error parsiong Sprint UUID: {}",
+                        e
+                    ))),
                 }
-                Err(e) => Err(Error::RuntimeError(
-                    "Error parsiong Sprint UUID".to_string(),
-                )),
-            }
-        })
+            },
+        )
         .unwrap();
     ctx.globals().set("todoist_add_task_to_sprint", f).unwrap();
 }
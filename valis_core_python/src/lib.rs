@@ -1,9 +1,13 @@
 use std::env;
+use std::path::PathBuf;
 
 use pyo3::{wrap_pyfunction, wrap_pymodule};
+use pyo3::exceptions::{PyIOError, PyValueError};
 use pyo3::prelude::*;
 
 use valis_core::modules::admin::backup::kopia as kopia;
+use valis_core::modules::admin::credentials;
+use valis_core::modules::config;
 use valis_core::modules::core as core;
 use valis_core::modules::log as logrs;
 
@@ -13,13 +17,59 @@ fn ack(message: &str) {
 }
 
 #[pyfunction]
-fn kopia_connect_s3_from_env() {
-    let bucket = env::var("WASABI_KOPIA_BUCKET").unwrap();
-    let access_key = env::var("WASABI_KOPIA_ACCESS_KEY").unwrap();
-    let secret_key = env::var("WASABI_KOPIA_SECRET_KEY").unwrap();
-    let endpoint = env::var("WASABI_KOPIA_ENDPOINT").unwrap();
-    let password = env::var("KOPIA_PASSWORD").unwrap();
-    kopia_connect_s3(&bucket, &access_key, &secret_key, &endpoint, &password)
+fn error(message: &str) {
+    logrs::error(message);
+}
+
+#[pyfunction]
+fn warn(message: &str) {
+    logrs::warn(message);
+}
+
+#[pyfunction]
+fn info(message: &str) {
+    logrs::info(message);
+}
+
+#[pyfunction]
+fn debug(message: &str) {
+    logrs::debug(message);
+}
+
+/// Configure logging: the least severe `level` logged, the `format` (`text` or `json`) and
+/// an optional `file` the messages are also appended to.
+#[pyfunction]
+#[pyo3(signature = (level = "info", format = "text", file = None))]
+fn configure(level: &str, format: &str, file: Option<&str>) -> PyResult<()> {
+    let config = logrs::LogConfig {
+        level: level.parse().map_err(PyValueError::new_err)?,
+        format: format.parse().map_err(PyValueError::new_err)?,
+        file: file.map(PathBuf::from),
+    };
+    logrs::init(config).map_err(|e| PyIOError::new_err(e.to_string()))
+}
+
+/// Connect with the `kopia` settings of the valis configuration, falling back to the
+/// `WASABI_KOPIA_*` variables. The secrets are the `kopia-s3` and `kopia` credentials.
+#[pyfunction]
+fn kopia_connect_s3_from_env() -> PyResult<()> {
+    let settings = &config::get().kopia;
+    let credential = |name: &str| {
+        credentials::get(name).map_err(|e| PyValueError::new_err(e.to_string()))
+    };
+    let s3 = credential("kopia-s3")?;
+    let password = credential("kopia")?;
+    let setting = |value: &Option<String>, var: &str| {
+        value
+            .clone()
+            .or_else(|| env::var(var).ok())
+            .ok_or_else(|| PyValueError::new_err(format!("{} is not set", var)))
+    };
+    let bucket = setting(&settings.bucket, "WASABI_KOPIA_BUCKET")?;
+    let access_key = setting(&settings.access_key.clone().or(s3.login), "WASABI_KOPIA_ACCESS_KEY")?;
+    let endpoint = setting(&settings.endpoint, "WASABI_KOPIA_ENDPOINT")?;
+    kopia_connect_s3(&bucket, &access_key, s3.secret.expose(), &endpoint, password.secret.expose());
+    Ok(())
 }
 
 
@@ -28,9 +78,9 @@ fn kopia_connect_s3(bucket: &str, access_key: &str, secret_key: &str, endpoint:
     let s3_endpoint = kopia::S3Endpoint {
         bucket: bucket.to_string(),
         access_key: access_key.to_string(),
-        secret_key: secret_key.to_string(),
+        secret_key: secret_key.into(),
         endpoint: endpoint.to_string(),
-        password: password.to_string(),
+        password: password.into(),
     };
     kopia::kopia_connect_s3(&s3_endpoint);
 }
@@ -44,6 +94,11 @@ fn create_snapshot(path: &str) {
 #[pymodule]
 fn log(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_wrapped(wrap_pyfunction!(ack))?;
+    m.add_wrapped(wrap_pyfunction!(error))?;
+    m.add_wrapped(wrap_pyfunction!(warn))?;
+    m.add_wrapped(wrap_pyfunction!(info))?;
+    m.add_wrapped(wrap_pyfunction!(debug))?;
+    m.add_wrapped(wrap_pyfunction!(configure))?;
     Ok(())
 }
 